@@ -1 +1,70 @@
-fn main() {}
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// A tight loop of arithmetic and a compare-and-jump, run `$1` times
+const LOOP_PROGRAM: &str = r"
+.data
+.code
+load $0 #0
+load $2 #3
+top: inc $0
+add $0 $2 $3
+mul $3 $2 $4
+jlt $0 $1 @top
+hlt
+";
+
+/// Assembles the loop program once, returning the path to its bytecode
+fn assemble_loop() -> PathBuf {
+    let dir = env::temp_dir().join(format!("iridium-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("loop.iasm");
+    let output = dir.join("loop.bin");
+    fs::write(&source, LOOP_PROGRAM).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .arg("assemble")
+        .arg(&source)
+        .arg("-o")
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    output
+}
+
+/// Runs the bytecode through the `run` subcommand, so this includes starting the process. The largest iteration
+/// count is where dispatch dominates. Each count is run decoding as it goes and with the decode cache.
+fn run_loop(c: &mut Criterion) {
+    let bytecode = assemble_loop();
+    let mut group = c.benchmark_group("run_loop");
+    group.sample_size(10);
+    for iterations in [1_000, 1_000_000].iter() {
+        for (name, flags) in [("bytes", vec![]), ("decode_cache", vec!["--decode-cache"])].iter() {
+            group.bench_with_input(
+                BenchmarkId::new(*name, iterations),
+                iterations,
+                |b, iterations| {
+                    b.iter(|| {
+                        let output = Command::new(env!("CARGO_BIN_EXE_iridium"))
+                            .arg("run")
+                            .arg(&bytecode)
+                            .args(flags)
+                            .arg("--set")
+                            .arg(format!("$1={}", iterations))
+                            .output()
+                            .unwrap();
+                        assert!(output.status.success());
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, run_loop);
+criterion_main!(benches);
@@ -1,15 +1,111 @@
 use std::error::Error;
 use std::fmt;
 
-#[derive(Debug, Clone)]
+use nom::types::CompleteStr;
+use nom::{Context, ErrorKind};
+
+use crate::assembler::instruction_parsers::EXPECTED_OPERAND;
+use crate::assembler::operand_parsers::{INTEGER_OUT_OF_RANGE, UNTERMINATED_STRING};
+
+/// What the parser was looking at when it gave up
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    ExpectedOperand,
+    UnterminatedString,
+    /// The parser stopped before the end of the source, at text that isn't an instruction or directive
+    UnexpectedText,
+    /// Any other nom error, by its description
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum AssemblerError {
-    NoSegmentDeclarationFound { instruction: u32 },
-    StringConstantDeclaredWithoutLabel { instruction: u32 },
-    SymbolAlreadyDeclared,
-    UnknownDirectiveFound { directive: String },
+    NoSegmentDeclarationFound {
+        instruction: u32,
+    },
+    StringConstantDeclaredWithoutLabel {
+        instruction: u32,
+    },
+    SymbolAlreadyDeclared {
+        name: String,
+    },
+    UnknownDirective {
+        name: String,
+    },
+    UnexpectedDirectiveOperands {
+        name: String,
+    },
     NonOpcodeInOpcodeField,
+    NonOperandInOperandField,
+    UnknownLabel {
+        name: String,
+    },
+    InstructionInDataSection {
+        instruction: u32,
+    },
+    LabelOutOfRange {
+        name: String,
+        value: u32,
+    },
+    ImmediateOutOfRange {
+        opcode: String,
+        value: i32,
+    },
+    OperandsTooWide {
+        opcode: String,
+        width: usize,
+    },
     InsufficientSections,
-    ParseError { error: String },
+    ParseError {
+        kind: ParseErrorKind,
+        line: usize,
+        near: String,
+    },
+    MalformedAliasDeclaration {
+        instruction: u32,
+    },
+    UnknownAlias {
+        name: String,
+    },
+    FileReadError {
+        path: String,
+        error: String,
+    },
+    SymbolMapError {
+        path: String,
+        error: String,
+    },
+    CyclicInclude {
+        path: String,
+    },
+    IntegerOutOfRange {
+        value: String,
+    },
+    ByteOutOfRange {
+        value: String,
+    },
+    MalformedMacro {
+        line: usize,
+        reason: String,
+    },
+    UnterminatedMacro {
+        name: String,
+    },
+    MacroArgumentMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    RecursiveMacro {
+        name: String,
+    },
+    InvalidEntryPoint {
+        name: String,
+    },
+    SpaceTooLarge {
+        size: i32,
+        max: i32,
+    },
 }
 
 impl fmt::Display for AssemblerError {
@@ -23,13 +119,134 @@ impl fmt::Display for AssemblerError {
                 "Found a string constant without a corresponding label. Instruction # was {}: ",
                 instruction
             )),
-            AssemblerError::SymbolAlreadyDeclared => f.write_str("This symbol was previously declared."),
-            AssemblerError::UnknownDirectiveFound { ref directive } => {
-                f.write_str(&format!("Invalid or unknown directive found. Directive name was: {}", directive))
+            AssemblerError::SymbolAlreadyDeclared { ref name } => {
+                f.write_str(&format!("This symbol was previously declared: {}", name))
+            }
+            AssemblerError::UnknownDirective { ref name } => {
+                f.write_str(&format!("Invalid or unknown directive found. Directive name was: {}", name))
+            }
+            AssemblerError::UnexpectedDirectiveOperands { ref name } => {
+                f.write_str(&format!("The .{} directive doesn't take operands", name))
             }
             AssemblerError::NonOpcodeInOpcodeField => f.write_str("An non-opcode was found in an opcode field"),
-            AssemblerError::InsufficientSections => f.write_str("Less than two sections/segments were found in the code"),
-            AssemblerError::ParseError { ref error } => f.write_str(&format!("There was an error parsing the code: {}", error)),
+            AssemblerError::NonOperandInOperandField => f.write_str("A non-operand was found in an operand field"),
+            AssemblerError::UnknownLabel { ref name } => f.write_str(&format!("No label was declared for: {}", name)),
+            AssemblerError::LabelOutOfRange { ref name, value } => f.write_str(&format!(
+                "Label {} has the value {}, which does not fit in a 16-bit operand",
+                name, value
+            )),
+            AssemblerError::ImmediateOutOfRange { ref opcode, value } => f.write_str(&format!(
+                "{} was given the immediate {}, which does not fit in a 16-bit operand",
+                opcode, value
+            )),
+            AssemblerError::OperandsTooWide { ref opcode, width } => f.write_str(&format!(
+                "The operands given to {} don't fit in its {} bytes",
+                opcode, width
+            )),
+            AssemblerError::InstructionInDataSection { instruction } => f.write_str(&format!(
+                "Found an instruction in the .data section; instructions belong in .code. Instruction # was {}",
+                instruction
+            )),
+            AssemblerError::InsufficientSections => f.write_str("No .code section was found in the code"),
+            AssemblerError::ParseError { ref kind, line, ref near } => match kind {
+                ParseErrorKind::ExpectedOperand => {
+                    f.write_str(&format!("Expected an operand on line {} near `{}`", line, near))
+                }
+                ParseErrorKind::UnterminatedString => {
+                    f.write_str(&format!("Unterminated string on line {} near `{}`", line, near))
+                }
+                ParseErrorKind::UnexpectedText => f.write_str(&format!(
+                    "Expected an instruction or directive on line {} near `{}`",
+                    line, near
+                )),
+                ParseErrorKind::Other(ref description) => f.write_str(&format!(
+                    "There was an error parsing the code on line {} near `{}`: {}",
+                    line, near, description
+                )),
+            },
+            AssemblerError::MalformedAliasDeclaration { instruction } => f.write_str(&format!(
+                "An .equ directive needs a name followed by a register or integer. Instruction # was {}",
+                instruction
+            )),
+            AssemblerError::UnknownAlias { ref name } => f.write_str(&format!("No .equ alias was declared for: {}", name)),
+            AssemblerError::FileReadError { ref path, ref error } => {
+                f.write_str(&format!("Unable to read source file {}: {}", path, error))
+            }
+            AssemblerError::SymbolMapError { ref path, ref error } => {
+                f.write_str(&format!("Unable to read or write symbol map {}: {}", path, error))
+            }
+            AssemblerError::CyclicInclude { ref path } => {
+                f.write_str(&format!("File includes itself, directly or through other files: {}", path))
+            }
+            AssemblerError::IntegerOutOfRange { ref value } => {
+                f.write_str(&format!("Integer does not fit in 32 bits: {}", value))
+            }
+            AssemblerError::ByteOutOfRange { ref value } => {
+                f.write_str(&format!("A .byte operand must be 0 to 255 or a single character: {}", value))
+            }
+            AssemblerError::MalformedMacro { line, ref reason } => {
+                f.write_str(&format!("Malformed macro on line {}: {}", line, reason))
+            }
+            AssemblerError::UnterminatedMacro { ref name } => {
+                f.write_str(&format!("Macro {} has no .endmacro", name))
+            }
+            AssemblerError::MacroArgumentMismatch { ref name, expected, found } => f.write_str(&format!(
+                "Macro {} takes {} arguments but was given {}",
+                name, expected, found
+            )),
+            AssemblerError::RecursiveMacro { ref name } => {
+                f.write_str(&format!("Macro {} invokes itself, directly or through other macros", name))
+            }
+            AssemblerError::InvalidEntryPoint { ref name } => f.write_str(&format!(
+                "The entry point {} must be the only .entry and a label on an instruction",
+                name
+            )),
+            AssemblerError::SpaceTooLarge { size, max } => {
+                f.write_str(&format!("A .space can reserve 0 to {} bytes, not {}", max, size))
+            }
+        }
+    }
+}
+
+impl AssemblerError {
+    /// Turns a nom error from parsing `source` into the line it happened on and the text the parser stopped at
+    pub fn from_parse_error(source: &str, e: &nom::Err<CompleteStr>) -> AssemblerError {
+        let (rest, kind) = match e {
+            nom::Err::Error(Context::Code(rest, kind))
+            | nom::Err::Failure(Context::Code(rest, kind)) => (rest.0, kind),
+            nom::Err::Incomplete(_) => {
+                return AssemblerError::ParseError {
+                    kind: ParseErrorKind::Other("Incomplete input".to_string()),
+                    line: source.lines().count(),
+                    near: String::new(),
+                };
+            }
+        };
+        let kind = match kind {
+            ErrorKind::Custom(INTEGER_OUT_OF_RANGE) => {
+                let value = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+                return AssemblerError::IntegerOutOfRange { value };
+            }
+            ErrorKind::Custom(EXPECTED_OPERAND) => ParseErrorKind::ExpectedOperand,
+            ErrorKind::Custom(UNTERMINATED_STRING) => ParseErrorKind::UnterminatedString,
+            other => ParseErrorKind::Other(other.description().to_string()),
+        };
+        AssemblerError::parse_error_at(source, rest, kind)
+    }
+
+    /// Points at the text the parser left unconsumed at the end of `source`
+    pub fn from_remainder(source: &str, rest: &str) -> AssemblerError {
+        AssemblerError::parse_error_at(source, rest, ParseErrorKind::UnexpectedText)
+    }
+
+    /// `rest` is the end of `source` where parsing stopped, so its length says how far in the parser got
+    fn parse_error_at(source: &str, rest: &str, kind: ParseErrorKind) -> AssemblerError {
+        let rest = rest.trim_start();
+        let consumed = &source[..source.len() - rest.len()];
+        AssemblerError::ParseError {
+            kind,
+            line: consumed.matches('\n').count() + 1,
+            near: rest.lines().next().unwrap_or("").trim_end().to_string(),
         }
     }
 }
@@ -39,11 +256,75 @@ impl Error for AssemblerError {
         match self {
             AssemblerError::NoSegmentDeclarationFound { .. } => "No segment declaration (e.g., .code, .data) prior to finding an opcode or other directive.",
             AssemblerError::StringConstantDeclaredWithoutLabel { .. } => "Found a string constant without a corresponding label.",
-            AssemblerError::SymbolAlreadyDeclared => "This symbol was previously declared.",
-            AssemblerError::UnknownDirectiveFound { .. } => "Invalid or unknown directive found.",
+            AssemblerError::SymbolAlreadyDeclared { .. } => "This symbol was previously declared.",
+            AssemblerError::UnknownDirective { .. } => "Invalid or unknown directive found.",
+            AssemblerError::UnexpectedDirectiveOperands { .. } => "A directive was given operands it doesn't take.",
             AssemblerError::NonOpcodeInOpcodeField => "A non-opcode was found in an opcode field",
-            AssemblerError::InsufficientSections => "Less than two sections/segments were found in the code",
+            AssemblerError::NonOperandInOperandField => "A non-operand was found in an operand field",
+            AssemblerError::UnknownLabel { .. } => "No label was declared for this name.",
+            AssemblerError::LabelOutOfRange { .. } => "A label's value does not fit in a 16-bit operand.",
+            AssemblerError::ImmediateOutOfRange { .. } => "An immediate does not fit in a 16-bit operand.",
+            AssemblerError::OperandsTooWide { .. } => "An instruction's operands don't fit in it.",
+            AssemblerError::InstructionInDataSection { .. } => "Found an instruction in the .data section.",
+            AssemblerError::InsufficientSections => "No .code section was found in the code",
             AssemblerError::ParseError { .. } => "There was an error parsing the code",
+            AssemblerError::MalformedAliasDeclaration { .. } => "An .equ directive needs a name followed by a register or integer.",
+            AssemblerError::UnknownAlias { .. } => "No .equ alias was declared for this name.",
+            AssemblerError::FileReadError { .. } => "Unable to read a source file.",
+            AssemblerError::SymbolMapError { .. } => "Unable to read or write a symbol map.",
+            AssemblerError::CyclicInclude { .. } => "A file includes itself, directly or through other files.",
+            AssemblerError::IntegerOutOfRange { .. } => "Integer does not fit in 32 bits.",
+            AssemblerError::ByteOutOfRange { .. } => "A .byte operand does not fit in a byte.",
+            AssemblerError::MalformedMacro { .. } => "A .macro definition is malformed.",
+            AssemblerError::UnterminatedMacro { .. } => "A .macro definition has no .endmacro.",
+            AssemblerError::MacroArgumentMismatch { .. } => "A macro was given the wrong number of arguments.",
+            AssemblerError::RecursiveMacro { .. } => "A macro invokes itself.",
+            AssemblerError::InvalidEntryPoint { .. } => "The .entry label is repeated or not on an instruction.",
+            AssemblerError::SpaceTooLarge { .. } => "A .space reserves more bytes than can be addressed.",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            AssemblerError::InsufficientSections.to_string(),
+            "No .code section was found in the code"
+        );
+        assert_eq!(
+            AssemblerError::UnknownLabel {
+                name: "loop".to_string()
+            }
+            .to_string(),
+            "No label was declared for: loop"
+        );
+        assert_eq!(
+            AssemblerError::SymbolAlreadyDeclared {
+                name: "test".to_string()
+            }
+            .to_string(),
+            "This symbol was previously declared: test"
+        );
+    }
+
+    #[test]
+    fn test_boxed_error() {
+        fn fails() -> Result<(), Box<dyn Error>> {
+            Err(AssemblerError::ParseError {
+                kind: ParseErrorKind::Other("Tag".to_string()),
+                line: 3,
+                near: "bad input".to_string(),
+            })?;
+            Ok(())
         }
+        let error = fails().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "There was an error parsing the code on line 3 near `bad input`: Tag"
+        );
     }
 }
@@ -0,0 +1,38 @@
+use nom::multispace;
+use nom::types::CompleteStr;
+
+// A comment runs from a `;` to the end of the line
+named!(pub comment<CompleteStr, CompleteStr>,
+    preceded!(
+        char!(';'),
+        take_till!(|c| c == '\n')
+    )
+);
+
+// Whitespace, newlines and comments in any mix, which can come before or after any instruction or directive
+named!(pub filler<CompleteStr, ()>,
+    do_parse!(
+        many0!(alt!(multispace | comment)) >>
+        ()
+    )
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_comment() {
+        let result = comment(CompleteStr("; a note\nhlt"));
+        assert_eq!(result, Ok((CompleteStr("\nhlt"), CompleteStr(" a note"))));
+        assert!(comment(CompleteStr("hlt ; a note")).is_err());
+    }
+
+    #[test]
+    fn test_parse_filler() {
+        let result = filler(CompleteStr("  ; first\n\n\t; second\n  hlt"));
+        assert_eq!(result, Ok((CompleteStr("hlt"), ())));
+        let result = filler(CompleteStr("hlt"));
+        assert_eq!(result, Ok((CompleteStr("hlt"), ())));
+    }
+}
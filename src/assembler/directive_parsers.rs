@@ -1,9 +1,9 @@
+use crate::assembler::comment_parsers::filler;
 use crate::assembler::label_parsers::label_declaration;
 // use crate::assembler::opcode_parsers::*;
-use crate::assembler::operand_parsers::operand;
+use crate::assembler::operand_parsers::directive_operand;
 // use crate::assembler::register_parsers::register;
 use crate::assembler::Token;
-// use nom::multispace;
 use nom::types::CompleteStr;
 use nom::*;
 
@@ -19,24 +19,41 @@ named!(directive_declaration<CompleteStr, Token>,
     )
 );
 
+// One operand, or several separated by commas, which become a single `Token::OperandList`
+named!(directive_operands<CompleteStr, Token>,
+    do_parse!(
+        first: directive_operand >>
+        rest: many0!(preceded!(delimited!(space0, char!(','), space0), directive_operand)) >>
+        (
+            if rest.is_empty() {
+                first
+            } else {
+                let mut operands = vec![first];
+                operands.extend(rest);
+                Token::OperandList{operands}
+            }
+        )
+    )
+);
+
 named!(directive_combined<CompleteStr, AssemblerInstruction>,
-    ws!(
-        do_parse!(
-            l: opt!(label_declaration) >>
-            name: directive_declaration >>
-            o1: opt!(operand) >>
-            o2: opt!(operand) >>
-            o3: opt!(operand) >>
-            (
-                AssemblerInstruction {
-                    opcode: None,
-                    directive: Some(name),
-                    label: l,
-                    operand_one: o1,
-                    operand_two: o2,
-                    operand_three: o3,
-                }
-            )
+    do_parse!(
+        filler >>
+        l: opt!(label_declaration) >>
+        name: directive_declaration >>
+        o1: opt!(preceded!(space1, directive_operands)) >>
+        o2: opt!(preceded!(space1, directive_operand)) >>
+        o3: opt!(preceded!(space1, directive_operand)) >>
+        filler >>
+        (
+            AssemblerInstruction {
+                opcode: None,
+                directive: Some(name),
+                label: l,
+                operand_one: o1,
+                operand_two: o2,
+                operand_three: o3,
+            }
         )
     )
 );
@@ -91,4 +108,79 @@ mod tests {
         };
         assert_eq!(directive, correct_instruction);
     }
+
+    #[test]
+    fn directive_operand_list() {
+        let (rest, directive) =
+            directive_combined(CompleteStr("bytes: .byte #1, #2 ,'c'\n")).unwrap();
+        assert_eq!(rest, CompleteStr(""));
+        assert_eq!(
+            directive.operand_one,
+            Some(Token::OperandList {
+                operands: vec![
+                    Token::IntegerOperand { value: 1 },
+                    Token::IntegerOperand { value: 2 },
+                    Token::IrString {
+                        name: "c".to_string()
+                    },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn directive_with_comments() {
+        for source in [
+            "  test: .asciiz 'x'\n",
+            "test: .asciiz 'x'  ; note\n",
+            "  test: .asciiz 'x'  ; note\n",
+            "; leading comment\n  test: .asciiz 'x'\n",
+        ]
+        .iter()
+        {
+            let (rest, directive) = directive_combined(CompleteStr(source)).unwrap();
+            assert_eq!(rest, CompleteStr(""), "{}", source);
+            assert_eq!(
+                directive.label,
+                Some(Token::LabelDeclaration {
+                    name: "test".to_string()
+                })
+            );
+            assert_eq!(
+                directive.operand_one,
+                Some(Token::IrString {
+                    name: "x".to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn section_directive_with_comment() {
+        let (rest, directive) =
+            directive_combined(CompleteStr("  .code ; start here\nhlt")).unwrap();
+        assert_eq!(rest, CompleteStr("hlt"));
+        assert_eq!(
+            directive.directive,
+            Some(Token::Directive {
+                name: "code".to_string()
+            })
+        );
+        assert_eq!(directive.operand_one, None);
+    }
+
+    #[test]
+    fn equ_directive() {
+        let result = directive_combined(CompleteStr(".equ counter $5\n.code"));
+        assert!(result.is_ok());
+        let (rest, directive) = result.unwrap();
+        assert_eq!(rest, CompleteStr(".code"));
+        assert_eq!(
+            directive.operand_one,
+            Some(Token::Identifier {
+                name: "counter".to_string()
+            })
+        );
+        assert_eq!(directive.operand_two, Some(Token::Register { reg_num: 5 }));
+    }
 }
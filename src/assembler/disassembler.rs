@@ -0,0 +1,274 @@
+use byteorder::ByteOrder;
+
+use crate::assembler::symbols::{SymbolTable, SymbolType};
+use crate::assembler::{DataEndian, OperandEndian, PIE_HEADER_LENGTH, PIE_HEADER_RO_LENGTH_OFFSET};
+use crate::instruction::Opcode;
+
+/// Turns a complete bytecode program (header included) back into assembly text, one line per instruction.
+/// The read-only data between the header and the code is skipped.
+pub fn disassemble(bytes: &[u8]) -> Vec<String> {
+    disassemble_program(bytes, None)
+}
+
+/// Disassembles like `disassemble`, but uses a symbol map to declare labels where they point and to show jump, call
+/// and string operands by name, so the listing can be assembled again
+pub fn disassemble_with_symbols(bytes: &[u8], symbols: &SymbolTable) -> Vec<String> {
+    disassemble_program(bytes, Some(symbols))
+}
+
+fn disassemble_program(bytes: &[u8], symbols: Option<&SymbolTable>) -> Vec<String> {
+    if bytes.len() < PIE_HEADER_LENGTH + 4 {
+        return vec![];
+    }
+    let starting_offset =
+        DataEndian::read_u32(&bytes[PIE_HEADER_LENGTH..PIE_HEADER_LENGTH + 4]) as usize;
+    let code_start = PIE_HEADER_LENGTH + 4 + starting_offset;
+    if bytes.len() <= code_start {
+        return vec![];
+    }
+    let mut results = vec![];
+    let mut offset = code_start;
+    for chunk in split_instructions(&bytes[code_start..]) {
+        let mut instruction = vec![0; Opcode::from(chunk[0]).width()];
+        instruction[..chunk.len()].copy_from_slice(chunk);
+        let mut line = disassemble_instruction(&instruction, symbols);
+        // Label offsets count from the start of the program, header included
+        if let Some(label) = symbols.and_then(|s| s.symbol_name(offset as u32, SymbolType::Label)) {
+            line = format!("{}: {}", label, line);
+        }
+        results.push(line);
+        offset += chunk.len();
+    }
+    results
+}
+
+/// The read-only data of a complete bytecode program, or as much of it as the program actually contains
+pub fn read_only_data(bytes: &[u8]) -> &[u8] {
+    if bytes.len() < PIE_HEADER_LENGTH + 4 {
+        return &[];
+    }
+    let ro_start = PIE_HEADER_LENGTH + 4;
+    let ro_length =
+        DataEndian::read_u32(&bytes[PIE_HEADER_RO_LENGTH_OFFSET..PIE_HEADER_RO_LENGTH_OFFSET + 4])
+            as usize;
+    &bytes[ro_start..std::cmp::min(ro_start + ro_length, bytes.len())]
+}
+
+/// Splits a raw stream of instructions into one slice per instruction, using each opcode's width. The last slice is
+/// short if the stream ends partway through an instruction.
+pub fn split_instructions(bytes: &[u8]) -> Vec<&[u8]> {
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let end = std::cmp::min(offset + Opcode::from(bytes[offset]).width(), bytes.len());
+        instructions.push(&bytes[offset..end]);
+        offset = end;
+    }
+    instructions
+}
+
+/// Disassembles a raw stream of instructions that has no PIE header in front of it, such as the REPL's program
+pub fn disassemble_instructions(bytes: &[u8]) -> Vec<String> {
+    let mut results = vec![];
+    for chunk in split_instructions(bytes) {
+        let mut instruction = vec![0; Opcode::from(chunk[0]).width()];
+        instruction[..chunk.len()].copy_from_slice(chunk);
+        results.push(disassemble_instruction(&instruction, None));
+    }
+    results
+}
+
+fn disassemble_instruction(bytes: &[u8], symbols: Option<&SymbolTable>) -> String {
+    let opcode = Opcode::from(bytes[0]);
+    let register = |idx: usize| format!("${}", bytes[idx]);
+    let value = |idx: usize| OperandEndian::read_u16(&bytes[idx..]);
+    let immediate = |idx: usize| format!("#{}", value(idx));
+    // The assembler records code and read-only data labels alike as labels, and the two can't overlap since the code
+    // comes after the data
+    let target = |value: u32| match symbols.and_then(|s| s.symbol_name(value, SymbolType::Label)) {
+        Some(name) => format!("@{}", name),
+        None => format!("#{}", value),
+    };
+    let label = |idx: usize| target(u32::from(value(idx)));
+    let operands = match opcode {
+        Opcode::LOADW => vec![
+            register(1),
+            format!("#{}", OperandEndian::read_i32(&bytes[2..6])),
+        ],
+        Opcode::LOADF => vec![
+            register(1),
+            format!("#{:?}", DataEndian::read_f64(&bytes[2..10])),
+        ],
+        Opcode::HLT | Opcode::NOP | Opcode::RET | Opcode::DUMP | Opcode::DUMPF => vec![],
+        Opcode::LOAD | Opcode::LUI | Opcode::LOADL => vec![register(1), immediate(2)],
+        Opcode::ADD
+        | Opcode::SUB
+        | Opcode::MUL
+        | Opcode::DIV
+        | Opcode::DIVU
+        | Opcode::MIN
+        | Opcode::MAX
+        | Opcode::LOADIDX
+        | Opcode::STOREIDX
+        | Opcode::BLKCPY => {
+            vec![register(1), register(2), register(3)]
+        }
+        Opcode::EQ
+        | Opcode::NEQ
+        | Opcode::GT
+        | Opcode::LT
+        | Opcode::GTQ
+        | Opcode::LTQ
+        | Opcode::MOV
+        | Opcode::CMOV
+        | Opcode::MOVF
+        | Opcode::NEG
+        | Opcode::ABS
+        | Opcode::STOREM
+        | Opcode::LOADB
+        | Opcode::STOREB => vec![register(1), register(2)],
+        Opcode::JMP
+        | Opcode::JMPF
+        | Opcode::JMPB
+        | Opcode::JEQ
+        | Opcode::JNEQ
+        | Opcode::ALOC
+        | Opcode::INC
+        | Opcode::DEC
+        | Opcode::PRTSF
+        | Opcode::MOVREM
+        | Opcode::READ
+        | Opcode::PUSH
+        | Opcode::POP
+        | Opcode::TIME
+        | Opcode::RAND
+        | Opcode::GETPC => vec![register(1)],
+        Opcode::JLT | Opcode::JGT | Opcode::JLE | Opcode::JGE => {
+            vec![register(1), register(2), label(3)]
+        }
+        Opcode::SYSCALL | Opcode::STOREL => vec![immediate(1), register(3)],
+        Opcode::PRTS => vec![label(1)],
+        Opcode::JMPE => vec![target(OperandEndian::read_u32(&bytes[1..5]))],
+        Opcode::CALL => vec![label(1)],
+        Opcode::JMPFI | Opcode::JMPBI => vec![immediate(1)],
+        Opcode::IGL => return format!("<illegal: {:#04x}>", bytes[0]),
+    };
+
+    let mut line = opcode.to_string();
+    for operand in operands {
+        line.push(' ');
+        line.push_str(&operand);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::program_parsers::program;
+    use crate::assembler::symbols::SymbolTable;
+    use crate::assembler::Assembler;
+    use crate::vm::VirtualMachine;
+    use nom::types::CompleteStr;
+
+    #[test]
+    fn test_disassemble_round_trip() {
+        let (_, p) = program(CompleteStr("load $0 #100\nload $1 #500\nhlt\n")).unwrap();
+        let bytecode = VirtualMachine::prepend_header(p.to_bytes(&SymbolTable::new()).unwrap());
+        let listing = disassemble(&bytecode);
+        assert_eq!(listing, vec!["LOAD $0 #100", "LOAD $1 #500", "HLT"]);
+    }
+
+    #[test]
+    fn test_disassemble_skips_ro_data() {
+        let mut asm = Assembler::new();
+        let bytecode = asm
+            .assemble(".data\nhello: .asciiz 'Hello'\n.code\nprts @hello\nhlt\n")
+            .unwrap();
+        assert_eq!(disassemble(&bytecode), vec!["PRTS #0", "HLT"]);
+    }
+
+    #[test]
+    fn test_read_only_data() {
+        let mut asm = Assembler::new();
+        let bytecode = asm
+            .assemble(".data\nhello: .asciiz 'Hello'\n.code\nprts @hello\nhlt\n")
+            .unwrap();
+        assert_eq!(read_only_data(&bytecode), b"Hello\0");
+        assert!(read_only_data(&bytecode[..10]).is_empty());
+        // The length comes from the header, not the starting offset that follows it
+        let mut bytecode = bytecode;
+        bytecode[PIE_HEADER_LENGTH..PIE_HEADER_LENGTH + 4].copy_from_slice(&[0, 0, 0, 0]);
+        assert_eq!(read_only_data(&bytecode), b"Hello\0");
+    }
+
+    #[test]
+    fn test_disassemble_with_symbols() {
+        let source = ".data\nhello: .asciiz 'Hi'\n.code\nload $0 #0\nload $1 #3\ntop: inc $0\nprts @hello\njlt $0 $1 @top\nhlt\n";
+        let mut asm = Assembler::new();
+        let bytecode = asm.assemble(source).unwrap();
+        let listing = disassemble_with_symbols(&bytecode, &asm.symbols);
+        assert_eq!(
+            listing,
+            vec![
+                "LOAD $0 #0",
+                "LOAD $1 #3",
+                "top: INC $0",
+                "PRTS @hello",
+                "JLT $0 $1 @top",
+                "HLT"
+            ]
+        );
+
+        // The annotated listing assembles back into the same program
+        let mut reassembler = Assembler::new();
+        let reassembled = reassembler
+            .assemble(&format!(
+                ".data\nhello: .asciiz 'Hi'\n.code\n{}\n",
+                listing.join("\n").to_lowercase()
+            ))
+            .unwrap();
+        assert_eq!(reassembled, bytecode);
+    }
+
+    #[test]
+    fn test_disassemble_register_operands() {
+        let listing = disassemble_instructions(&[1, 0, 1, 2, 9, 3, 4, 0, 18, 7, 0, 0]);
+        assert_eq!(listing, vec!["ADD $0 $1 $2", "EQ $3 $4", "INC $7"]);
+        let listing = disassemble_instructions(&[43, 0, 1, 0, 72, 5, 0, 0, 0]);
+        assert_eq!(listing, vec!["JLT $0 $1 #72", "HLT"]);
+    }
+
+    #[test]
+    fn test_disassemble_mixed_widths() {
+        let mut asm = Assembler::new();
+        let bytecode = asm
+            .assemble(".code\nloadf $1 #-1.5\nloadw $2 #100000\nload $3 #4\nhlt\n")
+            .unwrap();
+        assert_eq!(
+            disassemble(&bytecode),
+            vec!["LOADF $1 #-1.5", "LOADW $2 #100000", "LOAD $3 #4", "HLT"]
+        );
+    }
+
+    #[test]
+    fn test_split_instructions() {
+        let bytes = [41, 0, 0, 0, 0, 1, 5, 0, 0, 0, 42, 1, 0];
+        assert_eq!(
+            split_instructions(&bytes),
+            vec![&bytes[0..6], &bytes[6..10], &bytes[10..13]]
+        );
+    }
+
+    #[test]
+    fn test_disassemble_illegal_opcode() {
+        let listing = disassemble_instructions(&[254, 0, 0, 0]);
+        assert_eq!(listing, vec!["<illegal: 0xfe>"]);
+    }
+
+    #[test]
+    fn test_disassemble_header_only() {
+        let bytecode = VirtualMachine::prepend_header(vec![]);
+        assert_eq!(disassemble(&bytecode).len(), 0);
+    }
+}
@@ -1,20 +1,20 @@
 // use crate::assembler::directive_parsers::directive;
+use crate::assembler::assembler_errors::AssemblerError;
+use crate::assembler::comment_parsers::filler;
 use crate::assembler::label_parsers::label_declaration;
 use crate::assembler::opcode_parsers::*;
 use crate::assembler::operand_parsers::operand;
-use crate::assembler::register_parsers::register;
 use crate::assembler::symbols::SymbolTable;
-use crate::assembler::Token;
+use crate::assembler::{DataEndian, OperandEndian, Token};
 use crate::instruction;
-use byteorder::{LittleEndian, WriteBytesExt};
-use nom::multispace;
+use byteorder::WriteBytesExt;
 use nom::types::CompleteStr;
 use nom::*;
 
 use std::fmt;
 
-const MAX_I16: i32 = 32768;
-const MIN_I16: i32 = -32768;
+/// Largest immediate LOAD can hold on its own. The VM zero-extends the 16 bits, so anything outside 0..=MAX_LOAD_IMMEDIATE has to be split into a LOAD/LUI pair.
+pub const MAX_LOAD_IMMEDIATE: i32 = u16::MAX as i32;
 
 #[derive(Debug, PartialEq)]
 pub struct AssemblerInstruction {
@@ -26,42 +26,39 @@ pub struct AssemblerInstruction {
     pub operand_three: Option<Token>,
 }
 impl AssemblerInstruction {
-    pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> Result<Vec<u8>, AssemblerError> {
         let mut results = vec![];
-        // if let Some(ref token) = self.opcode {
-        //     match token {
-        //         Token::Op { code } => match code {
-        //             _ => {
-        //                 let b: u8 = (*code).into();
-        //                 results.push(b);
-        //             }
-        //         },
-        //     }
-        // }
-        match self.opcode {
-            Some(Token::Op { code }) => match code {
-                _ => {
-                    results.push(code as u8);
-                }
-            },
-            _ => {
-                println!("Non-opcode found in opcode field");
-                std::process::exit(1);
-            }
+        let code = match self.opcode {
+            Some(Token::Op { code }) => code,
+            _ => return Err(AssemblerError::NonOpcodeInOpcodeField),
         };
+        results.push(code as u8);
 
         for operand in vec![&self.operand_one, &self.operand_two, &self.operand_three] {
-            match operand {
-                Some(t) => AssemblerInstruction::extract_operand(t, &mut results, symbols),
-                None => {}
+            if let Some(t) = operand {
+                AssemblerInstruction::extract_operand(t, &mut results, symbols, code)?;
             }
         }
 
-        while results.len() < 4 {
+        if results.len() > code.width() {
+            return Err(AssemblerError::OperandsTooWide {
+                opcode: code.to_string(),
+                width: code.width(),
+            });
+        }
+        while results.len() < code.width() {
             results.push(0);
         }
 
-        return results;
+        Ok(results)
+    }
+
+    /// How many bytes `to_bytes` turns this instruction into, which depends on its opcode
+    pub fn width(&self) -> u32 {
+        match self.opcode {
+            Some(Token::Op { code }) => code.width() as u32,
+            _ => 4,
+        }
     }
 
     pub fn is_label(&self) -> bool {
@@ -88,7 +85,7 @@ impl AssemblerInstruction {
                         if let Some(ref first_half) = self.operand_two {
                             match first_half {
                                 Token::IntegerOperand { ref value } => {
-                                    if *value > MAX_I16 || *value < MIN_I16 {
+                                    if *value > MAX_LOAD_IMMEDIATE || *value < 0 {
                                         return true;
                                     }
                                     return false;
@@ -98,7 +95,7 @@ impl AssemblerInstruction {
                                 }
                             }
                         }
-                        return true;
+                        return false;
                     }
                     _ => {
                         return false;
@@ -112,10 +109,10 @@ impl AssemblerInstruction {
         false
     }
 
-    pub fn get_integer_value(&self) -> Option<i16> {
+    pub fn get_integer_value(&self) -> Option<i32> {
         if let Some(ref operand) = self.operand_two {
             match operand {
-                Token::IntegerOperand { ref value } => return Some(*value as i16),
+                Token::IntegerOperand { ref value } => return Some(*value),
                 _ => return None,
             }
         }
@@ -180,32 +177,61 @@ impl AssemblerInstruction {
         }
     }
 
-    fn extract_operand(t: &Token, results: &mut Vec<u8>, symbols: &SymbolTable) {
+    fn extract_operand(
+        t: &Token,
+        results: &mut Vec<u8>,
+        symbols: &SymbolTable,
+        code: instruction::Opcode,
+    ) -> Result<(), AssemblerError> {
+        // Only LOADW and JMPE have room for 32-bit immediates
+        let wide = code == instruction::Opcode::LOADW || code == instruction::Opcode::JMPE;
         match t {
             Token::Register { reg_num } => results.push(*reg_num),
+            // LOADF only takes a float, so a whole number is written as one
+            Token::IntegerOperand { value } if code == instruction::Opcode::LOADF => {
+                results.write_f64::<DataEndian>(f64::from(*value)).unwrap();
+            }
+            Token::IntegerOperand { value } if wide => {
+                results.write_i32::<OperandEndian>(*value).unwrap();
+            }
+            Token::LabelUsage { name } if wide => match symbols.symbol_value(name) {
+                Some(value) => results.write_u32::<OperandEndian>(value).unwrap(),
+                None => return Err(AssemblerError::UnknownLabel { name: name.clone() }),
+            },
             Token::IntegerOperand { value } => {
-                let converted = *value as u16;
-                let byte_one = converted;
-                let byte_two = converted >> 8;
-                results.push(byte_two as u8);
-                results.push(byte_one as u8);
+                // Only LOAD is split up when its immediate is too big, so anything else has to fit as it is
+                if *value < 0 || *value > i32::from(u16::MAX) {
+                    return Err(AssemblerError::ImmediateOutOfRange {
+                        opcode: code.to_string(),
+                        value: *value,
+                    });
+                }
+                results.write_u16::<OperandEndian>(*value as u16).unwrap();
+            }
+            // Floats are written as their IEEE-754 bits, which follow the data byte order rather than the operand one
+            Token::FloatOperand { value } => {
+                results.write_f64::<DataEndian>(*value).unwrap();
             }
             Token::LabelUsage { name } => {
                 if let Some(value) = symbols.symbol_value(name) {
-                    let mut wtr = vec![];
-                    wtr.write_u32::<LittleEndian>(value).unwrap();
-                    results.push(wtr[1]);
-                    results.push(wtr[0]);
+                    // Label operands get 16 bits, like any other immediate
+                    if value > u16::MAX as u32 {
+                        return Err(AssemblerError::LabelOutOfRange {
+                            name: name.clone(),
+                            value,
+                        });
+                    }
+                    results.write_u16::<OperandEndian>(value as u16).unwrap();
                 } else {
-                    println!("No value found for {:?}", name);
-                    std::process::exit(1);
+                    return Err(AssemblerError::UnknownLabel { name: name.clone() });
                 }
             }
-            _ => {
-                println!("Opcode found in operand field");
-                std::process::exit(1);
+            Token::Identifier { name } => {
+                return Err(AssemblerError::UnknownAlias { name: name.clone() });
             }
+            _ => return Err(AssemblerError::NonOperandInOperandField),
         }
+        Ok(())
     }
 }
 
@@ -224,13 +250,31 @@ impl fmt::Display for AssemblerInstruction {
     }
 }
 
+/// Custom nom error code for text after an opcode that isn't an operand or a comment
+pub const EXPECTED_OPERAND: u32 = 3;
+
+/// Anything other than a comment or the end of the line after an opcode has to be an operand. Failures from inside
+/// an operand, like an out of range integer, are passed on as they are.
+fn required_operand(input: CompleteStr) -> IResult<CompleteStr, Token> {
+    peek!(input, none_of!(";\r\n"))?;
+    match operand(input) {
+        Err(Err::Error(_)) => Err(Err::Failure(Context::Code(
+            input,
+            ErrorKind::Custom(EXPECTED_OPERAND),
+        ))),
+        result => result,
+    }
+}
+
 named!(instruction_combined<CompleteStr, AssemblerInstruction>,
     do_parse!(
+        filler >>
         l: opt!(label_declaration) >>
         o: opcode >>
-        o1: opt!(operand) >>
-        o2: opt!(operand) >>
-        o3: opt!(operand) >>
+        o1: opt!(preceded!(space1, required_operand)) >>
+        o2: opt!(preceded!(space1, required_operand)) >>
+        o3: opt!(preceded!(space1, required_operand)) >>
+        filler >>
         (
             AssemblerInstruction {
                 opcode: Some(o),
@@ -242,15 +286,12 @@ named!(instruction_combined<CompleteStr, AssemblerInstruction>,
             }
         )
     )
-
 );
 
 named!(pub instruction<CompleteStr, AssemblerInstruction>,
     do_parse!(
         ins: alt!(
-            instruction_one |
-            instruction_two |
-            instruction_three
+            instruction_combined
         ) >>
         (
             ins
@@ -258,66 +299,15 @@ named!(pub instruction<CompleteStr, AssemblerInstruction>,
     )
 );
 
-named!(instruction_three <CompleteStr,AssemblerInstruction>,
-    do_parse!(
-        o: opcode
-            >> register_one: register
-            >> register_two: register
-            >> register_three: register
-            >> (AssemblerInstruction {
-                label: None,
-                directive: None,
-                opcode: Some(o),
-                operand_one: Some(register_one),
-                operand_two: Some(register_two),
-                operand_three: Some(register_three)
-            })
-    )
-);
-
-named!(instruction_two<CompleteStr, AssemblerInstruction>,
-    do_parse!(
-        o: opcode >>
-        opt!(multispace) >>
-        (
-            AssemblerInstruction{
-                label: None,
-                directive: None,
-                opcode: Some(o),
-                operand_one: None,
-                operand_two: None,
-                operand_three: None
-            }
-        )
-    )
-);
-
-named!(instruction_one<CompleteStr, AssemblerInstruction>,
-    do_parse!(
-        o: opcode >>
-        r: register >>
-        i: operand >>
-        (
-            AssemblerInstruction{
-                label: None,
-                directive: None,
-                opcode: Some(o),
-                operand_one: Some(r),
-                operand_two: Some(i),
-                operand_three: None
-            }
-        )
-    )
-);
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assembler::symbols::{Symbol, SymbolType};
     use crate::instruction::Opcode;
 
     #[test]
     fn parse_instruction_form_one() {
-        let result = instruction_one(CompleteStr("load $0 #100\n"));
+        let result = instruction(CompleteStr("load $0 #100\n"));
         assert_eq!(
             result,
             Ok((
@@ -336,7 +326,7 @@ mod tests {
 
     #[test]
     fn parse_instruction_form_two() {
-        let result = instruction_two(CompleteStr("hlt\n"));
+        let result = instruction(CompleteStr("hlt\n"));
         assert_eq!(
             result,
             Ok((
@@ -355,7 +345,7 @@ mod tests {
 
     #[test]
     fn parse_instruction_form_three() {
-        let result = instruction_three(CompleteStr("add $0 $1 $2\n"));
+        let result = instruction(CompleteStr("add $0 $1 $2\n"));
         assert_eq!(
             result,
             Ok((
@@ -371,4 +361,380 @@ mod tests {
             ))
         )
     }
+
+    #[test]
+    fn parse_nop() {
+        let result = instruction(CompleteStr("nop\n"));
+        assert!(result.is_ok());
+        let (_, instruction) = result.unwrap();
+        assert_eq!(instruction.opcode, Some(Token::Op { code: Opcode::NOP }));
+        assert_eq!(
+            instruction.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![23, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn parse_mov() {
+        let result = instruction(CompleteStr("mov $3 $7\n"));
+        assert!(result.is_ok());
+        let (_, instruction) = result.unwrap();
+        assert_eq!(
+            instruction.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![24, 3, 7, 0]
+        );
+    }
+
+    #[test]
+    fn parse_neg() {
+        let (_, neg) = instruction(CompleteStr("neg $3 $7\n")).unwrap();
+        assert_eq!(neg.opcode, Some(Token::Op { code: Opcode::NEG }));
+        assert_eq!(
+            neg.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![48, 3, 7, 0]
+        );
+    }
+
+    #[test]
+    fn parse_abs() {
+        let (_, abs) = instruction(CompleteStr("abs $1 $2\n")).unwrap();
+        assert_eq!(abs.opcode, Some(Token::Op { code: Opcode::ABS }));
+        assert_eq!(
+            abs.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![49, 1, 2, 0]
+        );
+    }
+
+    #[test]
+    fn parse_min_max() {
+        let symbols = SymbolTable::new();
+        let (_, min) = instruction(CompleteStr("min $2 $0 $1\n")).unwrap();
+        assert_eq!(min.opcode, Some(Token::Op { code: Opcode::MIN }));
+        assert_eq!(min.to_bytes(&symbols).unwrap(), vec![50, 2, 0, 1]);
+        let (_, max) = instruction(CompleteStr("max $2 $0 $1\n")).unwrap();
+        assert_eq!(max.opcode, Some(Token::Op { code: Opcode::MAX }));
+        assert_eq!(max.to_bytes(&symbols).unwrap(), vec![51, 2, 0, 1]);
+    }
+
+    #[test]
+    fn parse_storem() {
+        let (_, storem) = instruction(CompleteStr("storem $4 $5\n")).unwrap();
+        assert_eq!(
+            storem.opcode,
+            Some(Token::Op {
+                code: Opcode::STOREM
+            })
+        );
+        assert_eq!(
+            storem.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![52, 4, 5, 0]
+        );
+    }
+
+    #[test]
+    fn parse_time() {
+        let (_, time) = instruction(CompleteStr("time $6\n")).unwrap();
+        assert_eq!(time.opcode, Some(Token::Op { code: Opcode::TIME }));
+        assert_eq!(
+            time.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![53, 6, 0, 0]
+        );
+    }
+
+    #[test]
+    fn parse_required_operand() {
+        // Trailing spaces and comments after the operands are fine
+        for source in ["load $0 #1   \n", "load $0 #1 ; note\n", "hlt \n"].iter() {
+            assert!(instruction(CompleteStr(source)).is_ok(), "{}", source);
+        }
+        match instruction(CompleteStr("load $0 #x\n")) {
+            Err(nom::Err::Failure(nom::Context::Code(rest, ErrorKind::Custom(code)))) => {
+                assert_eq!(code, EXPECTED_OPERAND);
+                assert_eq!(rest, CompleteStr("#x\n"));
+            }
+            other => panic!("Expected a missing operand failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_heap_access() {
+        let (_, loadb) = instruction(CompleteStr("loadb $2 $0\n")).unwrap();
+        assert_eq!(
+            loadb.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![59, 2, 0, 0]
+        );
+        let (_, storeb) = instruction(CompleteStr("storeb $0 $3\n")).unwrap();
+        assert_eq!(
+            storeb.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![60, 0, 3, 0]
+        );
+        let (_, blkcpy) = instruction(CompleteStr("blkcpy $4 $5 $6\n")).unwrap();
+        assert_eq!(
+            blkcpy.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![61, 4, 5, 6]
+        );
+    }
+
+    #[test]
+    fn parse_getpc() {
+        let (_, getpc) = instruction(CompleteStr("getpc $9\n")).unwrap();
+        assert_eq!(
+            getpc.opcode,
+            Some(Token::Op {
+                code: Opcode::GETPC
+            })
+        );
+        assert_eq!(
+            getpc.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![58, 9, 0, 0]
+        );
+    }
+
+    #[test]
+    fn parse_cmov() {
+        let (_, cmov) = instruction(CompleteStr("cmov $1 $4\n")).unwrap();
+        assert_eq!(cmov.opcode, Some(Token::Op { code: Opcode::CMOV }));
+        assert_eq!(
+            cmov.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![57, 1, 4, 0]
+        );
+    }
+
+    #[test]
+    fn parse_rand() {
+        let (_, rand) = instruction(CompleteStr("rand $2\n")).unwrap();
+        assert_eq!(rand.opcode, Some(Token::Op { code: Opcode::RAND }));
+        assert_eq!(
+            rand.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![54, 2, 0, 0]
+        );
+    }
+
+    #[test]
+    fn parse_indexed_load_store() {
+        let symbols = SymbolTable::new();
+        let (_, load) = instruction(CompleteStr("loadidx $3 $0 $1\n")).unwrap();
+        assert_eq!(load.to_bytes(&symbols).unwrap(), vec![55, 3, 0, 1]);
+        let (_, store) = instruction(CompleteStr("storeidx $0 $1 $2\n")).unwrap();
+        assert_eq!(store.to_bytes(&symbols).unwrap(), vec![56, 0, 1, 2]);
+    }
+
+    #[test]
+    fn parse_prtsf() {
+        let result = instruction(CompleteStr("prtsf $4\n"));
+        assert!(result.is_ok());
+        let (_, instruction) = result.unwrap();
+        assert_eq!(
+            instruction.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![26, 4, 0, 0]
+        );
+    }
+
+    #[test]
+    fn parse_relative_immediate_jumps() {
+        let (_, forward) = instruction(CompleteStr("jmpfi #8\n")).unwrap();
+        assert_eq!(
+            forward.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![27, 0, 8, 0]
+        );
+        let (_, backward) = instruction(CompleteStr("jmpbi #260\n")).unwrap();
+        assert_eq!(
+            backward.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![28, 1, 4, 0]
+        );
+    }
+
+    #[test]
+    fn parse_syscall() {
+        let (_, syscall) = instruction(CompleteStr("syscall #1 $3\n")).unwrap();
+        assert_eq!(
+            syscall.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![30, 0, 1, 3]
+        );
+    }
+
+    #[test]
+    fn parse_stack_instructions() {
+        let symbols = SymbolTable::new();
+        let (_, push) = instruction(CompleteStr("push $3\n")).unwrap();
+        assert_eq!(push.to_bytes(&symbols).unwrap(), vec![32, 3, 0, 0]);
+        let (_, call) = instruction(CompleteStr("call #16\n")).unwrap();
+        assert_eq!(call.to_bytes(&symbols).unwrap(), vec![34, 0, 16, 0]);
+        let (_, loadl) = instruction(CompleteStr("loadl $1 #2\n")).unwrap();
+        assert_eq!(loadl.to_bytes(&symbols).unwrap(), vec![36, 1, 0, 2]);
+        let (_, storel) = instruction(CompleteStr("storel #2 $1\n")).unwrap();
+        assert_eq!(storel.to_bytes(&symbols).unwrap(), vec![37, 0, 2, 1]);
+        let (_, ret) = instruction(CompleteStr("ret\n")).unwrap();
+        assert_eq!(ret.to_bytes(&symbols).unwrap(), vec![35, 0, 0, 0]);
+    }
+
+    #[test]
+    fn float_operand_bytes() {
+        let symbols = SymbolTable::new();
+        let (_, one) = instruction(CompleteStr("loadf $1 #1.0\n")).unwrap();
+        assert_eq!(
+            one.to_bytes(&symbols).unwrap(),
+            vec![42, 1, 0, 0, 0, 0, 0, 0, 0xF0, 0x3F]
+        );
+        assert_eq!(one.width(), 10);
+        let (_, negative) = instruction(CompleteStr("loadf $1 #-1.5\n")).unwrap();
+        assert_eq!(
+            negative.to_bytes(&symbols).unwrap(),
+            vec![42, 1, 0, 0, 0, 0, 0, 0, 0xF8, 0xBF]
+        );
+
+        // Only LOADF has room for a float
+        let (_, load) = instruction(CompleteStr("load $1 #1.0\n")).unwrap();
+        match load.to_bytes(&symbols) {
+            Err(AssemblerError::OperandsTooWide { opcode, width }) => {
+                assert_eq!(opcode, "LOAD");
+                assert_eq!(width, 4);
+            }
+            other => panic!("Expected the operands not to fit, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wide_integer_operand_bytes() {
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol(Symbol::new_with_offset(
+            "far".to_string(),
+            SymbolType::Label,
+            0x0001_0203,
+        ));
+        let (_, loadw) = instruction(CompleteStr("loadw $2 #2000000000\n")).unwrap();
+        assert_eq!(
+            loadw.to_bytes(&symbols).unwrap(),
+            vec![41, 2, 0x77, 0x35, 0x94, 0x00]
+        );
+        assert_eq!(loadw.width(), 6);
+        // A label too far away for a 16-bit operand still fits in 32 bits
+        let (_, far) = instruction(CompleteStr("loadw $2 @far\n")).unwrap();
+        assert_eq!(far.to_bytes(&symbols).unwrap(), vec![41, 2, 0, 1, 2, 3]);
+        let (_, jmpe) = instruction(CompleteStr("jmpe @far\n")).unwrap();
+        assert_eq!(jmpe.to_bytes(&symbols).unwrap(), vec![22, 0, 1, 2, 3]);
+        assert_eq!(jmpe.width(), 5);
+    }
+
+    #[test]
+    fn instruction_width() {
+        for source in ["hlt\n", "load $0 #100\n", "add $0 $1 $2\n", "prts @msg\n"].iter() {
+            let (_, parsed) = instruction(CompleteStr(source)).unwrap();
+            assert_eq!(parsed.width(), 4, "{}", source);
+        }
+    }
+
+    #[test]
+    fn parse_divu() {
+        let (_, divu) = instruction(CompleteStr("divu $0 $1 $2\n")).unwrap();
+        assert_eq!(divu.opcode, Some(Token::Op { code: Opcode::DIVU }));
+        assert_eq!(
+            divu.to_bytes(&SymbolTable::new()).unwrap(),
+            vec![47, 0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn parse_compare_jumps() {
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol(Symbol::new_with_offset(
+            "done".to_string(),
+            SymbolType::Label,
+            300,
+        ));
+        for &(source, code) in [
+            ("jlt $1 $2 @done\n", Opcode::JLT),
+            ("jgt $1 $2 @done\n", Opcode::JGT),
+            ("jle $1 $2 @done\n", Opcode::JLE),
+            ("jge $1 $2 @done\n", Opcode::JGE),
+        ]
+        .iter()
+        {
+            let (_, parsed) = instruction(CompleteStr(source)).unwrap();
+            assert_eq!(parsed.opcode, Some(Token::Op { code }), "{}", source);
+            assert_eq!(parsed.width(), 5, "{}", source);
+            assert_eq!(
+                parsed.to_bytes(&symbols).unwrap(),
+                vec![code as u8, 1, 2, 1, 44],
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn parse_instruction_with_comments() {
+        let expected = AssemblerInstruction {
+            opcode: Some(Token::Op { code: Opcode::LOAD }),
+            label: None,
+            directive: None,
+            operand_one: Some(Token::Register { reg_num: 0 }),
+            operand_two: Some(Token::IntegerOperand { value: 1 }),
+            operand_three: None,
+        };
+        for source in [
+            "  load $0 #1\n",
+            "load $0 #1  ; note\n",
+            "  load $0 #1  ; note\n",
+            "; leading comment\n  load $0 #1\n",
+        ]
+        .iter()
+        {
+            let (rest, parsed) = instruction(CompleteStr(source)).unwrap();
+            assert_eq!(rest, CompleteStr(""), "{}", source);
+            assert_eq!(parsed, expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn parse_labeled_instruction_with_comment() {
+        let (rest, parsed) = instruction(CompleteStr("  top: inc $0 ; count up\nhlt")).unwrap();
+        assert_eq!(rest, CompleteStr("hlt"));
+        assert_eq!(
+            parsed.label,
+            Some(Token::LabelDeclaration {
+                name: "top".to_string()
+            })
+        );
+        assert_eq!(parsed.opcode, Some(Token::Op { code: Opcode::INC }));
+    }
+
+    #[test]
+    fn parse_dump() {
+        let symbols = SymbolTable::new();
+        let (_, dump) = instruction(CompleteStr("dump\n")).unwrap();
+        assert_eq!(dump.to_bytes(&symbols).unwrap(), vec![38, 0, 0, 0]);
+        let (_, dumpf) = instruction(CompleteStr("dumpf\n")).unwrap();
+        assert_eq!(dumpf.to_bytes(&symbols).unwrap(), vec![40, 0, 0, 0]);
+    }
+
+    #[test]
+    fn parse_instruction_with_label() {
+        let result = instruction(CompleteStr("test: inc $0\nhlt"));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr("hlt"),
+                AssemblerInstruction {
+                    label: Some(Token::LabelDeclaration {
+                        name: "test".to_string()
+                    }),
+                    directive: None,
+                    opcode: Some(Token::Op { code: Opcode::INC }),
+                    operand_one: Some(Token::Register { reg_num: 0 }),
+                    operand_two: None,
+                    operand_three: None
+                }
+            ))
+        )
+    }
+
+    #[test]
+    fn parse_instruction_stops_at_end_of_line() {
+        let result = instruction(CompleteStr("hlt\n    load $0 #1"));
+        assert!(result.is_ok());
+        let (rest, instruction) = result.unwrap();
+        assert_eq!(rest, CompleteStr("load $0 #1"));
+        assert_eq!(instruction.operand_one, None);
+    }
 }
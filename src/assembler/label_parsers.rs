@@ -16,14 +16,11 @@ named!(pub label_declaration<CompleteStr, Token>,
 );
 
 named!(pub label_usage<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            tag!("@") >>
-            name: alphanumeric >>
-            opt!(multispace) >>
-            (
-                Token::LabelUsage{name: name.to_string()}
-            )
+    do_parse!(
+        tag!("@") >>
+        name: alphanumeric >>
+        (
+            Token::LabelUsage{name: name.to_string()}
         )
     )
 );
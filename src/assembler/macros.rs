@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+
+use crate::assembler::assembler_errors::AssemblerError;
+
+/// A `.macro NAME PARAMS...` definition: the parameter names and the lines up to `.endmacro`
+#[derive(Debug, PartialEq)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+/// Source with its macros expanded, along with where each of its lines came from
+#[derive(Debug, PartialEq)]
+pub struct Expanded {
+    pub text: String,
+    /// The line of the original source each line of `text` came from, for the lines an invocation expands to as
+    /// much as any other
+    source_lines: Vec<usize>,
+}
+
+impl Expanded {
+    /// The line of the original source that line `line` of `text` came from, both counting from 1
+    pub fn source_line(&self, line: usize) -> usize {
+        match self.source_lines.get(line.saturating_sub(1)) {
+            Some(source_line) => *source_line,
+            // Past the end of the text, where the original source ends too
+            None => match self.source_lines.last() {
+                Some(last) => last + line - self.source_lines.len(),
+                None => line,
+            },
+        }
+    }
+
+    /// Points a parse error found in `text` at the line of the original source it came from
+    pub fn locate(&self, error: AssemblerError) -> AssemblerError {
+        match error {
+            AssemblerError::ParseError { kind, line, near } => AssemblerError::ParseError {
+                kind,
+                line: self.source_line(line),
+                near,
+            },
+            other => other,
+        }
+    }
+}
+
+/// Expands every macro invocation in `source` and drops the definitions, before the source is parsed. A macro is
+/// defined with
+///
+/// ```text
+/// .macro inc2 $r
+/// inc $r
+/// inc $r
+/// .endmacro
+/// ```
+///
+/// and invoked like an instruction, `inc2 $0`, with one argument per parameter. Arguments replace whole words in the
+/// body, so `$r` doesn't touch `$rr`. Bodies can invoke other macros, but not themselves. A label on an invocation
+/// goes on the first line of the expansion. An invocation can take up more lines than it did in the source, so use
+/// `Expanded::locate` to report errors against the original lines.
+pub fn expand_macros(source: &str) -> Result<Expanded, AssemblerError> {
+    let (lines, macros) = collect_macros(source)?;
+    let mut expanded = vec![];
+    let mut source_lines = vec![];
+    for (idx, line) in lines.iter().enumerate() {
+        expand_lines(
+            std::slice::from_ref(line),
+            idx + 1,
+            &macros,
+            &mut vec![],
+            &mut expanded,
+        )?;
+        source_lines.resize(expanded.len(), idx + 1);
+    }
+    Ok(Expanded {
+        text: expanded.join("\n"),
+        source_lines,
+    })
+}
+
+/// Splits the definitions out of the source, leaving a blank line for each line they took up
+fn collect_macros(source: &str) -> Result<(Vec<String>, HashMap<String, Macro>), AssemblerError> {
+    let mut lines = vec![];
+    let mut macros = HashMap::new();
+    let mut current: Option<(String, Macro)> = None;
+    for (idx, line) in source.lines().enumerate() {
+        let line_number = idx + 1;
+        let words = words(line);
+        let keyword = words.first().map(|word| word.to_lowercase());
+        match (keyword.as_deref(), current.take()) {
+            (Some(".macro"), Some(_)) => {
+                return Err(AssemblerError::MalformedMacro {
+                    line: line_number,
+                    reason: "macros can't be defined inside other macros".to_string(),
+                });
+            }
+            (Some(".macro"), None) => {
+                let name = match words.get(1) {
+                    Some(name) if name.starts_with(char::is_alphabetic) => name.to_string(),
+                    _ => {
+                        return Err(AssemblerError::MalformedMacro {
+                            line: line_number,
+                            reason: ".macro needs a name".to_string(),
+                        });
+                    }
+                };
+                if macros.contains_key(&name) {
+                    return Err(AssemblerError::MalformedMacro {
+                        line: line_number,
+                        reason: format!("{} is already defined", name),
+                    });
+                }
+                let params = words[2..].iter().map(|param| param.to_string()).collect();
+                current = Some((
+                    name,
+                    Macro {
+                        params,
+                        body: vec![],
+                    },
+                ));
+            }
+            (Some(".endmacro"), Some((name, definition))) => {
+                macros.insert(name, definition);
+            }
+            (Some(".endmacro"), None) => {
+                return Err(AssemblerError::MalformedMacro {
+                    line: line_number,
+                    reason: ".endmacro without a .macro".to_string(),
+                });
+            }
+            (_, Some((name, mut definition))) => {
+                definition.body.push(line.to_string());
+                current = Some((name, definition));
+                lines.push(String::new());
+                continue;
+            }
+            (_, None) => {
+                lines.push(line.to_string());
+                continue;
+            }
+        }
+        lines.push(String::new());
+    }
+    if let Some((name, _)) = current {
+        return Err(AssemblerError::UnterminatedMacro { name });
+    }
+    Ok((lines, macros))
+}
+
+/// Copies `lines` to `expanded`, replacing invocations with their bodies. `line_number` is the source line they came
+/// from, and `active` holds the macros being expanded, to catch one that invokes itself.
+fn expand_lines(
+    lines: &[String],
+    line_number: usize,
+    macros: &HashMap<String, Macro>,
+    active: &mut Vec<String>,
+    expanded: &mut Vec<String>,
+) -> Result<(), AssemblerError> {
+    for line in lines {
+        let mut words = words(line);
+        let label = match words.first() {
+            Some(word) if word.ends_with(':') => Some(words.remove(0)),
+            _ => None,
+        };
+        let (name, definition) = match words.first().and_then(|name| macros.get_key_value(*name)) {
+            Some(found) => found,
+            None => {
+                expanded.push(line.clone());
+                continue;
+            }
+        };
+        let args = &words[1..];
+        if args.len() != definition.params.len() {
+            return Err(AssemblerError::MacroArgumentMismatch {
+                name: name.clone(),
+                expected: definition.params.len(),
+                found: args.len(),
+            });
+        }
+        if active.contains(name) {
+            return Err(AssemblerError::RecursiveMacro { name: name.clone() });
+        }
+
+        let body: Vec<String> = definition
+            .body
+            .iter()
+            .map(|body_line| substitute(body_line, &definition.params, args))
+            .collect();
+        let first = expanded.len();
+        active.push(name.clone());
+        expand_lines(&body, line_number, macros, active, expanded)?;
+        active.pop();
+        match (label, expanded.get_mut(first)) {
+            (Some(label), Some(first_line)) => {
+                *first_line = format!("{} {}", label, first_line.trim_start());
+            }
+            (Some(label), None) => {
+                return Err(AssemblerError::MalformedMacro {
+                    line: line_number,
+                    reason: format!("{} has no lines to put {} on", name, label),
+                });
+            }
+            (None, _) => {}
+        }
+    }
+    Ok(())
+}
+
+/// The words of a line before any comment. Operands can be separated by commas as well as spaces.
+fn words(line: &str) -> Vec<&str> {
+    line.split(';')
+        .next()
+        .unwrap_or("")
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Replaces each whole word of `line` that is one of `params` with the matching argument
+fn substitute(line: &str, params: &[String], args: &[&str]) -> String {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    let mut result = String::with_capacity(line.len());
+    let mut word = String::new();
+    for c in line.chars().chain(std::iter::once('\n')) {
+        if is_word_char(c) {
+            word.push(c);
+            continue;
+        }
+        match params.iter().position(|param| *param == word) {
+            Some(idx) => result.push_str(args[idx]),
+            None => result.push_str(&word),
+        }
+        word.clear();
+        result.push(c);
+    }
+    result.pop();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_simple_macro() {
+        let source = ".macro inc2 $r\ninc $r\ninc $r\n.endmacro\n.code\ninc2 $3\nhlt";
+        let expanded = expand_macros(source).unwrap();
+        let lines: Vec<&str> = expanded
+            .text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect();
+        assert_eq!(lines, vec![".code", "inc $3", "inc $3", "hlt"]);
+        // The definition's lines are left blank, and both lines of the expansion come from line 6
+        assert_eq!(expanded.text.lines().count(), source.lines().count() + 1);
+        assert_eq!(expanded.source_line(6), 6);
+        assert_eq!(expanded.source_line(7), 6);
+        assert_eq!(expanded.source_line(8), 7);
+    }
+
+    #[test]
+    fn test_expand_nested_macro_with_label() {
+        let source = r"
+        .macro inc2 $r
+            inc $r
+            inc $r
+        .endmacro
+        .macro addinc $a, $b, $dst ; adds, then bumps the result twice
+            add $a $b $dst
+            inc2 $dst
+        .endmacro
+        .code
+        top: addinc $0 $1 $2
+        hlt";
+        let expanded = expand_macros(source).unwrap();
+        let lines: Vec<&str> = expanded
+            .text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![".code", "top: add $0 $1 $2", "inc $2", "inc $2", "hlt"]
+        );
+    }
+
+    #[test]
+    fn test_argument_mismatch() {
+        let source = ".macro inc2 $r\ninc $r\ninc $r\n.endmacro\n.code\ninc2 $0 $1\n";
+        assert_eq!(
+            expand_macros(source),
+            Err(AssemblerError::MacroArgumentMismatch {
+                name: "inc2".to_string(),
+                expected: 1,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_recursive_macro() {
+        let source = ".macro forever\nforever\n.endmacro\n.code\nforever\n";
+        assert_eq!(
+            expand_macros(source),
+            Err(AssemblerError::RecursiveMacro {
+                name: "forever".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_malformed_macros() {
+        assert_eq!(
+            expand_macros(".code\n.macro inc2 $r\ninc $r\n"),
+            Err(AssemblerError::UnterminatedMacro {
+                name: "inc2".to_string()
+            })
+        );
+        for source in [
+            ".macro\n.endmacro\n",
+            ".endmacro\n",
+            ".macro outer\n.macro inner\n.endmacro\n.endmacro\n",
+            ".macro twice\n.endmacro\n.macro twice\n.endmacro\n",
+        ]
+        .iter()
+        {
+            match expand_macros(source) {
+                Err(AssemblerError::MalformedMacro { .. }) => {}
+                other => panic!("Expected {:?} to be malformed, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_label_on_empty_macro() {
+        let source = ".macro nothing\n.endmacro\n.code\ntop: nothing\nhlt\n";
+        assert_eq!(
+            expand_macros(source),
+            Err(AssemblerError::MalformedMacro {
+                line: 4,
+                reason: "nothing has no lines to put top: on".to_string()
+            })
+        );
+        // Without a label there's nothing to lose
+        assert!(expand_macros(".macro nothing\n.endmacro\n.code\nnothing\nhlt\n").is_ok());
+    }
+
+    #[test]
+    fn test_substitute_whole_words() {
+        let params = vec!["$r".to_string(), "n".to_string()];
+        assert_eq!(
+            substitute("load $r #n ; $rr n2", &params, &["$4", "7"]),
+            "load $4 #7 ; $rr n2"
+        );
+    }
+}
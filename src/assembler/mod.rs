@@ -1,18 +1,26 @@
 pub mod assembler_errors;
+pub mod comment_parsers;
 pub mod directive_parsers;
+pub mod disassembler;
 pub mod instruction_parsers;
 pub mod label_parsers;
+pub mod macros;
 pub mod opcode_parsers;
 pub mod operand_parsers;
+pub mod optimizer;
 pub mod program_parsers;
 pub mod register_parsers;
 pub mod symbols;
 
-use byteorder::{LittleEndian, WriteBytesExt};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
 use nom::types::CompleteStr;
 
 use crate::assembler::assembler_errors::AssemblerError;
-use crate::assembler::instruction_parsers::AssemblerInstruction;
+use crate::assembler::instruction_parsers::{AssemblerInstruction, MAX_LOAD_IMMEDIATE};
 use crate::assembler::program_parsers::{program, Program};
 use crate::assembler::symbols::{Symbol, SymbolTable, SymbolType};
 use crate::instruction::Opcode;
@@ -20,18 +28,78 @@ use crate::instruction::Opcode;
 /// Magic number that begins every bytecode file prefix. These spell out EPIE in ASCII, if you were wondering.
 pub const PIE_HEADER_PREFIX: [u8; 4] = [0x45, 0x50, 0x49, 0x45];
 
-/// Constant that determines how long the header is. There are 60 zeros left after the prefix, for later usage if needed.
+/// Constant that determines how long the header is. Apart from the prefix, version, read-only data length and entry point, the rest is zeros left for later usage if needed.
 pub const PIE_HEADER_LENGTH: usize = 64;
 
+/// Where in the header the bytecode format version is stored, right after the prefix
+pub const PIE_HEADER_VERSION_OFFSET: usize = 4;
+
+/// Version of the bytecode format the assembler writes and the VM understands. Bump it when the format changes.
+pub const BYTECODE_VERSION: u8 = 4;
+
+/// The oldest bytecode format version the VM can still run. Version 4 widened JMPE's target to 32 bits, so earlier
+/// programs would jump to the wrong place.
+pub const OLDEST_BYTECODE_VERSION: u8 = 4;
+
+/// Where in the header the 4-byte little-endian length of the read-only data is stored
+pub const PIE_HEADER_RO_LENGTH_OFFSET: usize = 8;
+
+/// Where in the header the 4-byte little-endian offset of the first instruction to run is stored, set by `.entry`.
+/// Zero means the start of the code.
+pub const PIE_HEADER_ENTRY_OFFSET: usize = 12;
+
+/// Largest buffer a single `.space` can reserve. Read-only data is addressed with 16-bit operands, so anything bigger
+/// couldn't be reached anyway.
+pub const MAX_SPACE_SIZE: i32 = u16::MAX as i32;
+
+/// Every directive the assembler understands. Anything else is reported as `AssemblerError::UnknownDirective` in
+/// the first phase, whatever operands it has.
+pub const KNOWN_DIRECTIVES: [&str; 9] = [
+    "data", "code", "asciiz", "integer", "space", "byte", "equ", "include", "entry",
+];
+
+/// Byte order of the integer and label operands inside instructions: 16-bit immediates and jump targets, and the
+/// 32-bit immediates of the wide instructions. A LOAD too big for 16 bits is split by value into a LOAD of the low
+/// half and a LUI of the high half, so each half is encoded in this order too.
+pub type OperandEndian = BigEndian;
+
+/// Byte order of everything else: the header fields, the code offset after the header, `.integer` read-only data,
+/// words in the heap and float immediates.
+pub type DataEndian = LittleEndian;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
-    Op { code: Opcode },
-    Register { reg_num: u8 },
-    IntegerOperand { value: i32 },
-    LabelDeclaration { name: String },
-    LabelUsage { name: String },
-    Directive { name: String },
-    IrString { name: String },
+    Op {
+        code: Opcode,
+    },
+    Register {
+        reg_num: u8,
+    },
+    IntegerOperand {
+        value: i32,
+    },
+    FloatOperand {
+        value: f64,
+    },
+    LabelDeclaration {
+        name: String,
+    },
+    LabelUsage {
+        name: String,
+    },
+    Directive {
+        name: String,
+    },
+    IrString {
+        name: String,
+    },
+    Identifier {
+        name: String,
+    },
+    /// Comma-separated directive operands, such as the bytes in `.byte #1, #2, #3`
+    OperandList {
+        operands: Vec<Token>,
+    },
 }
 
 #[derive(Debug, Default)]
@@ -45,6 +113,16 @@ pub struct Assembler {
     current_section: Option<AssemblerSection>,
     current_instruction: u32,
     errors: Vec<AssemblerError>,
+    /// Canonical paths of the files currently being included, used to detect include cycles
+    included: HashSet<PathBuf>,
+    /// Runs the peephole optimizer on the program before code labels are resolved
+    optimize: bool,
+    /// The label named by `.entry`, if there is one
+    entry: Option<String>,
+    /// Where the entry label ended up once code labels were resolved, or zero to start at the first instruction
+    entry_offset: u32,
+    /// Where the code will be loaded, if it isn't going to stay behind its own header
+    code_offset: Option<u32>,
     buf: [u8; 4],
 }
 
@@ -60,13 +138,67 @@ impl Assembler {
             sections: vec![],
             errors: vec![],
             current_section: None,
+            included: HashSet::new(),
+            optimize: false,
+            entry: None,
+            entry_offset: 0,
+            code_offset: None,
             buf: [0, 0, 0, 0],
         }
     }
 
+    /// Turns on the peephole optimizer. Only use it on programs whose jumps all go to labels, since it moves code.
+    pub fn with_optimization(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    /// Resolves code labels as if the first instruction were at `offset`, for code that is cut out of the assembled
+    /// program and loaded somewhere else, such as the end of a headerless program
+    pub fn with_code_offset(mut self, offset: u32) -> Self {
+        self.code_offset = Some(offset);
+        self
+    }
+
+    /// Assembles source text. Any `.include` paths are resolved relative to the working directory.
     pub fn assemble(&mut self, raw: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        self.assemble_with_base(raw, Path::new(""))
+    }
+
+    /// Assembles a source file. Any `.include` paths are resolved relative to the directory the file is in.
+    pub fn assemble_file(&mut self, path: &Path) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        let (canonical, raw) = match Assembler::read_source(path) {
+            Ok(source) => source,
+            Err(e) => return Err(vec![e]),
+        };
+        let base = canonical.parent().unwrap_or(Path::new("")).to_path_buf();
+        self.included.insert(canonical.clone());
+        let result = self.assemble_with_base(&raw, &base);
+        self.included.remove(&canonical);
+        result
+    }
+
+    fn assemble_with_base(
+        &mut self,
+        raw: &str,
+        base: &Path,
+    ) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        let expanded = match macros::expand_macros(raw) {
+            Ok(expanded) => expanded,
+            Err(e) => return Err(vec![e]),
+        };
+        let raw = &expanded.text;
         match program(CompleteStr(raw)) {
-            Ok((_remainder, mut program)) => {
+            Ok((remainder, _)) if !remainder.is_empty() => Err(vec![
+                expanded.locate(AssemblerError::from_remainder(raw, &remainder))
+            ]),
+            Ok((_, mut program)) => {
+                self.process_includes(&mut program, base);
+                if !self.errors.is_empty() {
+                    error!("Errors were found while including files: {:?}", self.errors);
+                    return Err(self.errors.clone());
+                }
+
                 self.process_first_phase(&mut program);
 
                 if !self.errors.is_empty() {
@@ -79,16 +211,30 @@ impl Assembler {
                 debug!("First parsing phase complete");
                 debug!("Phase 1 program: {:#?}", program);
 
-                if self.sections.len() != 2 {
-                    println!("Did not find at least two sections.");
+                // Sections can repeat: .data blocks are merged into the read-only data and .code blocks into the code
+                let has_code = self
+                    .sections
+                    .iter()
+                    .any(|section| matches!(section, AssemblerSection::Code { .. }));
+                if !has_code {
+                    println!("Did not find a code section.");
                     self.errors.push(AssemblerError::InsufficientSections);
                     // TODO: Can we avoid a clone here?
                     return Err(self.errors.clone());
                 }
 
                 let mut body = self.process_second_phase(&program);
+                if !self.errors.is_empty() {
+                    error!(
+                        "Errors were found in the second parsing phase: {:?}",
+                        self.errors
+                    );
+                    return Err(self.errors.clone());
+                }
                 let mut assembled_program = self.write_pie_header();
 
+                // The read-only data sits between the header and the code
+                assembled_program.extend_from_slice(&self.ro);
                 assembled_program.append(&mut body);
                 debug!("Complete program is: {:#?}", assembled_program);
 
@@ -96,11 +242,103 @@ impl Assembler {
             }
             Err(e) => {
                 println!("There was an error assembling the code: {:?}", e);
-                Err(vec![AssemblerError::ParseError {
-                    error: e.to_string(),
-                }])
+                Err(vec![
+                    expanded.locate(AssemblerError::from_parse_error(raw, &e))
+                ])
+            }
+        }
+    }
+
+    /// Writes every symbol with its resolved offset to `path` as JSON, for debuggers and the disassembler to show
+    /// names instead of raw offsets. `SymbolTable::read_symbol_map` reads it back.
+    pub fn write_symbol_map(&self, path: &Path) -> Result<(), AssemblerError> {
+        let to_error = |error: String| AssemblerError::SymbolMapError {
+            path: path.display().to_string(),
+            error,
+        };
+        let map =
+            serde_json::to_string_pretty(&self.symbols).map_err(|e| to_error(e.to_string()))?;
+        fs::write(path, map).map_err(|e| to_error(e.to_string()))
+    }
+
+    fn read_source(path: &Path) -> Result<(PathBuf, String), AssemblerError> {
+        let to_error = |e: std::io::Error| AssemblerError::FileReadError {
+            path: path.display().to_string(),
+            error: e.to_string(),
+        };
+        let canonical = path.canonicalize().map_err(to_error)?;
+        let raw = fs::read_to_string(&canonical).map_err(to_error)?;
+        Ok((canonical, raw))
+    }
+
+    /// Replaces every `.include 'path'` directive with the instructions of the file it names, recursively
+    fn process_includes(&mut self, p: &mut Program, base: &Path) {
+        let mut instructions = vec![];
+        for i in p.instructions.drain(..) {
+            if i.get_directive_name() != Some("include".to_string()) {
+                instructions.push(i);
+                continue;
+            }
+            if let Some(mut included) = self.load_include(&i, base) {
+                instructions.append(&mut included.instructions);
+            }
+        }
+        p.instructions = instructions;
+    }
+
+    fn load_include(&mut self, i: &AssemblerInstruction, base: &Path) -> Option<Program> {
+        let path = match i.get_string_constant() {
+            Some(path) => base.join(path),
+            None => {
+                self.errors.push(AssemblerError::FileReadError {
+                    path: String::new(),
+                    error: "An .include directive needs a quoted path".to_string(),
+                });
+                return None;
             }
+        };
+        debug!("Including file: {:?}", path);
+        let (canonical, raw) = match Assembler::read_source(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                self.errors.push(e);
+                return None;
+            }
+        };
+        if self.included.contains(&canonical) {
+            self.errors.push(AssemblerError::CyclicInclude {
+                path: canonical.display().to_string(),
+            });
+            return None;
         }
+
+        // Macros are local to the file that defines them
+        let expanded = match macros::expand_macros(&raw) {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                self.errors.push(e);
+                return None;
+            }
+        };
+        let raw = &expanded.text;
+        let mut included = match program(CompleteStr(raw)) {
+            Ok((remainder, _)) if !remainder.is_empty() => {
+                self.errors
+                    .push(expanded.locate(AssemblerError::from_remainder(raw, &remainder)));
+                return None;
+            }
+            Ok((_, included)) => included,
+            Err(e) => {
+                self.errors
+                    .push(expanded.locate(AssemblerError::from_parse_error(raw, &e)));
+                return None;
+            }
+        };
+        let dir = canonical.parent().unwrap_or(base).to_path_buf();
+        self.included.insert(canonical.clone());
+        self.process_includes(&mut included, &dir);
+        self.included.remove(&canonical);
+        Some(included)
     }
 
     fn write_pie_header(&self) -> Vec<u8> {
@@ -108,40 +346,60 @@ impl Assembler {
         for byte in PIE_HEADER_PREFIX.into_iter() {
             header.push(byte.clone());
         }
+        header.push(BYTECODE_VERSION);
+
+        while header.len() < PIE_HEADER_RO_LENGTH_OFFSET {
+            header.push(0);
+        }
+        header
+            .write_u32::<DataEndian>(self.ro.len() as u32)
+            .unwrap();
+        header.write_u32::<DataEndian>(self.entry_offset).unwrap();
 
-        while header.len() <= PIE_HEADER_LENGTH {
+        while header.len() < PIE_HEADER_LENGTH {
             header.push(0 as u8);
         }
+
+        // Right after the header is the 4-byte offset of the code from the end of the header. The read-only data comes first, so this is its length too.
+        let mut wtr: Vec<u8> = vec![];
+        wtr.write_u32::<DataEndian>(self.ro.len() as u32).unwrap();
+        header.append(&mut wtr);
         header
     }
 
+    /// Where the first opcode ends up in the assembled program: after the header, its starting offset field, and the read-only data.
+    /// With a code offset, that's where it ends up instead.
+    fn code_start(&self) -> u32 {
+        self.code_offset
+            .unwrap_or((PIE_HEADER_LENGTH + 4 + self.ro.len()) as u32)
+    }
+
     fn process_first_phase(&mut self, p: &mut Program) {
+        self.process_aliases(p);
+
         info!("Beginning search for LOAD instructions that need to be split up");
         let mut inserts_to_do = Vec::new();
         for (idx, i) in p.instructions.iter_mut().enumerate() {
             if i.is_integer_needs_splitting() {
-                let value = i.get_integer_value();
-                let _register = i.get_register_number();
-                let mut wtr = vec![];
-                let _ = wtr.write_i16::<LittleEndian>(value.unwrap());
-                i.operand_two = Some(Token::IntegerOperand {
-                    value: wtr[1].into(),
-                });
+                // LOAD puts the lower 16 bits in the register, then LUI fills in the upper 16 bits
+                let value = i.get_integer_value().unwrap();
+                let upper = (value >> 16) & MAX_LOAD_IMMEDIATE;
+                let lower = value & MAX_LOAD_IMMEDIATE;
+                i.operand_two = Some(Token::IntegerOperand { value: lower });
                 let new_instruction = AssemblerInstruction {
                     opcode: Some(Token::Op { code: Opcode::LUI }),
                     label: None,
                     directive: None,
                     operand_one: i.operand_one.clone(),
-                    operand_two: Some(Token::IntegerOperand {
-                        value: wtr[0].into(),
-                    }),
+                    operand_two: Some(Token::IntegerOperand { value: upper }),
                     operand_three: None,
                 };
                 inserts_to_do.push((idx + 1, new_instruction));
             }
         }
 
-        for insert in inserts_to_do {
+        // Insert from the back so earlier inserts don't shift the indices of later ones
+        for insert in inserts_to_do.into_iter().rev() {
             p.instructions.insert(insert.0, insert.1)
         }
         info!("Beginning first parsing phase");
@@ -168,16 +426,138 @@ impl Assembler {
                         instruction: self.current_instruction,
                     });
                 }
+            }
 
-                if i.is_directive() {
-                    self.process_directive(i);
+            if i.is_opcode() {
+                match self.current_section {
+                    Some(AssemblerSection::Data { .. }) => {
+                        self.errors.push(AssemblerError::InstructionInDataSection {
+                            instruction: self.current_instruction,
+                        });
+                    }
+                    // An unlabeled opcode outside of a section; labeled ones were reported above
+                    None if !i.is_label() => {
+                        self.errors.push(AssemblerError::NoSegmentDeclarationFound {
+                            instruction: self.current_instruction,
+                        });
+                    }
+                    _ => {}
                 }
+            }
+
+            if i.is_directive() {
+                self.process_directive(i);
+            }
+
+            // This is used to keep track of which instruction we hit an error on
+            self.current_instruction += 1;
+        }
+        if self.optimize {
+            let removed = optimizer::fold_constants(p) + optimizer::peephole(p);
+            debug!("Optimizer removed {} instructions", removed);
+        }
+        self.resolve_code_labels(p);
+        self.resolve_entry();
+        self.phase = AssemblerPhase::Second;
+    }
+
+    /// Gives labels on opcodes the offset of that opcode in the assembled program. This can only happen once all of the
+    /// read-only data has been seen, since the code comes after it.
+    fn resolve_code_labels(&mut self, p: &Program) {
+        let mut offset = self.code_start();
+        for i in &p.instructions {
+            if !i.is_opcode() {
+                continue;
+            }
+            if let Some(name) = i.get_label_name() {
+                self.symbols.set_symbol_offset(&name, offset);
+            }
+            offset += i.width();
+        }
+    }
+
+    /// Looks up where the `.entry` label landed. It has to be on an instruction, which only code labels are.
+    fn resolve_entry(&mut self) {
+        let name = match self.entry {
+            Some(ref name) => name.clone(),
+            None => return,
+        };
+        match self.symbols.symbol_value(&name) {
+            Some(offset) if offset >= self.code_start() => self.entry_offset = offset,
+            Some(_) => self.errors.push(AssemblerError::InvalidEntryPoint { name }),
+            None => self.errors.push(AssemblerError::UnknownLabel { name }),
+        }
+    }
+
+    /// Records every `.equ` alias and then swaps each bare identifier operand for the register or integer it names.
+    /// This has to run before LOAD splitting so that a named constant too large for 16 bits still gets split.
+    fn process_aliases(&mut self, p: &mut Program) {
+        info!("Beginning search for .equ aliases");
+        for (idx, i) in p.instructions.iter().enumerate() {
+            if i.get_directive_name() == Some("equ".to_string()) {
+                self.handle_equ(i, idx as u32);
+            }
+        }
+
+        for i in p.instructions.iter_mut() {
+            // `.entry main` names a label, not an alias
+            if let Some("equ") | Some("entry") = i.get_directive_name().as_deref() {
+                continue;
+            }
+            self.resolve_alias(&mut i.operand_one);
+            self.resolve_alias(&mut i.operand_two);
+            self.resolve_alias(&mut i.operand_three);
+        }
+    }
 
-                // This is used to keep track of which instruction we hit an error on
-                self.current_instruction += 1;
+    fn handle_equ(&mut self, i: &AssemblerInstruction, instruction: u32) {
+        let name = match i.operand_one {
+            Some(Token::Identifier { ref name }) => name.clone(),
+            _ => {
+                self.errors
+                    .push(AssemblerError::MalformedAliasDeclaration { instruction });
+                return;
             }
-            self.phase = AssemblerPhase::Second;
+        };
+        if self.symbols.has_symbol(&name) {
+            self.errors
+                .push(AssemblerError::SymbolAlreadyDeclared { name });
+            return;
         }
+        let symbol = match i.operand_two {
+            Some(Token::Register { reg_num }) => {
+                Symbol::new_with_offset(name, SymbolType::Register, reg_num as u32)
+            }
+            Some(Token::IntegerOperand { value }) => {
+                Symbol::new_with_offset(name, SymbolType::Constant, value as u32)
+            }
+            _ => {
+                self.errors
+                    .push(AssemblerError::MalformedAliasDeclaration { instruction });
+                return;
+            }
+        };
+        self.symbols.add_symbol(symbol);
+    }
+
+    fn resolve_alias(&mut self, operand: &mut Option<Token>) {
+        let name = match operand {
+            Some(Token::Identifier { name }) => name.clone(),
+            _ => return,
+        };
+        let value = self.symbols.symbol_value(&name);
+        *operand = match (self.symbols.symbol_type(&name), value) {
+            (Some(SymbolType::Register), Some(value)) => Some(Token::Register {
+                reg_num: value as u8,
+            }),
+            (Some(SymbolType::Constant), Some(value)) => Some(Token::IntegerOperand {
+                value: value as i32,
+            }),
+            _ => {
+                self.errors.push(AssemblerError::UnknownAlias { name });
+                return;
+            }
+        };
     }
 
     fn process_second_phase(&mut self, p: &Program) -> Vec<u8> {
@@ -194,8 +574,10 @@ impl Assembler {
                 continue;
             }
             if i.is_opcode() {
-                let mut bytes = i.to_bytes(&self.symbols);
-                program.append(&mut bytes);
+                match i.to_bytes(&self.symbols) {
+                    Ok(mut bytes) => program.append(&mut bytes),
+                    Err(e) => self.errors.push(e),
+                }
             }
             self.current_instruction += 1
         }
@@ -230,7 +612,8 @@ impl Assembler {
             }
         };
         if self.symbols.has_symbol(&name) {
-            self.errors.push(AssemblerError::SymbolAlreadyDeclared);
+            self.errors
+                .push(AssemblerError::SymbolAlreadyDeclared { name });
             return;
         }
         let symbol = Symbol::new(name, SymbolType::Label);
@@ -245,15 +628,28 @@ impl Assembler {
                 return;
             }
         };
+        if !KNOWN_DIRECTIVES.contains(&directive_name.as_str()) {
+            self.errors.push(AssemblerError::UnknownDirective {
+                name: directive_name,
+            });
+            return;
+        }
 
         if i.has_operands() {
             match directive_name.as_ref() {
                 "asciiz" => self.handle_asciiz(i),
+                "integer" => self.handle_integer(i),
+                "space" => self.handle_space(i),
+                "byte" => self.handle_byte(i),
+                "entry" => self.handle_entry(i),
+                // Aliases were already recorded by `process_aliases`
+                "equ" => {}
+                // Section headers, which are known but take nothing
                 _ => {
-                    self.errors.push(AssemblerError::UnknownDirectiveFound {
-                        directive: directive_name.clone(),
-                    });
-                    return;
+                    self.errors
+                        .push(AssemblerError::UnexpectedDirectiveOperands {
+                            name: directive_name.clone(),
+                        });
                 }
             }
         } else {
@@ -291,7 +687,136 @@ impl Assembler {
         }
     }
 
-    // fn handle_integer(&mut self, i: &AssemblerInstruction) {}
+    /// Writes a 32-bit little-endian integer into the read-only data, pointing the directive's label at it
+    fn handle_integer(&mut self, i: &AssemblerInstruction) {
+        if self.phase != AssemblerPhase::First {
+            return;
+        }
+
+        match i.get_i32_constant() {
+            Some(value) => {
+                match i.get_label_name() {
+                    Some(name) => {
+                        self.symbols.set_symbol_offset(&name, self.ro_offset);
+                    }
+                    None => {
+                        self.errors
+                            .push(AssemblerError::StringConstantDeclaredWithoutLabel {
+                                instruction: self.current_instruction,
+                            });
+                        return;
+                    }
+                };
+                self.ro.write_i32::<DataEndian>(value).unwrap();
+                self.ro_offset += 4;
+            }
+            None => {
+                self.errors.push(AssemblerError::NonOperandInOperandField);
+            }
+        }
+    }
+
+    /// Reserves zeroed bytes in the read-only data for buffers, pointing the directive's label at the first one
+    fn handle_space(&mut self, i: &AssemblerInstruction) {
+        if self.phase != AssemblerPhase::First {
+            return;
+        }
+
+        let size = match i.get_i32_constant() {
+            Some(size) => size,
+            None => {
+                self.errors.push(AssemblerError::NonOperandInOperandField);
+                return;
+            }
+        };
+        if !(0..=MAX_SPACE_SIZE).contains(&size) {
+            self.errors.push(AssemblerError::SpaceTooLarge {
+                size,
+                max: MAX_SPACE_SIZE,
+            });
+            return;
+        }
+        match i.get_label_name() {
+            Some(name) => {
+                self.symbols.set_symbol_offset(&name, self.ro_offset);
+            }
+            None => {
+                self.errors
+                    .push(AssemblerError::StringConstantDeclaredWithoutLabel {
+                        instruction: self.current_instruction,
+                    });
+                return;
+            }
+        }
+        self.ro.resize(self.ro.len() + size as usize, 0);
+        self.ro_offset += size as u32;
+    }
+
+    /// Records the label execution starts at, written as `.entry @main` or `.entry main`. There can only be one.
+    fn handle_entry(&mut self, i: &AssemblerInstruction) {
+        if self.phase != AssemblerPhase::First {
+            return;
+        }
+
+        let name = match i.operand_one {
+            Some(Token::LabelUsage { ref name }) | Some(Token::Identifier { ref name }) => {
+                name.clone()
+            }
+            _ => {
+                self.errors.push(AssemblerError::NonOperandInOperandField);
+                return;
+            }
+        };
+        if self.entry.is_some() {
+            self.errors.push(AssemblerError::InvalidEntryPoint { name });
+            return;
+        }
+        self.entry = Some(name);
+    }
+
+    /// Writes one byte per operand into the read-only data, pointing the directive's label at the first. Each operand
+    /// is an integer from 0 to 255 or a one character string.
+    fn handle_byte(&mut self, i: &AssemblerInstruction) {
+        if self.phase != AssemblerPhase::First {
+            return;
+        }
+
+        let operands = match i.operand_one {
+            Some(Token::OperandList { ref operands }) => operands.clone(),
+            Some(ref operand) => vec![operand.clone()],
+            None => return,
+        };
+        let mut bytes = Vec::with_capacity(operands.len());
+        for operand in operands {
+            match operand {
+                Token::IntegerOperand { value } if value >= 0 && value <= i32::from(u8::MAX) => {
+                    bytes.push(value as u8)
+                }
+                Token::IrString { ref name } if name.len() == 1 => bytes.push(name.as_bytes()[0]),
+                Token::IntegerOperand { value } => {
+                    self.errors.push(AssemblerError::ByteOutOfRange {
+                        value: value.to_string(),
+                    });
+                    return;
+                }
+                Token::IrString { name } => {
+                    self.errors.push(AssemblerError::ByteOutOfRange {
+                        value: format!("'{}'", name),
+                    });
+                    return;
+                }
+                _ => {
+                    self.errors.push(AssemblerError::NonOperandInOperandField);
+                    return;
+                }
+            }
+        }
+        if let Some(name) = i.get_label_name() {
+            self.symbols.set_symbol_offset(&name, self.ro_offset);
+        }
+        self.ro_offset += bytes.len() as u32;
+        self.ro.append(&mut bytes);
+    }
 
     fn process_section_header(&mut self, header_name: &str) {
         let new_section: AssemblerSection = header_name.into();
@@ -301,6 +826,9 @@ impl Assembler {
                 "Found an section header that is unknown: {:#?}",
                 header_name
             );
+            self.errors.push(AssemblerError::UnknownDirective {
+                name: header_name.to_string(),
+            });
             return;
         }
         self.sections.push(new_section.clone());
@@ -350,7 +878,10 @@ impl<'a> From<&'a str> for AssemblerSection {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assembler::assembler_errors::ParseErrorKind;
     use crate::assembler::symbols::{Symbol, SymbolTable, SymbolType};
+    use crate::vm::{VMEventType, VirtualMachine};
+    use byteorder::ByteOrder;
 
     // #[test]
     // fn assemble_program() {
@@ -401,79 +932,1069 @@ mod tests {
     //     assert_eq!(program.is_ok(), true);
     // }
 
-    // #[test]
-    // /// Simple test of data that goes into the read only section
-    // fn test_code_start_offset_written() {
-    //     let mut asm = Assembler::new();
-    //     let test_string = r"
-    //     .data
-    //     test1: .asciiz 'Hello'
-    //     .code
-    //     load $0 #100
-    //     load $1 #1
-    //     load $2 #0
-    //     test: inc $0
-    //     neq $0 $2
-    //     jmpe @test
-    //     hlt
-    //     ";
-    //     let program = asm.assemble(test_string);
-    //     assert_eq!(program.is_ok(), true);
-    //     let unwrapped = program.unwrap();
-    //     assert_eq!(unwrapped[64], 6);
-    // }
-
-    // #[test]
-    // /// Simple test of data that goes into the read only section
-    // fn test_ro_data_i32() {
-    //     let mut asm = Assembler::new();
-    //     let test_string = r"
-    //     .data
-    //     test: .integer #300
-    //     .code
-    //     ";
-    //     let program = asm.assemble(test_string);
-    //     assert_eq!(program.is_ok(), true);
-    // }
-
     #[test]
-    /// This tests that a section name that isn't `code` or `data` throws an error
-    fn test_bad_ro_data() {
+    /// Tests that the offset of the code from the end of the header is written after the header
+    fn test_code_start_offset_written() {
         let mut asm = Assembler::new();
         let test_string = r"
+        .data
+        test1: .asciiz 'Hello'
         .code
-        test: .asciiz 'This is a test'
-        .wrong
+        load $0 #100
+        load $1 #1
+        load $2 #0
+        test: inc $0
+        neq $0 $2
+        jmpe @test
+        hlt
         ";
         let program = asm.assemble(test_string);
-        assert_eq!(program.is_ok(), false);
+        assert!(program.is_ok());
+        let unwrapped = program.unwrap();
+        assert_eq!(unwrapped[64], 6);
+        assert_eq!(
+            LittleEndian::read_u32(&unwrapped[PIE_HEADER_LENGTH..PIE_HEADER_LENGTH + 4]) as usize,
+            asm.ro.len()
+        );
+        // The first instruction is `load $0 #100`
+        assert_eq!(
+            &unwrapped[PIE_HEADER_LENGTH + 4 + 6..PIE_HEADER_LENGTH + 4 + 10],
+            &[0, 0, 0, 100]
+        );
     }
 
     #[test]
-    /// Tests that code which does not declare a segment first does not work
-    fn test_first_phase_no_segment() {
+    /// Simple test of data that goes into the read only section
+    fn test_ro_data_i32() {
         let mut asm = Assembler::new();
-        let test_string = "hello: .asciiz 'Fail'";
-        let result = program(CompleteStr(test_string));
-        assert_eq!(result.is_ok(), true);
-        let (_, mut p) = result.unwrap();
-        asm.process_first_phase(&mut p);
-        assert_eq!(asm.errors.len(), 0);
+        let test_string = r"
+        .data
+        test: .integer #300
+        .code
+        ";
+        let program = asm.assemble(test_string);
+        assert!(program.is_ok());
+        assert_eq!(asm.ro, vec![44, 1, 0, 0]);
     }
 
     #[test]
-    /// Tests that code inside a proper segment works
-    fn test_first_phase_inside_segment() {
+    /// Tests that `.space` reserves zeroed bytes and that labels after it land past the reserved space
+    fn test_ro_data_space() {
         let mut asm = Assembler::new();
         let test_string = r"
         .data
-        test: .asciiz 'Hello'
+        hi: .asciiz 'Hi'
+        buf: .space 16
+        after: .integer #1
+        .code
+        hlt
         ";
-        let result = program(CompleteStr(test_string));
-        assert_eq!(result.is_ok(), true);
-        let (_, mut p) = result.unwrap();
-        asm.process_first_phase(&mut p);
-        assert_eq!(asm.errors.len(), 0);
+        assert!(asm.assemble(test_string).is_ok());
+        assert_eq!(asm.ro.len(), 3 + 16 + 4);
+        assert_eq!(&asm.ro[3..19], &[0; 16]);
+        assert_eq!(asm.symbols.symbol_value("buf"), Some(3));
+        assert_eq!(asm.symbols.symbol_value("after"), Some(19));
+    }
+
+    #[test]
+    /// Tests that a `.space` needs a label and a size small enough to address
+    fn test_ro_data_space_errors() {
+        for (source, expected) in [
+            (
+                ".data\nbuf: .space 2000000000\n.code\nhlt\n",
+                AssemblerError::SpaceTooLarge {
+                    size: 2_000_000_000,
+                    max: MAX_SPACE_SIZE,
+                },
+            ),
+            (
+                ".data\n.space 16\n.code\nhlt\n",
+                AssemblerError::StringConstantDeclaredWithoutLabel { instruction: 1 },
+            ),
+            (
+                ".data\nbuf: .space 'x'\n.code\nhlt\n",
+                AssemblerError::NonOperandInOperandField,
+            ),
+        ]
+        .iter()
+        {
+            let mut asm = Assembler::new();
+            match asm.assemble(source) {
+                Err(errors) => assert_eq!(errors, vec![expected.clone()], "{}", source),
+                Ok(_) => panic!("{:?} should not assemble", source),
+            }
+        }
+    }
+
+    #[test]
+    /// Tests that `.byte` writes single bytes, from integers or one character strings, and lists of them
+    fn test_ro_data_byte() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        one: .byte #65
+        letter: .byte 'B'
+        list: .byte #1, #2,#3 , 'C'
+        .code
+        hlt
+        ";
+        assert!(asm.assemble(test_string).is_ok());
+        assert_eq!(asm.ro, vec![65, 66, 1, 2, 3, 67]);
+        assert_eq!(asm.symbols.symbol_value("letter"), Some(1));
+        assert_eq!(asm.symbols.symbol_value("list"), Some(2));
+    }
+
+    #[test]
+    /// Tests that `.byte` rejects values that don't fit in a byte
+    fn test_ro_data_byte_out_of_range() {
+        for (operand, expected) in [("#256", "256"), ("#1, 'AB'", "'AB'")].iter() {
+            let mut asm = Assembler::new();
+            let test_string = format!(".data\nbad: .byte {}\n.code\nhlt\n", operand);
+            match asm.assemble(&test_string) {
+                Err(errors) => match errors[0] {
+                    AssemblerError::ByteOutOfRange { ref value } => assert_eq!(value, expected),
+                    ref e => panic!("Unexpected error: {:?}", e),
+                },
+                Ok(_) => panic!("{} should not assemble", operand),
+            }
+        }
+    }
+
+    #[test]
+    /// Tests that an `.integer` without a label or without an integer is an error rather than being dropped
+    fn test_ro_data_integer_errors() {
+        let mut asm = Assembler::new();
+        match asm.assemble(".data\n.integer #5\n.code\nhlt\n") {
+            Err(errors) => match errors[0] {
+                AssemblerError::StringConstantDeclaredWithoutLabel { .. } => {}
+                ref e => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(_) => panic!("An unlabeled .integer should not assemble"),
+        }
+
+        let mut asm = Assembler::new();
+        assert_eq!(
+            asm.assemble(".data\nx: .integer @foo\n.code\nhlt\n"),
+            Err(vec![AssemblerError::NonOperandInOperandField])
+        );
+    }
+
+    #[test]
+    /// Tests that a 16-bit immediate that doesn't fit is an error rather than being truncated
+    fn test_immediate_out_of_range() {
+        for (source, opcode, value) in [
+            ("jmpfi #70000", "JMPFI", 70000),
+            ("syscall #70001 $0", "SYSCALL", 70001),
+            ("lui $0 #65536", "LUI", 65536),
+        ]
+        .iter()
+        {
+            let mut asm = Assembler::new();
+            match asm.assemble(&format!(".code\n{}\nhlt\n", source)) {
+                Err(errors) => assert_eq!(
+                    errors[0],
+                    AssemblerError::ImmediateOutOfRange {
+                        opcode: opcode.to_string(),
+                        value: *value
+                    }
+                ),
+                Ok(_) => panic!("{} should not assemble", source),
+            }
+        }
+        assert!(Assembler::new()
+            .assemble(".code\njmpfi #65535\nhlt\n")
+            .is_ok());
+    }
+
+    #[test]
+    /// Tests that a label too far into the read-only data to fit in an operand is an error rather than being truncated
+    fn test_load_data_label_out_of_range() {
+        let mut asm = Assembler::new();
+        let padding = "x".repeat(65535);
+        let test_string = format!(
+            ".data\npadding: .asciiz '{}'\nfar: .integer #1\n.code\nload $0 @far\nhlt\n",
+            padding
+        );
+        match asm.assemble(&test_string) {
+            Err(errors) => match errors[0] {
+                AssemblerError::LabelOutOfRange { ref name, value } => {
+                    assert_eq!(name, "far");
+                    assert_eq!(value, 65536);
+                }
+                ref e => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(_) => panic!("Program with an out of range label should not assemble"),
+        }
+    }
+
+    #[test]
+    /// Tests that a data label can be used as a LOAD immediate to get the data's offset in the read-only data
+    fn test_load_data_label() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        hello: .asciiz 'Hi'
+        count: .integer #300
+        .code
+        load $0 @count
+        load $1 @hello
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(vm.registers[0], 3);
+        assert_eq!(vm.registers[1], 0);
+        assert_eq!(&vm.ro_data()[3..7], &[44, 1, 0, 0]);
+    }
+
+    #[test]
+    /// This tests that a section name that isn't `code` or `data` throws an error
+    fn test_bad_ro_data() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .code
+        test: .asciiz 'This is a test'
+        .wrong
+        ";
+        match asm.assemble(test_string) {
+            Err(errors) => match errors[0] {
+                AssemblerError::UnknownDirective { ref name } => assert_eq!(name, "wrong"),
+                ref e => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(_) => panic!("Program with an unknown directive should not assemble"),
+        }
+    }
+
+    #[test]
+    /// Tests that macro invocations are expanded into their bodies before the program is assembled
+    fn test_macro_expansion() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .macro inc2 $r
+        inc $r
+        inc $r
+        .endmacro
+        .code
+        load $0 #5
+        inc2 $0
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(
+            &program[PIE_HEADER_LENGTH + 4..],
+            &[0, 0, 0, 5, 18, 0, 0, 0, 18, 0, 0, 0, 5, 0, 0, 0]
+        );
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(vm.registers[0], 7);
+
+        let mut asm = Assembler::new();
+        let bad = ".macro inc2 $r\ninc $r\n.endmacro\n.code\ninc2\nhlt\n";
+        match asm.assemble(bad) {
+            Err(errors) => assert_eq!(
+                errors[0],
+                AssemblerError::MacroArgumentMismatch {
+                    name: "inc2".to_string(),
+                    expected: 1,
+                    found: 0
+                }
+            ),
+            Ok(_) => panic!("A macro invoked with too few arguments should not assemble"),
+        }
+    }
+
+    #[test]
+    /// Tests that parse errors say what went wrong and where, rather than passing on nom's error
+    fn test_structured_parse_errors() {
+        let cases = [
+            (
+                ".code\nload $0 #1\nload $0 #\nhlt\n",
+                ParseErrorKind::ExpectedOperand,
+                3,
+                "#",
+                "Expected an operand on line 3 near `#`",
+            ),
+            (
+                ".data\nhello: .asciiz 'Hello\n.code\nhlt\n",
+                ParseErrorKind::UnterminatedString,
+                2,
+                "'Hello",
+                "Unterminated string on line 2 near `'Hello`",
+            ),
+            // The invocation on line 5 expands to three lines, which doesn't move the error
+            (
+                ".macro inc3 $r\ninc $r\ninc $r\ninc $r\n.endmacro\n.code\ninc3 $0\nload $0 #\nhlt\n",
+                ParseErrorKind::ExpectedOperand,
+                8,
+                "#",
+                "Expected an operand on line 8 near `#`",
+            ),
+        ];
+        for (source, expected_kind, expected_line, expected_near, message) in cases.iter() {
+            let mut asm = Assembler::new();
+            match asm.assemble(source) {
+                Err(errors) => {
+                    match errors[0] {
+                        AssemblerError::ParseError {
+                            ref kind,
+                            line,
+                            ref near,
+                        } => {
+                            assert_eq!(kind, expected_kind);
+                            assert_eq!(line, *expected_line);
+                            assert_eq!(near, expected_near);
+                        }
+                        ref e => panic!("Unexpected error: {:?}", e),
+                    }
+                    assert_eq!(errors[0].to_string(), *message);
+                }
+                Ok(_) => panic!("{:?} should not assemble", source),
+            }
+        }
+    }
+
+    #[test]
+    /// Tests that text the parser can't make sense of is an error rather than being dropped with everything after it
+    fn test_unparsed_remainder() {
+        let mut asm = Assembler::new();
+        match asm.assemble(".code\nload $0 #1\n!!garbage\nhlt\n") {
+            Err(errors) => {
+                assert_eq!(
+                    errors[0],
+                    AssemblerError::ParseError {
+                        kind: ParseErrorKind::UnexpectedText,
+                        line: 3,
+                        near: "!!garbage".to_string()
+                    }
+                );
+                assert_eq!(
+                    errors[0].to_string(),
+                    "Expected an instruction or directive on line 3 near `!!garbage`"
+                );
+            }
+            Ok(_) => panic!("A program with trailing garbage should not assemble"),
+        }
+    }
+
+    #[test]
+    /// Tests that GETPC stores the address of its own instruction, the same offset a label on it resolves to
+    fn test_getpc_matches_label() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\nhi: .asciiz 'Hi'\n.code\nload $0 #70000\nhere: getpc $1\nhlt\n")
+            .unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(
+            vm.registers[1] as u32,
+            asm.symbols.symbol_value("here").unwrap()
+        );
+    }
+
+    #[test]
+    /// Tests that `.entry` makes execution start at its label instead of the first instruction
+    fn test_entry_point() {
+        for entry in ["main", "@main"].iter() {
+            let mut asm = Assembler::new();
+            let test_string = format!(
+                ".entry {}\n.code\ninit: load $0 #100\nhlt\nmain: load $1 #7\nhlt\n",
+                entry
+            );
+            let program = asm.assemble(&test_string).unwrap();
+            assert_eq!(
+                DataEndian::read_u32(&program[PIE_HEADER_ENTRY_OFFSET..]),
+                asm.symbols.symbol_value("main").unwrap()
+            );
+            let mut vm = VirtualMachine::new();
+            vm.add_bytes(program);
+            vm.run();
+            assert_eq!(vm.registers[0], 0);
+            assert_eq!(vm.registers[1], 7);
+        }
+    }
+
+    #[test]
+    /// Tests that `.entry` has to name one label on an instruction
+    fn test_bad_entry_point() {
+        let cases = [
+            (
+                ".entry nowhere\n.code\nhlt\n",
+                AssemblerError::UnknownLabel {
+                    name: "nowhere".to_string(),
+                },
+            ),
+            (
+                ".entry @hello\n.data\nhello: .asciiz 'Hi'\n.code\nhlt\n",
+                AssemblerError::InvalidEntryPoint {
+                    name: "hello".to_string(),
+                },
+            ),
+            (
+                ".entry a\n.entry b\n.code\na: hlt\nb: hlt\n",
+                AssemblerError::InvalidEntryPoint {
+                    name: "b".to_string(),
+                },
+            ),
+        ];
+        for (source, expected) in cases.iter() {
+            let mut asm = Assembler::new();
+            match asm.assemble(source) {
+                Err(errors) => assert_eq!(errors[0], *expected, "{}", source),
+                Ok(_) => panic!("{} should not assemble", source),
+            }
+        }
+    }
+
+    #[test]
+    /// Tests that unknown directives are rejected whether or not they have operands
+    fn test_unknown_directive() {
+        for source in [
+            ".data\n.frobnicate\n.code\nhlt\n",
+            ".data\nx: .frobnicate #1\n.code\nhlt\n",
+            ".data\n.frobnicate 'a', 'b'\n.code\nhlt\n",
+        ]
+        .iter()
+        {
+            let mut asm = Assembler::new();
+            match asm.assemble(source) {
+                Err(errors) => {
+                    assert_eq!(errors.len(), 1, "{}", source);
+                    match errors[0] {
+                        AssemblerError::UnknownDirective { ref name } => {
+                            assert_eq!(name, "frobnicate")
+                        }
+                        ref e => panic!("Unexpected error: {:?}", e),
+                    }
+                }
+                Ok(_) => panic!("{} should not assemble", source),
+            }
+        }
+    }
+
+    #[test]
+    /// Tests that a section header given operands is reported as such rather than as an unknown directive
+    fn test_section_header_with_operands() {
+        for (source, name) in [
+            (".data #1\n.code\nhlt\n", "data"),
+            (".data\n.code x\nhlt\n", "code"),
+        ]
+        .iter()
+        {
+            let mut asm = Assembler::new();
+            match asm.assemble(source) {
+                Err(errors) => assert!(
+                    errors.contains(&AssemblerError::UnexpectedDirectiveOperands {
+                        name: name.to_string()
+                    }),
+                    "{}: {:?}",
+                    source,
+                    errors
+                ),
+                Ok(_) => panic!("{} should not assemble", source),
+            }
+        }
+    }
+
+    #[test]
+    /// Tests that code which does not declare a segment first does not work
+    fn test_first_phase_no_segment() {
+        let mut asm = Assembler::new();
+        let test_string = "hello: .asciiz 'Fail'";
+        let result = program(CompleteStr(test_string));
+        assert_eq!(result.is_ok(), true);
+        let (_, mut p) = result.unwrap();
+        asm.process_first_phase(&mut p);
+        assert_eq!(asm.errors.len(), 1);
+    }
+
+    #[test]
+    /// Tests that code inside a proper segment works
+    fn test_first_phase_inside_segment() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        test: .asciiz 'Hello'
+        ";
+        let result = program(CompleteStr(test_string));
+        assert_eq!(result.is_ok(), true);
+        let (_, mut p) = result.unwrap();
+        asm.process_first_phase(&mut p);
+        assert_eq!(asm.errors.len(), 0);
+    }
+
+    #[test]
+    /// Tests that immediates too large for LOAD are split into LOAD/LUI and still produce the exact value
+    fn test_load_large_immediates() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        load $0 #70000
+        load $1 #2000000000
+        load $2 #65535
+        load $3 #100000
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        // Three LOAD/LUI pairs, a LOAD that fits on its own, and the HLT
+        assert_eq!(program.len(), PIE_HEADER_LENGTH + 4 + 32);
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(vm.registers[0], 70000);
+        assert_eq!(vm.registers[1], 2000000000);
+        assert_eq!(vm.registers[2], 65535);
+        assert_eq!(vm.registers[3], 100000);
+    }
+
+    #[test]
+    /// Tests that an integer that doesn't fit in 32 bits is reported instead of panicking
+    fn test_load_out_of_range_immediate() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".data\n.code\nload $0 #3000000000\nhlt\n");
+        match program {
+            Err(errors) => match errors[0] {
+                AssemblerError::IntegerOutOfRange { ref value } => assert_eq!(value, "3000000000"),
+                ref e => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(_) => panic!("Program with an out of range integer should not assemble"),
+        }
+    }
+
+    #[test]
+    /// Tests that `jmpe @label` loops back to the label until the comparison stops being equal
+    fn test_jmpe_loop() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        load $0 #0
+        load $1 #5
+        test: inc $0
+        neq $0 $1
+        jmpe @test
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(
+            asm.symbols.symbol_value("test"),
+            Some((PIE_HEADER_LENGTH + 4 + 8) as u32)
+        );
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(vm.registers[0], 5);
+    }
+
+    #[test]
+    /// Tests that a char literal assembles to its code point
+    fn test_char_literal() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\n.code\nload $0 'A'\nload $1 '\\n'\nhlt\n")
+            .unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(vm.registers[0], 65);
+        assert_eq!(vm.registers[1], 10);
+    }
+
+    #[test]
+    /// Tests that escapes in `.asciiz` strings are decoded before being written to ro_data
+    fn test_asciiz_escapes() {
+        let mut asm = Assembler::new();
+        asm.assemble(".data\nhello: .asciiz 'a\\nb'\nnul: .asciiz 'c\\0d'\n.code\nhlt\n")
+            .unwrap();
+        assert_eq!(asm.ro, vec![b'a', b'\n', b'b', 0, b'c', 0, b'd', 0]);
+        assert_eq!(asm.symbols.symbol_value("nul"), Some(4));
+    }
+
+    #[test]
+    /// Tests that the read-only data is written between the header and the code and that PRTS can read it in the VM
+    fn test_ro_data_loaded_into_vm() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        hello: .asciiz 'Hello'
+        .code
+        prts @hello
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(program[PIE_HEADER_LENGTH], 6);
+        assert_eq!(
+            LittleEndian::read_u32(
+                &program[PIE_HEADER_RO_LENGTH_OFFSET..PIE_HEADER_RO_LENGTH_OFFSET + 4]
+            ),
+            6
+        );
+        assert_eq!(
+            &program[PIE_HEADER_LENGTH + 4..PIE_HEADER_LENGTH + 10],
+            b"Hello\0"
+        );
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(program);
+        vm.run();
+        let events = vm.events();
+        assert_eq!(vm.ro_data(), b"Hello\0");
+        match events.last().unwrap().event {
+            VMEventType::GracefulStop { .. } => {}
+            ref e => panic!("Program should have stopped gracefully: {:?}", e),
+        }
+    }
+
+    #[test]
+    /// Tests a function that keeps its argument in a local stack slot and leaves the caller's locals alone
+    fn test_function_with_local() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .code
+        load $0 #6
+        load $5 #7
+        storel #0 $5
+        call @compute
+        loadl $4 #0
+        hlt
+        compute: storel #0 $0
+        mul $0 $0 $1
+        loadl $2 #0
+        add $1 $2 $3
+        ret
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(program);
+        let events = vm.run();
+        match events.last().unwrap().event {
+            VMEventType::GracefulStop { .. } => {}
+            ref e => panic!("Program should have stopped gracefully: {:?}", e),
+        }
+        assert_eq!(vm.registers[3], 42);
+        assert_eq!(vm.registers[4], 7);
+        assert_eq!(vm.bp, 0);
+        assert_eq!(vm.stack, vec![7]);
+    }
+
+    #[test]
+    /// Tests that `.equ` aliases stand in for registers and integers
+    fn test_equ_aliases() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .equ counter $5
+        .equ start #100
+        .code
+        load counter start
+        inc counter
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        let body = &program[program.len() - 12..];
+        assert_eq!(body, &[0, 5, 0, 100, 18, 5, 0, 0, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    /// Tests that an identifier without a matching `.equ` is an error
+    fn test_unknown_alias() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        inc counter
+        ";
+        let program = asm.assemble(test_string);
+        match program {
+            Err(errors) => match errors[0] {
+                AssemblerError::UnknownAlias { ref name } => assert_eq!(name, "counter"),
+                ref e => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(_) => panic!("Program with an unknown alias should not assemble"),
+        }
+    }
+
+    #[test]
+    /// Tests that declaring the same label twice is reported instead of silently keeping the first
+    fn test_duplicate_label() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        .code
+        test: inc $0
+        test: inc $1
+        hlt
+        ";
+        match asm.assemble(test_string) {
+            Err(errors) => match errors[0] {
+                AssemblerError::SymbolAlreadyDeclared { ref name } => assert_eq!(name, "test"),
+                ref e => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(_) => panic!("Program with a duplicate label should not assemble"),
+        }
+    }
+
+    #[test]
+    /// Tests that the assembler writes the bytecode version into the header
+    fn test_header_version() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".data\n.code\nhlt\n").unwrap();
+        assert_eq!(program[PIE_HEADER_VERSION_OFFSET], BYTECODE_VERSION);
+    }
+
+    #[test]
+    /// Tests that using a label that was never declared is an assembler error rather than exiting the process
+    fn test_unknown_label() {
+        let mut asm = Assembler::new();
+        match asm.assemble(".data\n.code\njmpe @missing\nhlt\n") {
+            Err(errors) => match errors[0] {
+                AssemblerError::UnknownLabel { ref name } => assert_eq!(name, "missing"),
+                ref e => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(_) => panic!("Program with an unknown label should not assemble"),
+        }
+    }
+
+    #[test]
+    /// Tests that the data section can come after the code section. The read-only data is always laid out first.
+    fn test_code_before_data() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .code
+        load $0 #1
+        test: prts @hello
+        jmpe @test
+        hlt
+        .data
+        hello: .asciiz 'Hello'
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        let mut reordered = Assembler::new();
+        let expected = reordered
+            .assemble(".data\nhello: .asciiz 'Hello'\n.code\nload $0 #1\ntest: prts @hello\njmpe @test\nhlt\n")
+            .unwrap();
+        assert_eq!(program, expected);
+    }
+
+    #[test]
+    /// Tests that opcodes in the data section are rejected instead of being quietly moved into the code
+    fn test_instruction_in_data_section() {
+        let mut asm = Assembler::new();
+        match asm.assemble(".code\nhlt\n.data\nload $0 #1\n") {
+            Err(errors) => match errors[0] {
+                AssemblerError::InstructionInDataSection { instruction } => {
+                    assert_eq!(instruction, 3)
+                }
+                ref e => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(_) => panic!("Program with an instruction in .data should not assemble"),
+        }
+    }
+
+    #[test]
+    /// Tests that compare-and-jump opcodes branch on labels
+    fn test_compare_jump_loop() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .code
+        load $0 #0
+        load $1 #3
+        top: inc $0
+        jlt $0 $1 @top
+        jle $0 $1 @done
+        load $0 #100
+        done: hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(vm.registers[0], 3);
+    }
+
+    #[test]
+    /// Tests that the VM reads back exactly the immediates the assembler wrote, including ones that need a LUI and
+    /// ones with a high byte distinct from the low one
+    fn test_load_round_trip() {
+        for value in [
+            0,
+            1,
+            0x12,
+            0x1234,
+            0xff00,
+            65535,
+            65536,
+            70000,
+            0x0102_0304,
+            i32::MAX,
+        ]
+        .iter()
+        {
+            let mut asm = Assembler::new();
+            let program = asm
+                .assemble(&format!(".code\nload $3 #{}\nhlt\n", value))
+                .unwrap();
+            let mut vm = VirtualMachine::new();
+            vm.add_bytes(program);
+            vm.run();
+            assert_eq!(vm.registers[3], *value, "load #{}", value);
+        }
+    }
+
+    #[test]
+    /// Tests that 16-bit operands are written high byte first and that the header's code offset is little-endian
+    fn test_operand_byte_order() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\nnum: .integer #258\n.code\nload $0 #4660\nhlt\n")
+            .unwrap();
+        assert_eq!(
+            &program[PIE_HEADER_LENGTH..PIE_HEADER_LENGTH + 4],
+            &[4, 0, 0, 0]
+        );
+        assert_eq!(
+            &program[PIE_HEADER_LENGTH + 4..PIE_HEADER_LENGTH + 8],
+            &[2, 1, 0, 0]
+        );
+        assert_eq!(
+            &program[PIE_HEADER_LENGTH + 8..PIE_HEADER_LENGTH + 12],
+            &[0, 0, 0x12, 0x34]
+        );
+    }
+
+    #[test]
+    /// Tests that comments can go on their own lines or after instructions and directives
+    fn test_comments_in_program() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        ; greeting program
+        .data ; read-only data
+        hello: .asciiz 'Hi' ; the message
+        .code
+          prts @hello ; print it
+        ; all done
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(asm.ro, vec![b'H', b'i', 0]);
+        assert_eq!(
+            &program[PIE_HEADER_LENGTH + 4 + 3..],
+            &[21, 0, 0, 0, 5, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    /// Tests that optimized bytecode is shorter but leaves the registers the same as unoptimized bytecode
+    fn test_optimization_keeps_results() {
+        let source = r"
+        .code
+        load $1 #4
+        load $2 #0
+        top: nop
+        nop
+        load $0 #0
+        add $1 $0 $0
+        add $2 $0 $2
+        dec $1
+        load $3 #0
+        eq $1 $3
+        jmpe @done
+        jlt $3 $1 @top
+        done: hlt
+        ";
+        let plain = Assembler::new().assemble(source).unwrap();
+        let mut asm = Assembler::new().with_optimization(true);
+        let optimized = asm.assemble(source).unwrap();
+        assert_eq!(optimized.len(), plain.len() - 8);
+
+        let mut expected = VirtualMachine::new();
+        expected.add_bytes(plain);
+        expected.run();
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(optimized);
+        vm.run();
+        assert_eq!(vm.registers, expected.registers);
+        assert_eq!(vm.registers[2], 10);
+    }
+
+    #[test]
+    /// Tests that folded constants give the same result as adding at runtime
+    fn test_constant_folding_keeps_result() {
+        let source = ".code\nload $0 #1200\nload $1 #34\nadd $0 $1 $5\nhlt\n";
+        let plain = Assembler::new().assemble(source).unwrap();
+        let optimized = Assembler::new()
+            .with_optimization(true)
+            .assemble(source)
+            .unwrap();
+        assert_eq!(optimized.len(), plain.len() - 8);
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(optimized);
+        vm.run();
+        assert_eq!(vm.registers[5], 1234);
+    }
+
+    #[test]
+    /// Tests that repeated sections are merged in the order they appear
+    fn test_multiple_sections() {
+        let mut asm = Assembler::new();
+        let test_string = r"
+        .data
+        first: .asciiz 'A'
+        .code
+        load $0 #1
+        .data
+        second: .asciiz 'B'
+        .code
+        test: prts @second
+        hlt
+        ";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(asm.ro, vec![b'A', 0, b'B', 0]);
+        assert_eq!(asm.symbols.symbol_value("second"), Some(2));
+        assert_eq!(
+            asm.symbols.symbol_value("test"),
+            Some((PIE_HEADER_LENGTH + 4 + 4 + 4) as u32)
+        );
+        assert_eq!(
+            &program[PIE_HEADER_LENGTH + 4..],
+            &[b'A', 0, b'B', 0, 0, 0, 0, 1, 21, 0, 2, 0, 5, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    /// Tests that labels after wider instructions account for their extra width
+    fn test_label_after_wide_instructions() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".code\nloadf $0 #2.5\nmiddle: loadw $1 #100000\nend: hlt\n")
+            .unwrap();
+        let code_start = asm.code_start();
+        assert_eq!(asm.symbols.symbol_value("middle"), Some(code_start + 10));
+        assert_eq!(asm.symbols.symbol_value("end"), Some(code_start + 16));
+        assert_eq!(program.len(), code_start as usize + 20);
+        assert_eq!(program[code_start as usize + 16], Opcode::HLT as u8);
+
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(vm.float_registers[0], 2.5);
+        assert_eq!(vm.registers[1], 100000);
+    }
+
+    #[test]
+    /// Tests that LOADF with a whole number loads it as a float
+    fn test_loadf_integer() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".code\nloadf $0 #3\nhlt\n").unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(vm.float_registers[0], 3.0);
+    }
+
+    #[test]
+    /// Tests that a program needs a code section, but not a data section
+    fn test_code_section_required() {
+        let mut asm = Assembler::new();
+        assert!(asm.assemble(".code\nhlt\n").is_ok());
+        let mut asm = Assembler::new();
+        match asm.assemble(".data\nhello: .asciiz 'Hi'\n") {
+            Err(errors) => match errors[0] {
+                AssemblerError::InsufficientSections => {}
+                ref e => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(_) => panic!("Program without a code section should not assemble"),
+        }
+    }
+
+    /// Creates an empty scratch directory for tests that need files on disk
+    fn scratch_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("iridium-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    /// Tests that `.include` splices another file's instructions in place
+    fn test_include_file() {
+        let dir = scratch_dir();
+        fs::write(dir.join("helper.iasm"), "inc $0\ninc $0\n").unwrap();
+        fs::write(
+            dir.join("main.iasm"),
+            ".data\n.code\nload $0 #1\n.include 'helper.iasm'\nhlt\n",
+        )
+        .unwrap();
+        let mut asm = Assembler::new();
+        let program = asm.assemble_file(&dir.join("main.iasm")).unwrap();
+        let body = &program[program.len() - 16..];
+        assert_eq!(body, &[0, 0, 0, 1, 18, 0, 0, 0, 18, 0, 0, 0, 5, 0, 0, 0]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    /// Tests that a symbol map written after assembly reads back with the same offsets
+    fn test_symbol_map_round_trip() {
+        let dir = scratch_dir();
+        let mut asm = Assembler::new();
+        asm.assemble(".data\nhello: .asciiz 'Hi'\n.code\nstart: load $0 #1\nend: hlt\n")
+            .unwrap();
+        let path = dir.join("program.map");
+        asm.write_symbol_map(&path).unwrap();
+
+        let map = SymbolTable::read_symbol_map(&path).unwrap();
+        assert_eq!(map.symbols.len(), 3);
+        assert_eq!(map.symbol_value("hello"), Some(0));
+        assert_eq!(map.symbol_value("start"), asm.symbols.symbol_value("start"));
+        assert_eq!(map.symbol_value("end"), asm.symbols.symbol_value("end"));
+        assert_eq!(map.symbol_type("end"), Some(SymbolType::Label));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    /// Tests that a missing symbol map is reported rather than read as empty
+    fn test_missing_symbol_map() {
+        let dir = scratch_dir();
+        match SymbolTable::read_symbol_map(&dir.join("missing.map")) {
+            Err(AssemblerError::SymbolMapError { ref path, .. }) => {
+                assert!(path.ends_with("missing.map"))
+            }
+            other => panic!("Expected a symbol map error, got {:?}", other),
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    /// Tests that two files including each other are reported instead of recursing forever
+    fn test_cyclic_include() {
+        let dir = scratch_dir();
+        fs::write(dir.join("a.iasm"), ".data\n.code\n.include 'b.iasm'\nhlt\n").unwrap();
+        fs::write(dir.join("b.iasm"), ".include 'a.iasm'\n").unwrap();
+        let mut asm = Assembler::new();
+        let errors = asm.assemble_file(&dir.join("a.iasm")).unwrap_err();
+        match errors[0] {
+            AssemblerError::CyclicInclude { ref path } => assert!(path.ends_with("a.iasm")),
+            ref e => panic!("Unexpected error: {:?}", e),
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    /// Tests that a missing include file is an error
+    fn test_missing_include() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".data\n.code\n.include 'does/not/exist.iasm'\n");
+        match program {
+            Err(errors) => match errors[0] {
+                AssemblerError::FileReadError { ref path, .. } => {
+                    assert_eq!(path, "does/not/exist.iasm")
+                }
+                ref e => panic!("Unexpected error: {:?}", e),
+            },
+            Ok(_) => panic!("Program with a missing include should not assemble"),
+        }
     }
 }
 
@@ -1,35 +1,118 @@
 use crate::assembler::label_parsers::label_usage;
 use crate::assembler::register_parsers::register;
-use nom::digit;
 use nom::types::CompleteStr;
+use nom::{alphanumeric, digit, ErrorKind};
 
 use crate::assembler::Token;
 
 named!(pub operand<CompleteStr, Token>,
     alt!(
-        integer_operand | register | irstring | label_usage
+        float_operand | integer_operand | register | char_operand | irstring | label_usage | identifier
     )
 );
 
-named!(irstring<CompleteStr, Token>,
+// Directives take strings, so a one character string such as `.asciiz 'a'` must not turn into a char literal
+named!(pub directive_operand<CompleteStr, Token>,
+    alt!(
+        irstring | operand | size_operand
+    )
+);
+
+// A bare count such as the 16 in `.space 16`. Only directives take these; instructions need the `#`.
+named!(size_operand<CompleteStr, Token>,
+    do_parse!(
+        peek!(digit) >>
+        value: return_error!(
+            ErrorKind::Custom(INTEGER_OUT_OF_RANGE),
+            map_res!(digit, |s: CompleteStr| s.parse::<i32>())
+        ) >>
+        (
+            Token::IntegerOperand{value}
+        )
+    )
+);
+
+// The escape sequences understood inside char and string literals: \n, \t, \0, \\ and \'
+named!(escaped_char<CompleteStr, char>,
+    preceded!(
+        char!('\\'),
+        alt!(
+            value!('\n', char!('n')) |
+            value!('\t', char!('t')) |
+            value!('\0', char!('0')) |
+            char!('\\') |
+            char!('\'')
+        )
+    )
+);
+
+// A single character between single quotes, such as `'a'` or `'\n'`, which assembles to its code point
+named!(char_operand<CompleteStr, Token>,
     do_parse!(
         tag!("'") >>
-        content: take_until!("'") >>
+        c: alt!(escaped_char | none_of!("'\\")) >>
         tag!("'") >>
         (
-            Token::IrString{ name: content.to_string()}
+            Token::IntegerOperand{value: c as i32}
+        )
+    )
+);
+
+/// Custom nom error code for a string with no closing quote on the line it starts on
+pub const UNTERMINATED_STRING: u32 = 2;
+
+// A quoted string. Escapes are decoded here, so an embedded `\0` ends up in ro_data as a real null and PRTS stops there.
+// Strings can't span lines; use `\n` for a newline.
+named!(irstring<CompleteStr, Token>,
+    do_parse!(
+        peek!(tag!("'")) >>
+        content: return_error!(
+            ErrorKind::Custom(UNTERMINATED_STRING),
+            delimited!(tag!("'"), many0!(alt!(escaped_char | none_of!("'\\\n"))), tag!("'"))
+        ) >>
+        (
+            Token::IrString{ name: content.into_iter().collect()}
         )
     )
 );
 
+/// Custom nom error code for an integer literal that does not fit in an i32
+pub const INTEGER_OUT_OF_RANGE: u32 = 1;
+
 named!( integer_operand<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            tag!("#") >>
-            reg_num: digit >>
-            (
-                Token::IntegerOperand{value: reg_num.parse::<i32>().unwrap()}
-            )
+    do_parse!(
+        tag!("#") >>
+        peek!(digit) >>
+        value: return_error!(
+            ErrorKind::Custom(INTEGER_OUT_OF_RANGE),
+            map_res!(digit, |s: CompleteStr| s.parse::<i32>())
+        ) >>
+        (
+            Token::IntegerOperand{value}
+        )
+    )
+);
+
+// A float immediate such as `#2.75` or `#-0.5`. The decimal point is required, so `#3` stays an integer.
+named!(float_operand<CompleteStr, Token>,
+    do_parse!(
+        tag!("#") >>
+        value: map_res!(
+            recognize!(tuple!(opt!(char!('-')), digit, char!('.'), digit)),
+            |s: CompleteStr| s.parse::<f64>()
+        ) >>
+        (
+            Token::FloatOperand{value}
+        )
+    )
+);
+
+// A bare name such as `counter`, which the assembler later resolves to whatever a `.equ` bound it to
+named!(identifier<CompleteStr, Token>,
+    do_parse!(
+        name: verify!(alphanumeric, |s: CompleteStr| s.starts_with(char::is_alphabetic)) >>
+        (
+            Token::Identifier{name: name.to_string()}
         )
     )
 );
@@ -45,6 +128,41 @@ fn parse_integer_operand() {
     // Test an invalid one (missing the #)
     let result = integer_operand(CompleteStr("10"));
     assert_eq!(result.is_ok(), false);
+
+    // Test one that is too large for an i32
+    let result = integer_operand(CompleteStr("#3000000000"));
+    match result {
+        Err(nom::Err::Failure(nom::Context::Code(rest, ErrorKind::Custom(code)))) => {
+            assert_eq!(code, INTEGER_OUT_OF_RANGE);
+            assert_eq!(rest, CompleteStr("3000000000"));
+        }
+        other => panic!("Expected an out of range failure, got {:?}", other),
+    }
+}
+
+#[test]
+fn parse_float_operand() {
+    let cases = [("#2.75", 2.75), ("#-0.5", -0.5), ("#0.0", 0.0)];
+    for (input, expected) in cases.iter() {
+        let result = float_operand(CompleteStr(input));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), Token::FloatOperand { value: *expected }))
+        );
+    }
+
+    // Without a decimal point it's an integer
+    assert!(float_operand(CompleteStr("#3")).is_err());
+    assert_eq!(
+        operand(CompleteStr("#3")),
+        Ok((CompleteStr(""), Token::IntegerOperand { value: 3 }))
+    );
+    assert_eq!(
+        operand(CompleteStr("#2.5")),
+        Ok((CompleteStr(""), Token::FloatOperand { value: 2.5 }))
+    );
+    assert!(float_operand(CompleteStr("#.5")).is_err());
+    assert!(float_operand(CompleteStr("#1.")).is_err());
 }
 
 #[test]
@@ -52,3 +170,127 @@ fn parse_string_operand() {
     let result = irstring(CompleteStr("'This is a test'"));
     assert_eq!(result.is_ok(), true);
 }
+
+#[test]
+fn parse_string_operand_escapes() {
+    let result = irstring(CompleteStr("'line\\none\\t\\'quoted\\'\\\\'"));
+    assert_eq!(
+        result,
+        Ok((
+            CompleteStr(""),
+            Token::IrString {
+                name: "line\none\t'quoted'\\".to_string()
+            }
+        ))
+    );
+
+    // A backslash that doesn't start a known escape is an error
+    let result = irstring(CompleteStr("'bad\\q'"));
+    assert!(result.is_err());
+}
+
+#[test]
+fn parse_identifier_operand() {
+    let result = identifier(CompleteStr("counter"));
+    assert!(result.is_ok());
+    let (_, value) = result.unwrap();
+    assert_eq!(
+        value,
+        Token::Identifier {
+            name: "counter".to_string()
+        }
+    );
+
+    // Identifiers have to start with a letter
+    let result = identifier(CompleteStr("1counter"));
+    assert!(result.is_err());
+}
+
+#[test]
+fn parse_char_operand() {
+    let result = char_operand(CompleteStr("'a'"));
+    assert_eq!(
+        result,
+        Ok((CompleteStr(""), Token::IntegerOperand { value: 97 }))
+    );
+
+    // Anything longer than one character is a string
+    let result = char_operand(CompleteStr("'ab'"));
+    assert!(result.is_err());
+    let result = operand(CompleteStr("'ab'"));
+    assert_eq!(
+        result,
+        Ok((
+            CompleteStr(""),
+            Token::IrString {
+                name: "ab".to_string()
+            }
+        ))
+    );
+}
+
+#[test]
+fn parse_char_operand_escapes() {
+    let cases = vec![
+        ("'\\n'", '\n'),
+        ("'\\t'", '\t'),
+        ("'\\0'", '\0'),
+        ("'\\\\'", '\\'),
+        ("'\\''", '\''),
+    ];
+    for (input, expected) in cases {
+        let result = operand(CompleteStr(input));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(""),
+                Token::IntegerOperand {
+                    value: expected as i32
+                }
+            )),
+            "parsing {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn parse_size_operand() {
+    for input in ["16", "#16"].iter() {
+        assert_eq!(
+            directive_operand(CompleteStr(input)),
+            Ok((CompleteStr(""), Token::IntegerOperand { value: 16 })),
+            "parsing {}",
+            input
+        );
+    }
+    // Instructions still need the `#`
+    assert!(operand(CompleteStr("16")).is_err());
+}
+
+#[test]
+fn parse_unterminated_string() {
+    for input in ["'Hello", "'Hello\n.code'"].iter() {
+        match directive_operand(CompleteStr(input)) {
+            Err(nom::Err::Failure(nom::Context::Code(rest, ErrorKind::Custom(code)))) => {
+                assert_eq!(code, UNTERMINATED_STRING);
+                assert_eq!(rest, CompleteStr(input));
+            }
+            other => panic!("Expected an unterminated string failure, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn parse_directive_operand_prefers_strings() {
+    let result = directive_operand(CompleteStr("'a'"));
+    assert_eq!(
+        result,
+        Ok((
+            CompleteStr(""),
+            Token::IrString {
+                name: "a".to_string()
+            }
+        ))
+    );
+}
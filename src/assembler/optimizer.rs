@@ -0,0 +1,293 @@
+use crate::assembler::instruction_parsers::{AssemblerInstruction, MAX_LOAD_IMMEDIATE};
+use crate::assembler::program_parsers::Program;
+use crate::assembler::Token;
+use crate::instruction::Opcode;
+
+/// Shortens redundant instruction sequences. This changes the offsets of the instructions after each change, so it
+/// has to run before code labels are resolved, and it breaks programs that jump by raw offsets instead of labels.
+/// Returns how many instructions were removed.
+pub fn peephole(p: &mut Program) -> usize {
+    let before = p.instructions.len();
+    let mut optimized: Vec<AssemblerInstruction> = Vec::with_capacity(before);
+    for i in p.instructions.drain(..) {
+        if let Some(previous) = optimized.last_mut() {
+            // A label on the second instruction means something can jump between the two, so leave them alone
+            if i.label.is_none() {
+                if is_opcode(previous, Opcode::NOP) && is_opcode(&i, Opcode::NOP) {
+                    continue;
+                }
+                if let Some(merged) = merge_zero_add(previous, &i) {
+                    *previous = merged;
+                    continue;
+                }
+            }
+        }
+        optimized.push(i);
+    }
+    p.instructions = optimized;
+    before - p.instructions.len()
+}
+
+/// Folds `load $a #x; load $b #y; add $a $b $c` into `load $c #(x+y)` when nothing else in the program uses `$a` or
+/// `$b`, other than `$c` itself. Those registers are left unset, so only their final values change. Returns how many
+/// instructions were removed.
+pub fn fold_constants(p: &mut Program) -> usize {
+    let before = p.instructions.len();
+    let mut idx = 0;
+    while idx + 2 < p.instructions.len() {
+        match fold_loads_and_add(&p.instructions, idx) {
+            Some(folded) => {
+                p.instructions.splice(idx..idx + 3, vec![folded]);
+            }
+            None => idx += 1,
+        }
+    }
+    before - p.instructions.len()
+}
+
+fn fold_loads_and_add(
+    instructions: &[AssemblerInstruction],
+    idx: usize,
+) -> Option<AssemblerInstruction> {
+    let (first, second, add) = (
+        &instructions[idx],
+        &instructions[idx + 1],
+        &instructions[idx + 2],
+    );
+    if !is_opcode(first, Opcode::LOAD)
+        || !is_opcode(second, Opcode::LOAD)
+        || !is_opcode(add, Opcode::ADD)
+        || second.label.is_some()
+        || add.label.is_some()
+    {
+        return None;
+    }
+    let (a, x) = load_parts(first)?;
+    let (b, y) = load_parts(second)?;
+    let c = add.operand_three.clone()?;
+    let reads_loaded = (add.operand_one.as_ref() == Some(&a)
+        && add.operand_two.as_ref() == Some(&b))
+        || (add.operand_one.as_ref() == Some(&b) && add.operand_two.as_ref() == Some(&a));
+    // Sums too big for one LOAD would need splitting, which has already happened by now
+    if a == b || !reads_loaded || x + y > MAX_LOAD_IMMEDIATE {
+        return None;
+    }
+    for register in [&a, &b] {
+        if *register != c && is_used_elsewhere(instructions, idx, register) {
+            return None;
+        }
+    }
+    Some(AssemblerInstruction {
+        opcode: first.opcode.clone(),
+        label: first.label.clone(),
+        directive: None,
+        operand_one: Some(c),
+        operand_two: Some(Token::IntegerOperand { value: x + y }),
+        operand_three: None,
+    })
+}
+
+/// The register and immediate of a `load $r #value`
+fn load_parts(i: &AssemblerInstruction) -> Option<(Token, i32)> {
+    match (&i.operand_one, &i.operand_two) {
+        (Some(register @ Token::Register { .. }), Some(Token::IntegerOperand { value })) => {
+            Some((register.clone(), *value))
+        }
+        _ => None,
+    }
+}
+
+/// Whether any instruction outside the three starting at `idx` has `register` as an operand
+fn is_used_elsewhere(instructions: &[AssemblerInstruction], idx: usize, register: &Token) -> bool {
+    instructions.iter().enumerate().any(|(other, i)| {
+        !(idx..idx + 3).contains(&other)
+            && [&i.operand_one, &i.operand_two, &i.operand_three]
+                .iter()
+                .any(|operand| operand.as_ref() == Some(register))
+    })
+}
+
+fn is_opcode(i: &AssemblerInstruction, opcode: Opcode) -> bool {
+    i.opcode == Some(Token::Op { code: opcode })
+}
+
+/// `load $r #0` followed by an `add` that reads `$r` and writes back to it is a copy of the add's other operand into
+/// `$r`. Adding `$r` to itself leaves the zero, so the add can just go.
+fn merge_zero_add(
+    load: &AssemblerInstruction,
+    add: &AssemblerInstruction,
+) -> Option<AssemblerInstruction> {
+    if !is_opcode(load, Opcode::LOAD)
+        || !is_opcode(add, Opcode::ADD)
+        || load.operand_two != Some(Token::IntegerOperand { value: 0 })
+    {
+        return None;
+    }
+    let zeroed = load.operand_one.clone()?;
+    if add.operand_three.as_ref() != Some(&zeroed) {
+        return None;
+    }
+    let other = if add.operand_one.as_ref() == Some(&zeroed) {
+        add.operand_two.clone()
+    } else if add.operand_two.as_ref() == Some(&zeroed) {
+        add.operand_one.clone()
+    } else {
+        return None;
+    };
+    if other.as_ref() == Some(&zeroed) {
+        return Some(AssemblerInstruction {
+            opcode: load.opcode.clone(),
+            label: load.label.clone(),
+            directive: None,
+            operand_one: load.operand_one.clone(),
+            operand_two: load.operand_two.clone(),
+            operand_three: None,
+        });
+    }
+    Some(AssemblerInstruction {
+        opcode: Some(Token::Op { code: Opcode::MOV }),
+        label: load.label.clone(),
+        directive: None,
+        operand_one: Some(zeroed),
+        operand_two: other,
+        operand_three: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::program_parsers::program;
+    use nom::types::CompleteStr;
+
+    fn optimize(source: &str) -> (usize, Program) {
+        let (_, mut p) = program(CompleteStr(source)).unwrap();
+        let removed = peephole(&mut p);
+        (removed, p)
+    }
+
+    #[test]
+    fn test_collapse_nops() {
+        let (removed, p) = optimize("nop\nnop\nnop\nload $0 #1\nnop\nhlt\n");
+        assert_eq!(removed, 2);
+        let opcodes: Vec<_> = p.instructions.iter().map(|i| i.opcode.clone()).collect();
+        assert_eq!(
+            opcodes,
+            vec![
+                Some(Token::Op { code: Opcode::NOP }),
+                Some(Token::Op { code: Opcode::LOAD }),
+                Some(Token::Op { code: Opcode::NOP }),
+                Some(Token::Op { code: Opcode::HLT }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_zero_add() {
+        let (removed, p) = optimize("load $0 #0\nadd $0 $3 $0\nhlt\n");
+        assert_eq!(removed, 1);
+        assert_eq!(
+            p.instructions[0].opcode,
+            Some(Token::Op { code: Opcode::MOV })
+        );
+        assert_eq!(
+            p.instructions[0].operand_one,
+            Some(Token::Register { reg_num: 0 })
+        );
+        assert_eq!(
+            p.instructions[0].operand_two,
+            Some(Token::Register { reg_num: 3 })
+        );
+
+        let (removed, p) = optimize("load $2 #0\nadd $2 $2 $2\nhlt\n");
+        assert_eq!(removed, 1);
+        assert_eq!(
+            p.instructions[0].opcode,
+            Some(Token::Op { code: Opcode::LOAD })
+        );
+    }
+
+    fn fold(source: &str) -> (usize, Program) {
+        let (_, mut p) = program(CompleteStr(source)).unwrap();
+        let removed = fold_constants(&mut p);
+        (removed, p)
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        for source in [
+            "load $0 #2\nload $1 #3\nadd $0 $1 $2\ninc $2\nhlt\n",
+            "load $0 #2\nload $1 #3\nadd $1 $0 $2\ninc $2\nhlt\n",
+            // The result can go in one of the loaded registers
+            "load $2 #2\nload $1 #3\nadd $2 $1 $2\ninc $2\nhlt\n",
+        ]
+        .iter()
+        {
+            let (removed, p) = fold(source);
+            assert_eq!(removed, 2, "{}", source);
+            assert_eq!(
+                p.instructions[0].opcode,
+                Some(Token::Op { code: Opcode::LOAD })
+            );
+            assert_eq!(
+                p.instructions[0].operand_one,
+                Some(Token::Register { reg_num: 2 })
+            );
+            assert_eq!(
+                p.instructions[0].operand_two,
+                Some(Token::IntegerOperand { value: 5 })
+            );
+        }
+    }
+
+    #[test]
+    fn test_fold_keeps_label() {
+        let (removed, p) = fold("start: load $0 #2\nload $1 #3\nadd $0 $1 $2\nhlt\n");
+        assert_eq!(removed, 2);
+        assert_eq!(
+            p.instructions[0].get_label_name(),
+            Some("start".to_string())
+        );
+    }
+
+    #[test]
+    fn test_no_fold() {
+        for source in [
+            // A loaded register is used again later
+            "load $0 #2\nload $1 #3\nadd $0 $1 $2\ninc $0\nhlt\n",
+            // ... or earlier, e.g. by a loop that comes back around
+            "top: inc $1\nload $0 #2\nload $1 #3\nadd $0 $1 $2\nhlt\n",
+            // The add doesn't read both loaded registers
+            "load $0 #2\nload $1 #3\nadd $0 $3 $2\nhlt\n",
+            // Something can jump between the instructions
+            "load $0 #2\nmid: load $1 #3\nadd $0 $1 $2\nhlt\n",
+            // The sum doesn't fit in a single LOAD
+            "load $0 #65535\nload $1 #1\nadd $0 $1 $2\nhlt\n",
+            // Both loads go to the same register
+            "load $0 #2\nload $0 #3\nadd $0 $0 $2\nhlt\n",
+        ]
+        .iter()
+        {
+            let (removed, _) = fold(source);
+            assert_eq!(removed, 0, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_leaves_other_sequences_alone() {
+        for source in [
+            // The zeroed register is still needed afterwards
+            "load $0 #0\nadd $0 $3 $1\nhlt\n",
+            // The register isn't zeroed
+            "load $0 #1\nadd $0 $3 $0\nhlt\n",
+            // Something can jump to the add or the second nop
+            "load $0 #0\ntop: add $0 $3 $0\nhlt\n",
+            "nop\nskip: nop\nhlt\n",
+        ]
+        .iter()
+        {
+            let (removed, _) = optimize(source);
+            assert_eq!(removed, 0, "{}", source);
+        }
+    }
+}
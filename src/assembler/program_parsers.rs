@@ -1,3 +1,4 @@
+use crate::assembler::assembler_errors::AssemblerError;
 use crate::assembler::directive_parsers::directive;
 use crate::assembler::instruction_parsers::{instruction, AssemblerInstruction};
 use crate::assembler::symbols::SymbolTable;
@@ -9,12 +10,34 @@ pub struct Program {
 }
 
 impl Program {
-    pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> Result<Vec<u8>, AssemblerError> {
         let mut program = vec![];
         for instruction in &self.instructions {
-            program.append(&mut instruction.to_bytes(symbols));
+            program.append(&mut instruction.to_bytes(symbols)?);
         }
-        program
+        Ok(program)
+    }
+
+    pub fn instructions(&self) -> &[AssemblerInstruction] {
+        &self.instructions
+    }
+}
+
+impl IntoIterator for Program {
+    type Item = AssemblerInstruction;
+    type IntoIter = std::vec::IntoIter<AssemblerInstruction>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.instructions.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Program {
+    type Item = &'a AssemblerInstruction;
+    type IntoIter = std::slice::Iter<'a, AssemblerInstruction>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.instructions.iter()
     }
 }
 
@@ -32,6 +55,7 @@ named!(pub program<CompleteStr, Program>,
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assembler::symbols::{Symbol, SymbolType};
 
     #[test]
     fn parse_program() {
@@ -48,10 +72,35 @@ mod tests {
         assert_eq!(result.is_ok(), true);
         let (_, program) = result.unwrap();
         let symbols = SymbolTable::new();
-        let bytecode = program.to_bytes(&symbols);
+        let bytecode = program.to_bytes(&symbols).unwrap();
         assert_eq!(bytecode.len(), 4);
     }
 
+    #[test]
+    fn test_program_to_bytes_with_labels() {
+        let (_, program) = program(CompleteStr("jmpe @test\n")).unwrap();
+        let mut symbols = SymbolTable::new();
+        match program.to_bytes(&symbols) {
+            Err(AssemblerError::UnknownLabel { ref name }) => assert_eq!(name, "test"),
+            other => panic!("Expected an unknown label error, got {:?}", other),
+        }
+        symbols.add_symbol(Symbol::new_with_offset(
+            "test".to_string(),
+            SymbolType::Label,
+            260,
+        ));
+        assert_eq!(program.to_bytes(&symbols).unwrap(), vec![22, 0, 0, 1, 4]);
+    }
+
+    #[test]
+    fn program_instructions() {
+        let (_, p) = program(CompleteStr(".code\nload $0 #1\ntest: inc $0\nhlt\n")).unwrap();
+        assert_eq!(p.instructions().len(), 4);
+        assert_eq!((&p).into_iter().filter(|i| i.is_opcode()).count(), 3);
+        let labels: Vec<String> = p.into_iter().filter_map(|i| i.get_label_name()).collect();
+        assert_eq!(labels, vec!["test"]);
+    }
+
     #[test]
     fn complete_program() {
         let test_program = CompleteStr(".data\nhello: .asciiz 'Hello everyone!'\n.code\nhlt");
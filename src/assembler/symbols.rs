@@ -1,4 +1,11 @@
-#[derive(Debug, Clone, Default)]
+use std::fs;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::assembler::assembler_errors::AssemblerError;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SymbolTable {
     pub symbols: Vec<Symbol>,
 }
@@ -12,6 +19,25 @@ impl SymbolTable {
         self.symbols.push(s);
     }
 
+    /// Adds a symbol, replacing any existing symbol with the same name. Returns true if one was replaced.
+    pub fn add_or_replace_symbol(&mut self, s: Symbol) -> bool {
+        for symbol in &mut self.symbols {
+            if symbol.name == s.name {
+                *symbol = s;
+                return true;
+            }
+        }
+        self.symbols.push(s);
+        false
+    }
+
+    /// Removes every symbol with the given name. Returns true if anything was removed.
+    pub fn remove_symbol(&mut self, name: &str) -> bool {
+        let before = self.symbols.len();
+        self.symbols.retain(|symbol| symbol.name != name);
+        self.symbols.len() != before
+    }
+
     pub fn has_symbol(&self, s: &str) -> bool {
         for symbol in &self.symbols {
             if symbol.name == s {
@@ -39,9 +65,36 @@ impl SymbolTable {
         }
         None
     }
+
+    /// Reads back a symbol map written by `Assembler::write_symbol_map`
+    pub fn read_symbol_map(path: &Path) -> Result<SymbolTable, AssemblerError> {
+        let to_error = |error: String| AssemblerError::SymbolMapError {
+            path: path.display().to_string(),
+            error,
+        };
+        let raw = fs::read_to_string(path).map_err(|e| to_error(e.to_string()))?;
+        serde_json::from_str(&raw).map_err(|e| to_error(e.to_string()))
+    }
+
+    /// The name of a symbol of the given type whose value is `offset`, if there is one
+    pub fn symbol_name(&self, offset: u32, symbol_type: SymbolType) -> Option<&str> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.offset == Some(offset) && symbol.symbol_type == symbol_type)
+            .map(|symbol| symbol.name.as_str())
+    }
+
+    pub fn symbol_type(&self, s: &str) -> Option<SymbolType> {
+        for symbol in &self.symbols {
+            if symbol.name == s {
+                return Some(symbol.symbol_type.clone());
+            }
+        }
+        None
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Symbol {
     name: String,
     offset: Option<u32>,
@@ -64,13 +117,32 @@ impl Symbol {
             symbol_type: symbol_type,
         }
     }
+
+    /// The name the symbol was declared with
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The resolved value of the symbol, or `None` if the assembler hasn't resolved it yet
+    pub fn offset(&self) -> Option<u32> {
+        self.offset
+    }
+
+    /// What kind of thing the symbol refers to
+    pub fn symbol_type(&self) -> &SymbolType {
+        &self.symbol_type
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum SymbolType {
     Label,
     Integer,
     IrString,
+    /// A `.equ` alias for a register; the offset holds the register number
+    Register,
+    /// A `.equ` alias for an integer; the offset holds the value
+    Constant,
 }
 
 #[cfg(test)]
@@ -90,4 +162,86 @@ mod tests {
         let v = sym.symbol_value("does_not_exist");
         assert_eq!(v.is_some(), false);
     }
+
+    #[test]
+    fn symbol_type_lookup() {
+        let mut sym = SymbolTable::new();
+        sym.add_symbol(Symbol::new_with_offset(
+            "counter".to_string(),
+            SymbolType::Register,
+            5,
+        ));
+        match sym.symbol_type("counter") {
+            Some(SymbolType::Register) => {}
+            other => panic!("Expected a register alias, got {:?}", other),
+        }
+        assert!(sym.symbol_type("does_not_exist").is_none());
+    }
+
+    #[test]
+    fn symbol_accessors() {
+        let symbol = Symbol::new_with_offset("hello".to_string(), SymbolType::IrString, 4);
+        assert_eq!(symbol.name(), "hello");
+        assert_eq!(symbol.offset(), Some(4));
+        match symbol.symbol_type() {
+            SymbolType::IrString => {}
+            other => panic!("Expected a string symbol, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn symbol_accessors_unresolved() {
+        let symbol = Symbol::new("start".to_string(), SymbolType::Label);
+        assert_eq!(symbol.name(), "start");
+        assert_eq!(symbol.offset(), None);
+        assert_eq!(symbol.symbol_type(), &SymbolType::Label);
+    }
+
+    #[test]
+    fn symbol_name_lookup() {
+        let mut sym = SymbolTable::new();
+        sym.add_symbol(Symbol::new_with_offset(
+            "hello".to_string(),
+            SymbolType::IrString,
+            72,
+        ));
+        sym.add_symbol(Symbol::new_with_offset(
+            "top".to_string(),
+            SymbolType::Label,
+            72,
+        ));
+        assert_eq!(sym.symbol_name(72, SymbolType::Label), Some("top"));
+        assert_eq!(sym.symbol_name(72, SymbolType::IrString), Some("hello"));
+        assert_eq!(sym.symbol_name(76, SymbolType::Label), None);
+    }
+
+    #[test]
+    fn remove_symbol() {
+        let mut sym = SymbolTable::new();
+        sym.add_symbol(Symbol::new_with_offset(
+            "test".to_string(),
+            SymbolType::Label,
+            12,
+        ));
+        assert!(sym.remove_symbol("test"));
+        assert!(!sym.has_symbol("test"));
+        assert!(!sym.remove_symbol("test"));
+    }
+
+    #[test]
+    fn redefine_symbol() {
+        let mut sym = SymbolTable::new();
+        assert!(!sym.add_or_replace_symbol(Symbol::new_with_offset(
+            "test".to_string(),
+            SymbolType::Label,
+            12,
+        )));
+        assert!(sym.add_or_replace_symbol(Symbol::new_with_offset(
+            "test".to_string(),
+            SymbolType::Label,
+            20,
+        )));
+        assert_eq!(sym.symbols.len(), 1);
+        assert_eq!(sym.symbol_value("test"), Some(20));
+    }
 }
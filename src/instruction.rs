@@ -1,104 +1,465 @@
 /**
  * HowTo: Add New Opcode
- * (1) Add new Opcode to enum;.
+ * (1) Add new Opcode to enum, using its byte value as the discriminant;
  * (2) Add new Opcode to From<u8> impl;
  * (3) Add code needed to execute Opcode to VM's `execute_instruction` fn;
  * (4) Add a test in VM
+ * (5) Add the Opcode's operand layout to the disassembler
+ * (6) Add the Opcode's mnemonic to the Display impl and `from_mnemonic`
+ * (7) If the Opcode's operands don't fit in 4 bytes, add its width to `width`
+ * (8) Add the positions of any register operands to `register_operands`
+ * (9) Add the position and size of any immediate to `immediate_operand`
  * */
 use nom::types::CompleteStr;
+use std::fmt;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+/// Where an instruction's immediate or jump target starts, and how it is encoded
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Immediate {
+    /// A 16-bit integer in the operand byte order
+    Short(usize),
+    /// A 32-bit integer in the operand byte order
+    Word(usize),
+    /// A 64-bit float in the data byte order
+    Float(usize),
+}
+
+/// The discriminants are the bytecode values, so `opcode as u8` encodes an Opcode and must agree with `From<u8>`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Opcode {
-    LOAD,
-    ADD,
-    SUB,
-    MUL,
-    DIV,
-    HLT,
-    JMP,
-    JMPF,
-    JMPB,
-    EQ,
-    NEQ,
-    GT,
-    LT,
-    GTQ,
-    LTQ,
-    JEQ,
-    JNEQ,
-    ALOC,
-    INC,
-    DEC,
-    PRTS,
-    LUI,
-    IGL,
+    LOAD = 0,
+    ADD = 1,
+    SUB = 2,
+    MUL = 3,
+    DIV = 4,
+    HLT = 5,
+    JMP = 6,
+    JMPF = 7,
+    JMPB = 8,
+    EQ = 9,
+    NEQ = 10,
+    GT = 14,
+    LT = 13,
+    GTQ = 11,
+    LTQ = 12,
+    JEQ = 15,
+    JNEQ = 16,
+    ALOC = 17,
+    INC = 18,
+    DEC = 19,
+    PRTS = 21,
+    /// Jumps to a 32-bit label if the equal flag is set
+    JMPE = 22,
+    NOP = 23,
+    MOV = 24,
+    MOVF = 25,
+    PRTSF = 26,
+    /// Jumps forward by an immediate number of bytes, counted from the start of the JMPFI instruction
+    JMPFI = 27,
+    /// Jumps backward by an immediate number of bytes, counted from the start of the JMPBI instruction
+    JMPBI = 28,
+    /// Copies the remainder of the last DIV into a register
+    MOVREM = 29,
+    /// Calls the host function numbered by the immediate, passing it the register
+    SYSCALL = 30,
+    /// Reads a line of input as an integer into a register, setting the equal flag if it parsed
+    READ = 31,
+    /// Pushes a register onto the stack
+    PUSH = 32,
+    /// Pops the top of the stack into a register
+    POP = 33,
+    /// Pushes the address of the next instruction and the frame pointer onto the stack, starts a new frame and jumps to
+    /// the immediate
+    CALL = 34,
+    /// Drops the current frame, restores the caller's frame pointer and jumps back to the address CALL saved
+    RET = 35,
+    /// Loads the stack slot at an immediate offset from the frame pointer into a register
+    LOADL = 36,
+    /// Stores a register into the stack slot at an immediate offset from the frame pointer, growing the frame if needed
+    STOREL = 37,
+    /// Prints every integer register and the equal flag, for debugging
+    DUMP = 38,
+    /// Prints every float register, for debugging
+    DUMPF = 40,
+    /// Loads a full 32-bit immediate into a register, without the LOAD/LUI pair
+    LOADW = 41,
+    /// Loads a 64-bit float immediate into a float register
+    LOADF = 42,
+    /// Jumps to a label if the first register is less than the second
+    JLT = 43,
+    /// Jumps to a label if the first register is greater than the second
+    JGT = 44,
+    /// Jumps to a label if the first register is less than or equal to the second
+    JLE = 45,
+    /// Jumps to a label if the first register is greater than or equal to the second
+    JGE = 46,
+    /// Divides like DIV, but treats both registers' bit patterns as `u32`. The quotient is stored back as the same
+    /// bit pattern, so `-2 divu 2` is `0x7fffffff` rather than `-1`.
+    DIVU = 47,
+    /// Negates a register into another; `i32::MIN` wraps around to itself
+    NEG = 48,
+    /// Stores a register's absolute value in another; `i32::MIN` stays `i32::MIN`
+    ABS = 49,
+    /// Stores the smaller of the second and third registers in the first. Unlike ADD, the destination comes first.
+    MIN = 50,
+    /// Stores the larger of the second and third registers in the first. Unlike ADD, the destination comes first.
+    MAX = 51,
+    /// Stores the second register as 4 little-endian bytes at the heap address in the first. Addresses in the
+    /// memory-mapped I/O range go to a device instead of the heap.
+    STOREM = 52,
+    /// Writes the milliseconds since the VM started running into a register
+    TIME = 53,
+    /// Writes a pseudo-random number into a register
+    RAND = 54,
+    /// Loads the 4-byte word at element `index` of the heap array starting at `base`: `loadidx $dst $base $index`
+    LOADIDX = 55,
+    /// Stores a register at element `index` of the heap array starting at `base`: `storeidx $base $index $src`
+    STOREIDX = 56,
+    /// Copies the second register into the first, like MOV, but only if the equal flag is set
+    CMOV = 57,
+    /// Writes the address of the GETPC instruction itself, counted from the start of the program, into a register
+    GETPC = 58,
+    /// Loads the byte at the heap address in the second register into the first, zero-extended
+    LOADB = 59,
+    /// Stores the low byte of the second register at the heap address in the first. Like STOREM, addresses in the
+    /// memory-mapped I/O range go to a device instead of the heap.
+    STOREB = 60,
+    /// Copies bytes within the heap, like `memmove`: `blkcpy $dst $src $len`. The ranges can overlap.
+    BLKCPY = 61,
+    LUI = 39,
+    IGL = 255,
 }
 
 impl From<u8> for Opcode {
     fn from(v: u8) -> Self {
+        Opcode::from_byte(v)
+    }
+}
+
+impl Opcode {
+    /// The same as `Opcode::from`, but usable in constants such as the VM's dispatch table
+    pub const fn from_byte(v: u8) -> Opcode {
         match v {
-            0 => return Opcode::LOAD,
-            1 => return Opcode::ADD,
-            2 => return Opcode::SUB,
-            3 => return Opcode::MUL,
-            4 => return Opcode::DIV,
-            5 => return Opcode::HLT,
-            6 => return Opcode::JMP,
-            7 => return Opcode::JMPF,
-            8 => return Opcode::JMPB,
-            9 => return Opcode::EQ,
-            10 => return Opcode::NEQ,
-            11 => return Opcode::GTQ,
-            12 => return Opcode::LTQ,
-            13 => return Opcode::LT,
-            14 => return Opcode::GT,
-            15 => return Opcode::JEQ,
-            16 => return Opcode::JNEQ,
-            17 => return Opcode::ALOC,
-            18 => return Opcode::INC,
-            19 => return Opcode::DEC,
-            21 => return Opcode::PRTS,
-            39 => return Opcode::LUI,
+            0 => Opcode::LOAD,
+            1 => Opcode::ADD,
+            2 => Opcode::SUB,
+            3 => Opcode::MUL,
+            4 => Opcode::DIV,
+            5 => Opcode::HLT,
+            6 => Opcode::JMP,
+            7 => Opcode::JMPF,
+            8 => Opcode::JMPB,
+            9 => Opcode::EQ,
+            10 => Opcode::NEQ,
+            11 => Opcode::GTQ,
+            12 => Opcode::LTQ,
+            13 => Opcode::LT,
+            14 => Opcode::GT,
+            15 => Opcode::JEQ,
+            16 => Opcode::JNEQ,
+            17 => Opcode::ALOC,
+            18 => Opcode::INC,
+            19 => Opcode::DEC,
+            21 => Opcode::PRTS,
+            22 => Opcode::JMPE,
+            23 => Opcode::NOP,
+            24 => Opcode::MOV,
+            25 => Opcode::MOVF,
+            26 => Opcode::PRTSF,
+            27 => Opcode::JMPFI,
+            28 => Opcode::JMPBI,
+            29 => Opcode::MOVREM,
+            30 => Opcode::SYSCALL,
+            31 => Opcode::READ,
+            32 => Opcode::PUSH,
+            33 => Opcode::POP,
+            34 => Opcode::CALL,
+            35 => Opcode::RET,
+            36 => Opcode::LOADL,
+            37 => Opcode::STOREL,
+            38 => Opcode::DUMP,
+            40 => Opcode::DUMPF,
+            41 => Opcode::LOADW,
+            42 => Opcode::LOADF,
+            43 => Opcode::JLT,
+            44 => Opcode::JGT,
+            45 => Opcode::JLE,
+            46 => Opcode::JGE,
+            47 => Opcode::DIVU,
+            48 => Opcode::NEG,
+            49 => Opcode::ABS,
+            50 => Opcode::MIN,
+            51 => Opcode::MAX,
+            52 => Opcode::STOREM,
+            53 => Opcode::TIME,
+            54 => Opcode::RAND,
+            55 => Opcode::LOADIDX,
+            56 => Opcode::STOREIDX,
+            57 => Opcode::CMOV,
+            58 => Opcode::GETPC,
+            59 => Opcode::LOADB,
+            60 => Opcode::STOREB,
+            61 => Opcode::BLKCPY,
+            39 => Opcode::LUI,
 
             // If the VirtualMachine ever encounters a number we didn't
             // plan to be an Opcode, we return the ILG opcode allowing
             // the VirtualMachine to stop with an error.
-            _ => return Opcode::IGL,
+            _ => Opcode::IGL,
         }
     }
 }
 
-impl<'a> From<CompleteStr<'a>> for Opcode {
-    fn from(v: CompleteStr<'a>) -> Self {
-        match v {
-            CompleteStr("load") => Opcode::LOAD,
-            CompleteStr("add") => Opcode::ADD,
-            CompleteStr("sub") => Opcode::SUB,
-            CompleteStr("mul") => Opcode::MUL,
-            CompleteStr("div") => Opcode::DIV,
-            CompleteStr("hlt") => Opcode::HLT,
-            CompleteStr("jmp") => Opcode::JMP,
-            CompleteStr("jmpf") => Opcode::JMPF,
-            CompleteStr("jmpb") => Opcode::JMPB,
-            CompleteStr("eq") => Opcode::EQ,
-            CompleteStr("neq") => Opcode::NEQ,
-            CompleteStr("gte") => Opcode::GTQ,
-            CompleteStr("gt") => Opcode::GT,
-            CompleteStr("lte") => Opcode::LTQ,
-            CompleteStr("lt") => Opcode::LT,
-            CompleteStr("jmpe") => Opcode::JEQ,
-            CompleteStr("jmpne") => Opcode::JNEQ,
-            CompleteStr("aloc") => Opcode::ALOC,
-            CompleteStr("inc") => Opcode::INC,
-            CompleteStr("dec") => Opcode::DEC,
-            CompleteStr("prts") => Opcode::PRTS,
-            CompleteStr("lui") => Opcode::LUI,
+/// Displays the mnemonic the assembler knows the opcode by, in upper case
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mnemonic = match self {
+            Opcode::LOAD => "LOAD",
+            Opcode::ADD => "ADD",
+            Opcode::SUB => "SUB",
+            Opcode::MUL => "MUL",
+            Opcode::DIV => "DIV",
+            Opcode::HLT => "HLT",
+            Opcode::JMP => "JMP",
+            Opcode::JMPF => "JMPF",
+            Opcode::JMPB => "JMPB",
+            Opcode::EQ => "EQ",
+            Opcode::NEQ => "NEQ",
+            Opcode::GT => "GT",
+            Opcode::LT => "LT",
+            Opcode::GTQ => "GTE",
+            Opcode::LTQ => "LTE",
+            Opcode::JEQ => "JEQ",
+            Opcode::JNEQ => "JMPNE",
+            Opcode::ALOC => "ALOC",
+            Opcode::INC => "INC",
+            Opcode::DEC => "DEC",
+            Opcode::PRTS => "PRTS",
+            Opcode::JMPE => "JMPE",
+            Opcode::NOP => "NOP",
+            Opcode::MOV => "MOV",
+            Opcode::MOVF => "MOVF",
+            Opcode::PRTSF => "PRTSF",
+            Opcode::JMPFI => "JMPFI",
+            Opcode::JMPBI => "JMPBI",
+            Opcode::MOVREM => "MOVREM",
+            Opcode::SYSCALL => "SYSCALL",
+            Opcode::READ => "READ",
+            Opcode::PUSH => "PUSH",
+            Opcode::POP => "POP",
+            Opcode::CALL => "CALL",
+            Opcode::RET => "RET",
+            Opcode::LOADL => "LOADL",
+            Opcode::STOREL => "STOREL",
+            Opcode::DUMP => "DUMP",
+            Opcode::DUMPF => "DUMPF",
+            Opcode::LOADW => "LOADW",
+            Opcode::LOADF => "LOADF",
+            Opcode::JLT => "JLT",
+            Opcode::JGT => "JGT",
+            Opcode::JLE => "JLE",
+            Opcode::JGE => "JGE",
+            Opcode::DIVU => "DIVU",
+            Opcode::NEG => "NEG",
+            Opcode::ABS => "ABS",
+            Opcode::MIN => "MIN",
+            Opcode::MAX => "MAX",
+            Opcode::STOREM => "STOREM",
+            Opcode::TIME => "TIME",
+            Opcode::RAND => "RAND",
+            Opcode::LOADIDX => "LOADIDX",
+            Opcode::STOREIDX => "STOREIDX",
+            Opcode::CMOV => "CMOV",
+            Opcode::GETPC => "GETPC",
+            Opcode::LOADB => "LOADB",
+            Opcode::STOREB => "STOREB",
+            Opcode::BLKCPY => "BLKCPY",
+            Opcode::LUI => "LUI",
+            Opcode::IGL => "IGL",
+        };
+        f.pad(mnemonic)
+    }
+}
 
-            _ => Opcode::IGL,
+impl Opcode {
+    /// How many bytes an instruction with this opcode takes up, opcode included. Most instructions fit in 4 bytes;
+    /// the few with wider immediates are longer, and the VM and disassembler use this to find the next instruction.
+    pub const fn width(self) -> usize {
+        match self {
+            // Opcode, register, 32-bit immediate
+            Opcode::LOADW => 6,
+            // Opcode, register, 64-bit float
+            Opcode::LOADF => 10,
+            // Opcode, 32-bit target
+            Opcode::JMPE => 5,
+            // Opcode, two registers, 16-bit target
+            Opcode::JLT | Opcode::JGT | Opcode::JLE | Opcode::JGE => 5,
+            _ => 4,
+        }
+    }
+
+    /// The byte positions within the instruction that hold register numbers, integer or float
+    pub const fn register_operands(self) -> &'static [usize] {
+        match self {
+            Opcode::ADD
+            | Opcode::SUB
+            | Opcode::MUL
+            | Opcode::DIV
+            | Opcode::DIVU
+            | Opcode::MIN
+            | Opcode::MAX
+            | Opcode::LOADIDX
+            | Opcode::STOREIDX
+            | Opcode::BLKCPY => &[1, 2, 3],
+            Opcode::EQ
+            | Opcode::NEQ
+            | Opcode::GT
+            | Opcode::LT
+            | Opcode::GTQ
+            | Opcode::LTQ
+            | Opcode::MOV
+            | Opcode::CMOV
+            | Opcode::MOVF
+            | Opcode::NEG
+            | Opcode::ABS
+            | Opcode::STOREM
+            | Opcode::LOADB
+            | Opcode::STOREB
+            | Opcode::JLT
+            | Opcode::JGT
+            | Opcode::JLE
+            | Opcode::JGE => &[1, 2],
+            Opcode::LOAD
+            | Opcode::LUI
+            | Opcode::LOADL
+            | Opcode::LOADW
+            | Opcode::LOADF
+            | Opcode::JMP
+            | Opcode::JMPF
+            | Opcode::JMPB
+            | Opcode::JEQ
+            | Opcode::JNEQ
+            | Opcode::ALOC
+            | Opcode::INC
+            | Opcode::DEC
+            | Opcode::PRTSF
+            | Opcode::MOVREM
+            | Opcode::READ
+            | Opcode::PUSH
+            | Opcode::POP
+            | Opcode::TIME
+            | Opcode::RAND
+            | Opcode::GETPC => &[1],
+            Opcode::SYSCALL | Opcode::STOREL => &[3],
+            Opcode::HLT
+            | Opcode::NOP
+            | Opcode::RET
+            | Opcode::DUMP
+            | Opcode::DUMPF
+            | Opcode::PRTS
+            | Opcode::JMPE
+            | Opcode::JMPFI
+            | Opcode::JMPBI
+            | Opcode::CALL
+            | Opcode::IGL => &[],
+        }
+    }
+
+    /// The immediate or jump target within the instruction, if it has one
+    pub const fn immediate_operand(self) -> Option<Immediate> {
+        match self {
+            Opcode::LOAD | Opcode::LUI | Opcode::LOADL => Some(Immediate::Short(2)),
+            Opcode::SYSCALL
+            | Opcode::STOREL
+            | Opcode::PRTS
+            | Opcode::CALL
+            | Opcode::JMPFI
+            | Opcode::JMPBI => Some(Immediate::Short(1)),
+            Opcode::JLT | Opcode::JGT | Opcode::JLE | Opcode::JGE => Some(Immediate::Short(3)),
+            Opcode::LOADW => Some(Immediate::Word(2)),
+            Opcode::JMPE => Some(Immediate::Word(1)),
+            Opcode::LOADF => Some(Immediate::Float(2)),
+            _ => None,
+        }
+    }
+
+    /// Looks up an opcode by the lowercase mnemonic the assembler knows it by. Returns `None` for an unknown
+    /// mnemonic, including one in another case, which leaves words like `LOAD` free for macro names; the assembler
+    /// turns those into IGL.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        match mnemonic {
+            "load" => Some(Opcode::LOAD),
+            "add" => Some(Opcode::ADD),
+            "sub" => Some(Opcode::SUB),
+            "mul" => Some(Opcode::MUL),
+            "div" => Some(Opcode::DIV),
+            "hlt" => Some(Opcode::HLT),
+            "jmp" => Some(Opcode::JMP),
+            "jmpf" => Some(Opcode::JMPF),
+            "jmpb" => Some(Opcode::JMPB),
+            "eq" => Some(Opcode::EQ),
+            "neq" => Some(Opcode::NEQ),
+            "gte" => Some(Opcode::GTQ),
+            "gt" => Some(Opcode::GT),
+            "lte" => Some(Opcode::LTQ),
+            "lt" => Some(Opcode::LT),
+            "jeq" => Some(Opcode::JEQ),
+            "jmpe" => Some(Opcode::JMPE),
+            "jmpne" => Some(Opcode::JNEQ),
+            "aloc" => Some(Opcode::ALOC),
+            "inc" => Some(Opcode::INC),
+            "dec" => Some(Opcode::DEC),
+            "prts" => Some(Opcode::PRTS),
+            "prtsf" => Some(Opcode::PRTSF),
+            "lui" => Some(Opcode::LUI),
+            "nop" => Some(Opcode::NOP),
+            "mov" => Some(Opcode::MOV),
+            "movf" => Some(Opcode::MOVF),
+            "jmpfi" => Some(Opcode::JMPFI),
+            "jmpbi" => Some(Opcode::JMPBI),
+            "movrem" => Some(Opcode::MOVREM),
+            "syscall" => Some(Opcode::SYSCALL),
+            "read" => Some(Opcode::READ),
+            "push" => Some(Opcode::PUSH),
+            "pop" => Some(Opcode::POP),
+            "call" => Some(Opcode::CALL),
+            "ret" => Some(Opcode::RET),
+            "loadl" => Some(Opcode::LOADL),
+            "storel" => Some(Opcode::STOREL),
+            "dump" => Some(Opcode::DUMP),
+            "dumpf" => Some(Opcode::DUMPF),
+            "loadw" => Some(Opcode::LOADW),
+            "loadf" => Some(Opcode::LOADF),
+            "jlt" => Some(Opcode::JLT),
+            "jgt" => Some(Opcode::JGT),
+            "jle" => Some(Opcode::JLE),
+            "jge" => Some(Opcode::JGE),
+            "divu" => Some(Opcode::DIVU),
+            "neg" => Some(Opcode::NEG),
+            "abs" => Some(Opcode::ABS),
+            "min" => Some(Opcode::MIN),
+            "max" => Some(Opcode::MAX),
+            "storem" => Some(Opcode::STOREM),
+            "time" => Some(Opcode::TIME),
+            "rand" => Some(Opcode::RAND),
+            "loadidx" => Some(Opcode::LOADIDX),
+            "storeidx" => Some(Opcode::STOREIDX),
+            "cmov" => Some(Opcode::CMOV),
+            "getpc" => Some(Opcode::GETPC),
+            "loadb" => Some(Opcode::LOADB),
+            "storeb" => Some(Opcode::STOREB),
+            "blkcpy" => Some(Opcode::BLKCPY),
+            _ => None,
         }
     }
 }
 
+impl<'a> From<CompleteStr<'a>> for Opcode {
+    fn from(v: CompleteStr<'a>) -> Self {
+        Opcode::from_mnemonic(&v).unwrap_or(Opcode::IGL)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Instruction {
     opcode: Opcode,
@@ -135,9 +496,159 @@ mod tests {
         assert_eq!(opcode, Opcode::IGL);
     }
 
+    #[test]
+    fn test_from_mnemonic() {
+        assert_eq!(Opcode::from_mnemonic("load"), Some(Opcode::LOAD));
+        assert_eq!(Opcode::from_mnemonic("jmpne"), Some(Opcode::JNEQ));
+        assert_eq!(Opcode::from_mnemonic("prtsf"), Some(Opcode::PRTSF));
+        assert_eq!(Opcode::from_mnemonic("PRTSF"), None);
+        assert_eq!(Opcode::from_mnemonic("Load"), None);
+        assert_eq!(Opcode::from_mnemonic("frobnicate"), None);
+        assert_eq!(Opcode::from_mnemonic("igl"), None);
+    }
+
+    #[test]
+    fn test_display_round_trips_through_from_mnemonic() {
+        for byte in 0..=255u8 {
+            let opcode = Opcode::from(byte);
+            if opcode != Opcode::IGL {
+                assert_eq!(
+                    Opcode::from_mnemonic(&opcode.to_string().to_lowercase()),
+                    Some(opcode)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_opcode_width() {
+        assert_eq!(Opcode::LOAD.width(), 4);
+        assert_eq!(Opcode::HLT.width(), 4);
+        assert_eq!(Opcode::IGL.width(), 4);
+        assert_eq!(Opcode::LOADW.width(), 6);
+        assert_eq!(Opcode::LOADF.width(), 10);
+        assert_eq!(Opcode::JLT.width(), 5);
+        assert_eq!(Opcode::JGE.width(), 5);
+    }
+
+    #[test]
+    fn test_register_operands() {
+        assert_eq!(Opcode::ADD.register_operands(), &[1, 2, 3]);
+        assert_eq!(Opcode::JLT.register_operands(), &[1, 2]);
+        assert_eq!(Opcode::SYSCALL.register_operands(), &[3]);
+        assert!(Opcode::HLT.register_operands().is_empty());
+    }
+
+    #[test]
+    fn test_immediate_operand() {
+        assert_eq!(Opcode::LOAD.immediate_operand(), Some(Immediate::Short(2)));
+        assert_eq!(
+            Opcode::SYSCALL.immediate_operand(),
+            Some(Immediate::Short(1))
+        );
+        assert_eq!(Opcode::JGE.immediate_operand(), Some(Immediate::Short(3)));
+        assert_eq!(Opcode::JMPE.immediate_operand(), Some(Immediate::Word(1)));
+        assert_eq!(Opcode::LOADF.immediate_operand(), Some(Immediate::Float(2)));
+        assert_eq!(Opcode::ADD.immediate_operand(), None);
+    }
+
+    #[test]
+    fn test_immediates_fit_in_width() {
+        for byte in 0..=255u8 {
+            let opcode = Opcode::from(byte);
+            let end = match opcode.immediate_operand() {
+                Some(Immediate::Short(start)) => start + 2,
+                Some(Immediate::Word(start)) => start + 4,
+                Some(Immediate::Float(start)) => start + 8,
+                None => continue,
+            };
+            assert!(end <= opcode.width(), "{}", opcode);
+        }
+    }
+
     #[test]
     fn test_int_to_opcode() {
         let opcode = Opcode::from(39);
         assert_eq!(opcode, Opcode::LUI);
     }
+
+    #[test]
+    fn test_opcode_display() {
+        assert_eq!(format!("{}", Opcode::LOAD), "LOAD");
+        assert_eq!(format!("{}", Opcode::HLT), "HLT");
+        assert_eq!(format!("{}", Opcode::PRTSF), "PRTSF");
+        assert_eq!(format!("{}", Opcode::GTQ), "GTE");
+        assert_eq!(format!("{}", Opcode::JNEQ), "JMPNE");
+    }
+
+    #[test]
+    fn test_opcode_to_int_round_trip() {
+        let opcodes = [
+            Opcode::LOAD,
+            Opcode::ADD,
+            Opcode::SUB,
+            Opcode::MUL,
+            Opcode::DIV,
+            Opcode::HLT,
+            Opcode::JMP,
+            Opcode::JMPF,
+            Opcode::JMPB,
+            Opcode::EQ,
+            Opcode::NEQ,
+            Opcode::GT,
+            Opcode::LT,
+            Opcode::GTQ,
+            Opcode::LTQ,
+            Opcode::JEQ,
+            Opcode::JNEQ,
+            Opcode::ALOC,
+            Opcode::INC,
+            Opcode::DEC,
+            Opcode::PRTS,
+            Opcode::JMPE,
+            Opcode::NOP,
+            Opcode::MOV,
+            Opcode::MOVF,
+            Opcode::PRTSF,
+            Opcode::JMPFI,
+            Opcode::JMPBI,
+            Opcode::MOVREM,
+            Opcode::SYSCALL,
+            Opcode::READ,
+            Opcode::PUSH,
+            Opcode::POP,
+            Opcode::CALL,
+            Opcode::RET,
+            Opcode::LOADL,
+            Opcode::STOREL,
+            Opcode::DUMP,
+            Opcode::DUMPF,
+            Opcode::LOADW,
+            Opcode::LOADF,
+            Opcode::JLT,
+            Opcode::JGT,
+            Opcode::JLE,
+            Opcode::JGE,
+            Opcode::DIVU,
+            Opcode::NEG,
+            Opcode::ABS,
+            Opcode::MIN,
+            Opcode::MAX,
+            Opcode::STOREM,
+            Opcode::TIME,
+            Opcode::RAND,
+            Opcode::LOADIDX,
+            Opcode::STOREIDX,
+            Opcode::CMOV,
+            Opcode::GETPC,
+            Opcode::LOADB,
+            Opcode::STOREB,
+            Opcode::BLKCPY,
+            Opcode::LUI,
+            Opcode::IGL,
+        ];
+        for opcode in opcodes.iter() {
+            assert_eq!(Opcode::from(*opcode as u8), *opcode);
+        }
+    }
 }
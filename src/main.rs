@@ -1,5 +1,4 @@
-use std::fs::File;
-use std::io::prelude::*;
+use std::fs;
 use std::path::Path;
 
 #[macro_use]
@@ -16,13 +15,17 @@ extern crate serde_derive;
 
 use clap::App;
 
+use crate::assembler::disassembler::{disassemble, disassemble_with_symbols, read_only_data};
+use crate::assembler::symbols::SymbolTable;
 use crate::vm::VirtualMachine;
 
 pub mod assembler;
 pub mod instruction;
 pub mod repl;
+pub mod syscall;
 pub mod utils;
 pub mod vm;
+pub mod vm_errors;
 
 extern crate env_logger;
 
@@ -30,52 +33,149 @@ fn main() {
     env_logger::init();
     let yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(yaml).get_matches();
-    let target_file = matches.value_of("INPUT_FILE");
-    match target_file {
-        Some(filename) => {
-            let program = read_file(filename);
-            let mut asm = assembler::Assembler::new();
-            let mut vm = VirtualMachine::new();
-            let program = asm.assemble(&program);
-            match program {
-                Ok(p) => {
-                    vm.add_bytes(p);
-                    vm.run();
-                    println!("{:#?}", vm.registers);
-                    std::process::exit(0)
+    match matches.subcommand() {
+        ("assemble", Some(sub_matches)) => {
+            let input = sub_matches.value_of("INPUT_FILE").unwrap();
+            let output = sub_matches.value_of("OUTPUT_FILE").unwrap();
+            let bytecode = assemble_file(input, sub_matches.value_of("MAP_FILE"));
+            if let Err(e) = fs::write(output, bytecode) {
+                eprintln!("Unable to write bytecode to {}: {}", output, e);
+                std::process::exit(1);
+            }
+        }
+        ("run", Some(sub_matches)) => {
+            let input = sub_matches.value_of("INPUT_FILE").unwrap();
+            run_bytecode(read_bytecode(input), sub_matches);
+        }
+        _ => match matches.value_of("INPUT_FILE") {
+            None if matches.is_present("DISASSEMBLE") => {
+                let bytecode = read_bytecode(matches.value_of("DISASSEMBLE").unwrap());
+                let listing = match matches.value_of("SYMBOLS") {
+                    Some(map) => match SymbolTable::read_symbol_map(Path::new(map)) {
+                        Ok(symbols) => disassemble_with_symbols(&bytecode, &symbols),
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            std::process::exit(1);
+                        }
+                    },
+                    None => disassemble(&bytecode),
+                };
+                for line in listing {
+                    println!("{}", line);
+                }
+            }
+            Some(filename) if matches.is_present("DUMP_RODATA") => {
+                for line in utils::hex_dump(read_only_data(&assemble_file(filename, None))) {
+                    println!("{}", line);
                 }
-                Err(_e) => {}
             }
+            Some(filename) => {
+                run_bytecode(assemble_file(filename, None), &matches);
+            }
+            None => {
+                start_repl();
+            }
+        },
+    }
+}
+
+/// Assembles a source file, exiting with a non-zero code after printing the errors if it can't be assembled. If
+/// `map` is given, the symbol map is written there too.
+fn assemble_file(filename: &str, map: Option<&str>) -> Vec<u8> {
+    let mut asm = assembler::Assembler::new();
+    let bytecode = match asm.assemble_file(Path::new(filename)) {
+        Ok(bytecode) => bytecode,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            std::process::exit(1);
         }
-        None => {
-            start_repl();
+    };
+    if let Some(map) = map {
+        if let Err(e) = asm.write_symbol_map(Path::new(map)) {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
     }
+    bytecode
 }
 
-fn start_repl() {
-    let mut repl = repl::REPL::new();
-    repl.run();
+/// Reads a bytecode file, exiting with a non-zero code if it can't be read or isn't bytecode
+fn read_bytecode(filename: &str) -> Vec<u8> {
+    match fs::read(filename) {
+        Ok(bytecode) if bytecode.starts_with(&assembler::PIE_HEADER_PREFIX) => bytecode,
+        Ok(_) => {
+            eprintln!("{} is not an assembled program", filename);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Unable to read bytecode from {}: {}", filename, e);
+            std::process::exit(1);
+        }
+    }
 }
 
-fn read_file(tmp: &str) -> String {
-    let filename = Path::new(tmp);
-    match File::open(Path::new(&filename)) {
-        Ok(mut fh) => {
-            let mut contents = String::new();
-            match fh.read_to_string(&mut contents) {
-                Ok(_) => {
-                    return contents;
-                }
-                Err(e) => {
-                    println!("There was an error reading file: {:?}", e);
-                    std::process::exit(1);
-                }
+/// Parses a `--set` value such as `$0=5` into a register index below `count` and the value to put in it
+fn parse_register_assignment(assignment: &str, count: usize) -> Result<(usize, i32), String> {
+    let mut parts = assignment.splitn(2, '=');
+    let register = parts.next().unwrap_or("");
+    let value = match parts.next() {
+        Some(value) => value,
+        None => return Err(format!("{} is not of the form $REGISTER=VALUE", assignment)),
+    };
+    let index = match register.strip_prefix('$').map(str::parse::<usize>) {
+        Some(Ok(index)) if index < count => index,
+        _ => {
+            return Err(format!(
+                "{} is not a register between $0 and ${}",
+                register,
+                count - 1
+            ))
+        }
+    };
+    match value.parse::<i32>() {
+        Ok(value) => Ok((index, value)),
+        Err(_) => Err(format!("{} is not a 32-bit integer", value)),
+    }
+}
+
+/// Collects the `--set` flags for a VM with `count` registers, exiting with a non-zero code if any of them is
+/// malformed
+fn register_assignments(matches: &clap::ArgMatches, count: usize) -> Vec<(usize, i32)> {
+    let mut assignments = vec![];
+    for assignment in matches.values_of("SET").into_iter().flatten() {
+        match parse_register_assignment(assignment, count) {
+            Ok(assignment) => assignments.push(assignment),
+            Err(e) => {
+                eprintln!("Invalid --set {}: {}", assignment, e);
+                std::process::exit(1);
             }
         }
-        Err(e) => {
-            println!("File not found: {:?}", e);
-            std::process::exit(1)
+    }
+    assignments
+}
+
+/// Runs bytecode with the `--set`, `--json` and `--decode-cache` flags in `matches`, then exits
+fn run_bytecode(bytecode: Vec<u8>, matches: &clap::ArgMatches) {
+    let mut vm = VirtualMachine::new().with_decode_cache(matches.is_present("DECODE_CACHE"));
+    for (register, value) in register_assignments(matches, vm.registers.len()) {
+        vm.registers[register] = value;
+    }
+    vm.add_bytes(bytecode);
+    vm.run();
+    if matches.is_present("JSON") {
+        match serde_json::to_string_pretty(&vm.snapshot()) {
+            Ok(json) => println!("{}", json),
+            Err(e) => println!("Unable to serialize the VM's state: {}", e),
         }
+    } else {
+        println!("{:#?}", vm.registers);
     }
+    std::process::exit(0)
+}
+
+fn start_repl() {
+    let mut repl = repl::REPL::new();
+    repl.run();
 }
@@ -1,103 +1,416 @@
-use crate::assembler::Assembler;
-use crate::vm::VirtualMachine;
-use nom::types::CompleteStr;
+use crate::assembler::assembler_errors::AssemblerError;
+use crate::assembler::disassembler::{disassemble_instructions, split_instructions};
+use crate::assembler::{
+    Assembler, BYTECODE_VERSION, OLDEST_BYTECODE_VERSION, PIE_HEADER_LENGTH, PIE_HEADER_PREFIX,
+    PIE_HEADER_VERSION_OFFSET,
+};
+use crate::instruction::Opcode;
+use crate::vm::{VMEventType, VirtualMachine, DEFAULT_REGISTER_COUNT};
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
 use std;
-use std::fs::File;
+use std::env;
+use std::fs::{self, File, OpenOptions};
 use std::io::Write;
-use std::io::{self, Read};
 use std::num::ParseIntError;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use crate::assembler::program_parsers::program;
+/// Name of the file in the user's home directory that REPL history is kept in
+pub const HISTORY_FILE_NAME: &str = ".lvm_history";
 
 /// Core structure for the REPL for the Assemler
 pub struct REPL {
     command_buffer: Vec<String>,
     vm: VirtualMachine,
     asm: Assembler,
+    /// Where commands are persisted between sessions. `None` if there is no home directory to put it in.
+    history_file: Option<PathBuf>,
+    /// Reads lines from the user with line editing, and lets them scroll back through `command_buffer`
+    editor: Editor<()>,
+    /// Lines entered since `.begin`, waiting for `.end` to be assembled together. `None` outside of a block.
+    block: Option<Vec<String>>,
+    /// Registers added with `.watch`, which are reported whenever an instruction changes them
+    watched: Vec<usize>,
+    /// The registers as they were before the last instruction, to tell which watched registers it changed
+    prev_registers: Vec<i32>,
 }
 
 impl REPL {
     pub fn new() -> Self {
-        REPL {
+        let history_file = env::var_os("HOME").map(|home| Path::new(&home).join(HISTORY_FILE_NAME));
+        let mut repl = REPL {
             vm: VirtualMachine::new(),
-            command_buffer: vec![],
+            command_buffer: REPL::load_history(&history_file),
             asm: Assembler::new(),
+            history_file,
+            editor: Editor::<()>::new(),
+            block: None,
+            watched: vec![],
+            prev_registers: vec![0; DEFAULT_REGISTER_COUNT],
+        };
+        repl.sync_editor_history();
+        repl
+    }
+
+    /// Keeps history in `path` instead of the default file, loading whatever is already there
+    pub fn with_history_file(mut self, path: PathBuf) -> Self {
+        self.history_file = Some(path);
+        self.command_buffer = REPL::load_history(&self.history_file);
+        self.sync_editor_history();
+        self
+    }
+
+    /// Makes the editor's history match `command_buffer`, so the arrow keys walk through the same commands
+    fn sync_editor_history(&mut self) {
+        self.editor.clear_history();
+        for command in &self.command_buffer {
+            self.editor.add_history_entry(command.as_str());
+        }
+    }
+
+    fn load_history(history_file: &Option<PathBuf>) -> Vec<String> {
+        match history_file {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(contents) => contents.lines().map(|line| line.to_string()).collect(),
+                Err(_) => vec![],
+            },
+            None => vec![],
+        }
+    }
+
+    /// Adds a command to the history, appending it to the history file as well
+    fn record_command(&mut self, command: &str) {
+        self.command_buffer.push(command.to_string());
+        self.editor.add_history_entry(command);
+        if let Some(ref path) = self.history_file {
+            let result = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut f| writeln!(f, "{}", command));
+            if let Err(e) = result {
+                warn!("Unable to write to history file {:?}: {}", path, e);
+            }
+        }
+    }
+
+    fn clear_history(&mut self) {
+        self.command_buffer.clear();
+        self.editor.clear_history();
+        if let Some(ref path) = self.history_file {
+            if let Err(e) = File::create(path) {
+                warn!("Unable to clear history file {:?}: {}", path, e);
+            }
         }
     }
 
     pub fn run(&mut self) {
         println!("Welcome. Let's be productive!");
         loop {
-            let mut buffer = String::new();
-            let stdin = io::stdin();
-            print!(">>> ");
-            io::stdout().flush().expect("Unable to flush stdout");
-            stdin
-                .read_line(&mut buffer)
-                .expect("Unable to read line from user");
-            let buffer = buffer.trim();
-            self.command_buffer.push(buffer.to_string());
-            match buffer {
-                ".quit" => {
+            let prompt = if self.block.is_some() { "... " } else { ">>> " };
+            let buffer = match self.editor.readline(prompt) {
+                Ok(line) => line,
+                // Ctrl-C abandons the line being typed, like in a shell
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => {
                     println!("Farewell! Have a great day!");
                     std::process::exit(0);
                 }
-                ".history" => {
-                    for command in &self.command_buffer {
-                        println!("{}", command);
+                Err(e) => {
+                    println!("Unable to read line from user: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let buffer = buffer.trim();
+            self.record_command(buffer);
+            self.execute_command(buffer);
+        }
+    }
+
+    /// Carries out one line of input: a dot-command, or instructions to add to the program and execute
+    pub fn execute_command(&mut self, buffer: &str) {
+        if let Some(ref mut block) = self.block {
+            if buffer == ".end" {
+                let block = self.block.take().unwrap();
+                self.run_block(block);
+            } else {
+                block.push(buffer.to_string());
+            }
+            return;
+        }
+        match buffer {
+            ".quit" => {
+                println!("Farewell! Have a great day!");
+                std::process::exit(0);
+            }
+            ".history clear" => {
+                self.clear_history();
+                println!("History cleared");
+            }
+            ".history" => {
+                for command in &self.command_buffer {
+                    println!("{}", command);
+                }
+            }
+            ".program" => {
+                println!("Listing instructions currently in VM's program vector:");
+                for instruction in split_instructions(&self.vm.program) {
+                    println!("{:<8} {:?}", Opcode::from(instruction[0]), instruction);
+                }
+                println!("End of Program Listing");
+            }
+            ".disassemble" => {
+                println!("Disassembling instructions currently in VM's program vector:");
+                for line in disassemble_instructions(&self.vm.program) {
+                    println!("{}", line);
+                }
+                println!("End of Disassembly");
+            }
+            ".registers" => {
+                println!("Listing registers and all contents:");
+                println!("{:#?}", self.vm.registers);
+                println!("End of Register Listing")
+            }
+            ".symbols" => {
+                println!("Listing symbols the assembler knows about:");
+                for symbol in &self.asm.symbols.symbols {
+                    match symbol.offset() {
+                        Some(offset) => {
+                            println!("{} ({:?}): {}", symbol.name(), symbol.symbol_type(), offset)
+                        }
+                        None => {
+                            println!("{} ({:?}): unresolved", symbol.name(), symbol.symbol_type())
+                        }
+                    }
+                }
+                println!("End of Symbols Listing");
+            }
+            ".clear" => self.vm.program = vec![],
+            ".run" => self.run_program(),
+            ".begin" => {
+                println!("Enter a program, ending it with .end");
+                self.block = Some(vec![]);
+            }
+            ".reset" => {
+                self.vm.reset();
+                println!("VM reset. The program is still loaded.");
+            }
+            ".watch" => {
+                println!("Watching registers:");
+                for register in &self.watched {
+                    println!("${}: {}", register, self.vm.registers[*register]);
+                }
+                println!("End of Watch Listing");
+            }
+            command if command.starts_with(".watch ") => {
+                let register = command[".watch ".len()..].trim();
+                match register.strip_prefix('$').map(str::parse::<usize>) {
+                    Some(Ok(register)) if register < self.vm.registers.len() => {
+                        if !self.watched.contains(&register) {
+                            self.watched.push(register);
+                        }
+                        self.prev_registers = self.vm.registers.clone();
+                        println!("Watching ${}", register);
                     }
+                    _ => println!("{} is not a register", register),
                 }
-                ".program" => {
-                    println!("Listing instructions currently in VM's program vector:");
-                    for instruction in &self.vm.program {
-                        println!("{}", instruction);
+            }
+            command if command.starts_with(".eval ") => {
+                let source = command[".eval ".len()..].trim().trim_matches('"');
+                match self.eval(source) {
+                    Ok(vm) => {
+                        if let Some(VMEventType::Crash { code }) =
+                            vm.events().last().map(|e| &e.event)
+                        {
+                            println!("Expression crashed with code {}", code);
+                        }
+                        for (register, value) in vm.registers.iter().enumerate() {
+                            if *value != 0 {
+                                println!("${}: {}", register, value);
+                            }
+                        }
                     }
-                    println!("End of Program Listing");
-                }
-                ".registers" => {
-                    println!("Listing registers and all contents:");
-                    println!("{:#?}", self.vm.registers);
-                    println!("End of Register Listing")
-                }
-                ".clear" => self.vm.program = vec![],
-                ".load_file" => {
-                    print!("Please enter the path to the file you wish to load: ");
-                    io::stdout().flush().expect("Unable to flush stdout");
-                    let mut tmp = String::new();
-                    stdin
-                        .read_line(&mut tmp)
-                        .expect("Unable to read line from user");
-                    let tmp = tmp.trim();
-                    let filename = Path::new(&tmp);
-                    let mut f = File::open(Path::new(&filename)).expect("File not found");
-                    let mut contents = String::new();
-                    f.read_to_string(&mut contents)
-                        .expect("There was an error reading from the file");
-                    let program = match program(CompleteStr(&contents)) {
-                        Ok((_remainder, program)) => program,
-                        Err(e) => {
-                            println!("Unable to parse input: {:?}", e);
-                            continue;
+                    Err(errors) => {
+                        for error in errors {
+                            println!("Unable to assemble expression: {}", error);
                         }
-                    };
-                    self.vm
-                        .program
-                        .append(&mut program.to_bytes(&self.asm.symbols));
-                }
-                _ => {
-                    let parsed_program = program(CompleteStr(buffer));
-                    if !parsed_program.is_ok() {
-                        println!("Unable to parse input");
-                        continue;
                     }
-                    let (_, result) = parsed_program.unwrap();
-                    let bytecode = result.to_bytes(&self.asm.symbols);
-
-                    for byte in bytecode {
-                        self.vm.add_byte(byte);
+                }
+            }
+            ".load" => {
+                let tmp = match self
+                    .editor
+                    .readline("Please enter the path to the bytecode file you wish to load: ")
+                {
+                    Ok(line) => line,
+                    Err(e) => {
+                        println!("Unable to read line from user: {}", e);
+                        return;
+                    }
+                };
+                self.load_bytecode(Path::new(tmp.trim()));
+            }
+            ".load_file" => {
+                let tmp = match self
+                    .editor
+                    .readline("Please enter the path to the file you wish to load: ")
+                {
+                    Ok(line) => line,
+                    Err(e) => {
+                        println!("Unable to read line from user: {}", e);
+                        return;
+                    }
+                };
+                self.load_file(Path::new(tmp.trim()));
+            }
+            _ => {
+                // Each line is assembled on its own, as if it were the only thing in a .code section. Its header is
+                // dropped, so its labels have to count from where it goes in the program.
+                let mut asm = Assembler::new().with_code_offset(self.vm.program.len() as u32);
+                let bytecode = match asm.assemble(&format!(".code\n{}", buffer)) {
+                    Ok(bytecode) => bytecode,
+                    Err(errors) => {
+                        for error in errors {
+                            println!("Unable to assemble input: {}", error);
+                        }
+                        return;
                     }
-                    self.vm.run_once();
+                };
+                let code_start = PIE_HEADER_LENGTH + 4 + asm.ro.len();
+                self.asm = asm;
+                self.vm.add_bytes(bytecode[code_start..].to_vec());
+                let before = self.vm.snapshot();
+                let stopped = self.vm.step();
+                for change in self.watch_changes() {
+                    println!("{}", change);
+                }
+                // Watched registers were just reported, so they are left out of the rest of what changed
+                let mut after = self.vm.snapshot();
+                for register in &self.watched {
+                    after.registers[*register] = before.registers[*register];
+                }
+                for change in before.diff(&after) {
+                    println!("{}", change);
+                }
+                if let Some(event) = stopped {
+                    println!("VM stopped: {:?}", event);
+                }
+            }
+        }
+    }
+
+    /// Runs the program entered so far from the start, after resetting the VM, and prints how it went. Typing
+    /// instructions executes them one at a time as they are added; this runs them all again in one go, as they would
+    /// run from a file. A header is prepended unless the program came from a `.begin` block and already has one.
+    /// Afterwards the pc is left at the end of the program, so instructions typed next pick up from there.
+    fn run_program(&mut self) {
+        let program = self.vm.program.clone();
+        self.vm.reset();
+        if !program.starts_with(&PIE_HEADER_PREFIX) {
+            self.vm.program = VirtualMachine::prepend_header(program.clone());
+        }
+        for event in self.vm.run() {
+            println!("{:?}", event.event);
+        }
+        self.vm.program = program;
+        self.vm.set_pc(self.vm.program.len());
+        println!("Listing registers and all contents:");
+        println!("{:#?}", self.vm.registers);
+        println!("End of Register Listing")
+    }
+
+    /// Describes each watched register that has changed since the last time this was called
+    fn watch_changes(&mut self) -> Vec<String> {
+        let mut changes = vec![];
+        for register in &self.watched {
+            let (before, after) = (self.prev_registers[*register], self.vm.registers[*register]);
+            if before != after {
+                changes.push(format!("${}: {} -> {}", register, before, after));
+            }
+        }
+        self.prev_registers = self.vm.registers.clone();
+        changes
+    }
+
+    /// Assembles a file the same way running it from the command line would, and loads it in place of whatever
+    /// program was loaded. `.run` runs it.
+    fn load_file(&mut self, path: &Path) {
+        let mut asm = Assembler::new();
+        match asm.assemble_file(path) {
+            Ok(bytecode) => {
+                self.asm = asm;
+                self.vm.reset();
+                self.vm.program = bytecode;
+                self.vm.set_pc(self.vm.program.len());
+                println!("Loaded {}; use .run to run it", path.display());
+            }
+            Err(errors) => {
+                for error in errors {
+                    println!("Unable to assemble {}: {}", path.display(), error);
+                }
+            }
+        }
+    }
+
+    /// Loads an already assembled program in place of whatever program was loaded, without assembling anything.
+    /// Files without a header this VM can run are rejected.
+    fn load_bytecode(&mut self, path: &Path) {
+        let bytecode = match fs::read(path) {
+            Ok(bytecode) => bytecode,
+            Err(e) => {
+                println!("Unable to read {}: {}", path.display(), e);
+                return;
+            }
+        };
+        if bytecode.len() < PIE_HEADER_LENGTH + 4 || !bytecode.starts_with(&PIE_HEADER_PREFIX) {
+            println!("{} is not an assembled program", path.display());
+            return;
+        }
+        let version = bytecode[PIE_HEADER_VERSION_OFFSET];
+        if !(OLDEST_BYTECODE_VERSION..=BYTECODE_VERSION).contains(&version) {
+            println!(
+                "{} was assembled for bytecode version {}, but this VM runs versions {} to {}",
+                path.display(),
+                version,
+                OLDEST_BYTECODE_VERSION,
+                BYTECODE_VERSION
+            );
+            return;
+        }
+        // The symbols of the program that was loaded before don't apply to this one
+        self.asm = Assembler::new();
+        self.vm.reset();
+        self.vm.program = bytecode;
+        self.vm.set_pc(self.vm.program.len());
+        println!("Loaded {}; use .run to run it", path.display());
+    }
+
+    /// Assembles a one-line program whose instructions are separated by `;`, as if it were a whole `.code` section,
+    /// and runs it to the end in a VM of its own. The REPL's own program and registers are left alone.
+    fn eval(&self, source: &str) -> Result<VirtualMachine, Vec<AssemblerError>> {
+        let mut asm = Assembler::new();
+        let bytecode = asm.assemble(&format!(".code\n{}\n", source.replace(';', "\n")))?;
+        let mut vm = VirtualMachine::new();
+        vm.program = bytecode;
+        for event in vm.run() {
+            debug!("{:?}", event.event);
+        }
+        Ok(vm)
+    }
+
+    /// Assembles the lines of a `.begin` block as a whole program, so labels can be used before they are declared,
+    /// and runs it in place of whatever program was loaded
+    fn run_block(&mut self, block: Vec<String>) {
+        let mut asm = Assembler::new();
+        match asm.assemble(&block.join("\n")) {
+            Ok(bytecode) => {
+                self.asm = asm;
+                self.vm.program = bytecode;
+                self.run_program();
+            }
+            Err(errors) => {
+                for error in errors {
+                    println!("Unable to assemble block: {}", error);
                 }
             }
         }
@@ -120,3 +433,214 @@ impl REPL {
         Ok(results)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_history_file() -> PathBuf {
+        env::temp_dir().join(format!("iridium-history-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_history_persists() {
+        let path = scratch_history_file();
+        let mut repl = REPL::new().with_history_file(path.clone());
+        assert!(repl.command_buffer.is_empty());
+        repl.record_command(".registers");
+        repl.record_command("load $0 #1");
+
+        let repl = REPL::new().with_history_file(path.clone());
+        assert_eq!(repl.command_buffer, vec![".registers", "load $0 #1"]);
+        let editor_history: Vec<&String> = repl.editor.history().iter().collect();
+        assert_eq!(editor_history, vec![".registers", "load $0 #1"]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_run_command() {
+        let mut repl = REPL::new().with_history_file(scratch_history_file());
+        repl.execute_command("load $0 #5");
+        repl.execute_command("load $1 #7");
+        repl.execute_command("add $0 $1 $2");
+        assert_eq!(repl.vm.registers[2], 12);
+        repl.vm.registers[2] = 0;
+
+        repl.execute_command(".run");
+        assert_eq!(repl.vm.registers[2], 12);
+        assert_eq!(repl.vm.program.len(), 12);
+        match repl.vm.events().last().unwrap().event {
+            VMEventType::GracefulStop { .. } => {}
+            ref e => panic!("Program should have stopped gracefully: {:?}", e),
+        }
+
+        // Typed instructions carry on after the program instead of running it again
+        repl.execute_command("inc $2");
+        assert_eq!(repl.vm.registers[2], 13);
+        assert_eq!(repl.vm.program.len(), 16);
+    }
+
+    #[test]
+    fn test_run_command_with_label() {
+        let mut repl = REPL::new().with_history_file(scratch_history_file());
+        repl.execute_command("load $0 #1");
+        repl.execute_command("eq $0 $0");
+        repl.execute_command("top: jmpe @top");
+        assert_eq!(repl.asm.symbols.symbol_value("top"), Some(8));
+        assert_eq!(repl.vm.snapshot().pc, 8);
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut repl = REPL::new().with_history_file(scratch_history_file());
+        repl.execute_command("load $3 #9");
+        let vm = repl.eval("load $0 #5; inc $0; add $0 $0 $1").unwrap();
+        assert_eq!(vm.registers[0], 6);
+        assert_eq!(vm.registers[1], 12);
+        assert_eq!(vm.registers[3], 0);
+
+        // The REPL's own program and registers are untouched
+        repl.execute_command(r#".eval "load $0 #5; inc $0""#);
+        assert_eq!(repl.vm.program, vec![0, 3, 0, 9]);
+        assert_eq!(repl.vm.registers[0], 0);
+        assert_eq!(repl.vm.registers[3], 9);
+    }
+
+    #[test]
+    fn test_eval_bad_expression() {
+        let repl = REPL::new().with_history_file(scratch_history_file());
+        assert!(repl.eval("load $0 #5; jmpe @nowhere").is_err());
+    }
+
+    #[test]
+    fn test_begin_block() {
+        let mut repl = REPL::new().with_history_file(scratch_history_file());
+        let lines = [
+            ".begin",
+            ".code",
+            "load $0 #0",
+            "load $1 #5",
+            "top: inc $0",
+            "neq $0 $1",
+            "jmpe @top",
+            "hlt",
+        ];
+        for line in lines.iter() {
+            repl.execute_command(line);
+        }
+        // Nothing runs until the block is ended
+        assert!(repl.vm.program.is_empty());
+        assert_eq!(repl.vm.registers[0], 0);
+
+        repl.execute_command(".end");
+        assert!(repl.block.is_none());
+        assert_eq!(repl.vm.registers[0], 5);
+        assert!(repl.asm.symbols.has_symbol("top"));
+
+        // The assembled program already has a header, so running it again doesn't add another
+        repl.execute_command(".run");
+        assert_eq!(repl.vm.registers[0], 5);
+    }
+
+    #[test]
+    fn test_load_file_with_labels() {
+        let path = env::temp_dir().join(format!("iridium-repl-{}.iasm", uuid::Uuid::new_v4()));
+        fs::write(
+            &path,
+            ".data\nhello: .asciiz 'Hi'\n.code\nload $0 #3\ntop: inc $1\ndec $0\nneq $0 $2\njmpe @top\nhlt\n",
+        )
+        .unwrap();
+        let mut repl = REPL::new().with_history_file(scratch_history_file());
+        repl.load_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(repl.asm.symbols.symbol_value("hello"), Some(0));
+        assert!(repl.asm.symbols.has_symbol("top"));
+        assert!(repl.vm.program.starts_with(&PIE_HEADER_PREFIX));
+
+        repl.execute_command(".run");
+        assert_eq!(repl.vm.registers[1], 3);
+        assert_eq!(repl.vm.ro_data(), b"Hi\0");
+    }
+
+    #[test]
+    fn test_load_bytecode() {
+        let bytecode = Assembler::new()
+            .assemble(".code\nload $0 #42\nhlt\n")
+            .unwrap();
+        let path = env::temp_dir().join(format!("iridium-repl-{}.bin", uuid::Uuid::new_v4()));
+        fs::write(&path, &bytecode).unwrap();
+        let mut repl = REPL::new().with_history_file(scratch_history_file());
+        repl.load_bytecode(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(repl.vm.program, bytecode);
+
+        repl.execute_command(".run");
+        assert_eq!(repl.vm.registers[0], 42);
+    }
+
+    #[test]
+    fn test_load_bytecode_rejects_bad_header() {
+        let path = env::temp_dir().join(format!("iridium-repl-{}.bin", uuid::Uuid::new_v4()));
+        let mut repl = REPL::new().with_history_file(scratch_history_file());
+        repl.execute_command("load $0 #5");
+
+        let mut not_bytecode = b"load $0 #42\nhlt\n".to_vec();
+        not_bytecode.resize(PIE_HEADER_LENGTH + 8, b' ');
+        fs::write(&path, &not_bytecode).unwrap();
+        repl.load_bytecode(&path);
+        assert_eq!(repl.vm.program, vec![0, 0, 0, 5]);
+
+        let mut wrong_version = VirtualMachine::prepend_header(vec![5, 0, 0, 0]);
+        wrong_version[PIE_HEADER_VERSION_OFFSET] = BYTECODE_VERSION + 1;
+        fs::write(&path, &wrong_version).unwrap();
+        repl.load_bytecode(&path);
+        assert_eq!(repl.vm.program, vec![0, 0, 0, 5]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut repl = REPL::new().with_history_file(scratch_history_file());
+        repl.execute_command(".watch $0");
+        repl.execute_command(".watch $2");
+        repl.execute_command(".watch $32");
+        assert_eq!(repl.watched, vec![0, 2]);
+
+        repl.execute_command("load $0 #5");
+        repl.execute_command("load $1 #7");
+        repl.execute_command("add $0 $1 $2");
+        repl.vm.program.extend_from_slice(&[18, 0, 0, 0]);
+        repl.vm.step();
+        assert_eq!(repl.watch_changes(), vec!["$0: 5 -> 6"]);
+        repl.vm
+            .program
+            .extend_from_slice(&[18, 1, 0, 0, 18, 2, 0, 0]);
+        repl.vm.step();
+        assert!(repl.watch_changes().is_empty());
+        repl.vm.step();
+        assert_eq!(repl.watch_changes(), vec!["$2: 12 -> 13"]);
+    }
+
+    #[test]
+    fn test_assemble_error_leaves_program_alone() {
+        let mut repl = REPL::new().with_history_file(scratch_history_file());
+        repl.execute_command("load $0 #5");
+        repl.execute_command("jmpe @missing");
+        assert_eq!(repl.vm.program.len(), 4);
+        assert_eq!(repl.vm.registers[0], 5);
+    }
+
+    #[test]
+    fn test_history_clear() {
+        let path = scratch_history_file();
+        let mut repl = REPL::new().with_history_file(path.clone());
+        repl.record_command(".registers");
+        repl.clear_history();
+        assert!(repl.command_buffer.is_empty());
+        assert!(repl.editor.history().is_empty());
+
+        let repl = REPL::new().with_history_file(path.clone());
+        assert!(repl.command_buffer.is_empty());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,49 @@
+use std::io::{self, BufRead, Write};
+
+/// Number of the syscall that reads a line of integer input into the argument register
+pub const SYSCALL_READ_INTEGER: u16 = 0;
+
+/// Number of the syscall that prints the argument register
+pub const SYSCALL_PRINT_INTEGER: u16 = 1;
+
+/// Number of the syscall that flushes any buffered output
+pub const SYSCALL_FLUSH: u16 = 2;
+
+/// Host functions a program can call with the SYSCALL opcode. Embedders implement this to give programs access to
+/// whatever I/O makes sense for them.
+pub trait Syscall {
+    /// Reads an integer, or returns `None` if one couldn't be read
+    fn read_integer(&mut self) -> Option<i32>;
+    fn print_integer(&mut self, value: i32);
+    /// Prints a single byte as a character, for the memory-mapped display
+    fn print_char(&mut self, byte: u8);
+    fn flush(&mut self);
+}
+
+/// The default host functions, which use stdin and stdout
+pub struct StdioSyscalls;
+
+impl Syscall for StdioSyscalls {
+    fn read_integer(&mut self) -> Option<i32> {
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(_) => line.trim().parse::<i32>().ok(),
+            Err(e) => {
+                error!("Unable to read from stdin: {}", e);
+                None
+            }
+        }
+    }
+
+    fn print_integer(&mut self, value: i32) {
+        print!("{}", value);
+    }
+
+    fn print_char(&mut self, byte: u8) {
+        print!("{}", byte as char);
+    }
+
+    fn flush(&mut self) {
+        io::stdout().flush().expect("Unable to flush stdout");
+    }
+}
@@ -5,3 +5,95 @@ pub fn from_hex(input: &str) -> Result<u8, std::num::ParseIntError> {
 pub fn is_hex_digit(c: char) -> bool {
     c.is_digit(16)
 }
+
+/// A small xorshift64* pseudo-random number generator. It is fast and reproducible from a seed, but not suitable for
+/// anything that needs to be unpredictable.
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        // An all-zero state would only ever produce zeros
+        let state = if seed == 0 {
+            0x9e37_79b9_7f4a_7c15
+        } else {
+            seed
+        };
+        XorShift { state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// The upper half of the next value, since the low bits of xorshift output are the weakest
+    pub fn next_i32(&mut self) -> i32 {
+        (self.next_u64() >> 32) as i32
+    }
+}
+
+/// Formats bytes as a hex dump, 16 to a line: the offset of the first byte, the bytes in hex, then the bytes as ASCII
+/// with anything unprintable shown as `.`
+pub fn hex_dump(bytes: &[u8]) -> Vec<String> {
+    let mut lines = vec![];
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        lines.push(format!(
+            "{:08x}  {:<47}  |{}|",
+            line * 16,
+            hex.join(" "),
+            ascii
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xorshift_reproducible() {
+        let mut first = XorShift::new(42);
+        let mut second = XorShift::new(42);
+        let values: Vec<i32> = (0..5).map(|_| first.next_i32()).collect();
+        assert_eq!(
+            values,
+            (0..5).map(|_| second.next_i32()).collect::<Vec<i32>>()
+        );
+        assert_ne!(values[0], values[1]);
+        assert_ne!(XorShift::new(0).next_u64(), 0);
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let mut bytes = b"Hello\0".to_vec();
+        bytes.extend_from_slice(b"0123456789abcdef");
+        let dump = hex_dump(&bytes);
+        assert_eq!(dump.len(), 2);
+        assert_eq!(
+            dump[0],
+            "00000000  48 65 6c 6c 6f 00 30 31 32 33 34 35 36 37 38 39  |Hello.0123456789|"
+        );
+        assert_eq!(
+            dump[1],
+            "00000010  61 62 63 64 65 66                                |abcdef|"
+        );
+        assert!(hex_dump(&[]).is_empty());
+    }
+}
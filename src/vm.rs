@@ -1,5 +1,6 @@
 use std;
-use std::io::Cursor;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead};
 // use std::net::SocketAddr;
 // use std::sync::{Arc, RwLock};
 // use std::thread;
@@ -7,18 +8,152 @@ use std::io::Cursor;
 use byteorder::*;
 use chrono::prelude::*;
 use num_cpus;
+use serde_derive::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::assembler::{PIE_HEADER_LENGTH, PIE_HEADER_PREFIX};
-use crate::instruction::Opcode;
+use crate::assembler::disassembler::disassemble_instructions;
+use crate::assembler::{
+    DataEndian, OperandEndian, BYTECODE_VERSION, OLDEST_BYTECODE_VERSION, PIE_HEADER_ENTRY_OFFSET,
+    PIE_HEADER_LENGTH, PIE_HEADER_PREFIX, PIE_HEADER_RO_LENGTH_OFFSET, PIE_HEADER_VERSION_OFFSET,
+};
+use crate::instruction::{Immediate, Opcode};
+use crate::syscall::{
+    StdioSyscalls, Syscall, SYSCALL_FLUSH, SYSCALL_PRINT_INTEGER, SYSCALL_READ_INTEGER,
+};
+use crate::utils::XorShift;
+use crate::vm_errors::VMError;
 
 /// Default starting size for a VM's heap
 pub const DEFAULT_HEAP_STARTING_SIZE: usize = 64;
 
+/// First heap address of the memory-mapped I/O range. Stores to addresses from here up to `MMIO_END` go to a device
+/// rather than the heap; the ones without a device are reserved and ignored. The heap can't grow past this, so an
+/// address is never both.
+pub const MMIO_START: usize = 0xFFF0;
+
+/// One past the last address of the memory-mapped I/O range
+pub const MMIO_END: usize = 0x1_0000;
+
+/// Storing to this address prints the low byte of the value as a character
+pub const MMIO_DISPLAY: usize = 0xFFF0;
+
 /// Default stack starting space. We'll default to 2MB.
 pub const DEFAULT_STACK_SPACE: usize = 2097152;
 
-#[derive(Clone, Debug)]
+/// How many integer and float registers a VM has unless `with_register_count` says otherwise
+pub const DEFAULT_REGISTER_COUNT: usize = 32;
+
+/// The most registers a program can address, since register operands are a single byte
+pub const MAX_REGISTER_COUNT: usize = 256;
+
+/// Crash code for a program whose header is malformed
+pub const INVALID_HEADER_CODE: u32 = 1;
+
+/// Crash code for a program written in a bytecode format version this VM doesn't understand
+pub const UNSUPPORTED_VERSION_CODE: u32 = 2;
+
+/// Crash code for a PUSH or CALL that would grow the stack past its limit
+pub const STACK_OVERFLOW_CODE: u32 = 3;
+
+/// Crash code for a DIVU with a zero divisor
+pub const DIVIDE_BY_ZERO_CODE: u32 = 4;
+
+/// Crash code for a program that fails `validate` when validation before running is turned on
+pub const INVALID_BYTECODE_CODE: u32 = 5;
+
+/// Runs one instruction given its decoded operands, once `pc` has moved past it, returning the event that stopped the
+/// VM if it should not keep going
+type Handler = fn(&mut VirtualMachine, Operands) -> Option<VMEventType>;
+
+/// What the VM needs to know to run an opcode byte
+#[derive(Clone, Copy)]
+struct OpcodeInfo {
+    opcode: Opcode,
+    width: usize,
+    handler: Handler,
+    /// The positions of the register operands, from `register_operands`. Ones the opcode doesn't have point at the
+    /// opcode byte, which is always there to read.
+    registers: [usize; 3],
+    /// From `immediate_operand`
+    immediate: Option<Immediate>,
+}
+
+/// Everything about each opcode byte that running it needs. Bytes that aren't an opcode decode to IGL, so they get
+/// its entry. Calling through this table keeps each instruction's code in its own small function, which runs
+/// noticeably faster than one `match` over every opcode, and keeps decoding an instruction down to a lookup and a few
+/// reads.
+static OPCODES: [OpcodeInfo; 256] = {
+    let mut opcodes = [OpcodeInfo {
+        opcode: Opcode::IGL,
+        width: 0,
+        handler: VirtualMachine::execute_igl,
+        registers: [0; 3],
+        immediate: None,
+    }; 256];
+    let mut byte = 0;
+    while byte < opcodes.len() {
+        let opcode = Opcode::from_byte(byte as u8);
+        let positions = opcode.register_operands();
+        let mut registers = [0; 3];
+        let mut idx = 0;
+        while idx < positions.len() {
+            registers[idx] = positions[idx];
+            idx += 1;
+        }
+        opcodes[byte] = OpcodeInfo {
+            opcode,
+            width: opcode.width(),
+            handler: VirtualMachine::handler(opcode),
+            registers,
+            immediate: opcode.immediate_operand(),
+        };
+        byte += 1;
+    }
+    opcodes
+};
+
+/// An instruction's operands, decoded from its bytes so handlers never read the program themselves
+#[derive(Clone, Copy)]
+struct Operands {
+    /// The register numbers, in the order they appear. Ones the opcode doesn't have hold its opcode byte.
+    registers: [u8; 3],
+    /// The immediate or jump target, if the opcode has one. A float is kept as its bits.
+    immediate: u64,
+}
+
+impl Operands {
+    /// Decodes the operands of an instruction from `bytes`, which start with its opcode and hold all of it
+    #[inline]
+    fn decode(info: &OpcodeInfo, bytes: &[u8]) -> Operands {
+        let [one, two, three] = info.registers;
+        let immediate = match info.immediate {
+            Some(Immediate::Short(start)) => u64::from(OperandEndian::read_u16(&bytes[start..])),
+            Some(Immediate::Word(start)) => u64::from(OperandEndian::read_u32(&bytes[start..])),
+            Some(Immediate::Float(start)) => DataEndian::read_u64(&bytes[start..]),
+            None => 0,
+        };
+        Operands {
+            registers: [bytes[one], bytes[two], bytes[three]],
+            immediate,
+        }
+    }
+
+    /// The number of the `idx`th register operand
+    fn register(&self, idx: usize) -> usize {
+        self.registers[idx] as usize
+    }
+}
+
+/// What the decode cache knows about an instruction: everything needed to run it, found and bounds checked ahead of
+/// time
+#[derive(Clone, Copy)]
+struct DecodedInstruction {
+    opcode: Opcode,
+    handler: Handler,
+    operands: Operands,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum VMEventType {
     Start,
     GracefulStop { code: u32 },
@@ -35,7 +170,7 @@ impl VMEventType {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 /// Struct for a VMEvent that includes the application ID and time
 pub struct VMEvent {
     pub event: VMEventType,
@@ -43,13 +178,82 @@ pub struct VMEvent {
     application_id: Uuid,
 }
 
+/// A callback that is given each event as the VM records it
+pub type EventHandler = Box<dyn FnMut(&VMEvent)>;
+
+/// The observable state of a VM at a point in time, e.g. for printing as JSON after a run or saving and restoring it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VmSnapshot {
+    pub registers: Vec<i32>,
+    pub float_registers: Vec<f64>,
+    pub equal_flag: bool,
+    pub remainder: u32,
+    pub pc: usize,
+    pub sp: usize,
+    pub bp: usize,
+    /// The caller registers saved by each `call` still in progress, when register banks are on
+    #[serde(default)]
+    pub register_banks: Vec<Vec<i32>>,
+    /// Only informational; `restore` leaves the VM's own event log alone
+    #[serde(default)]
+    pub events: Vec<VMEvent>,
+}
+
+impl VmSnapshot {
+    /// Describes everything that is different in `after`, one line per register, flag or pointer, such as
+    /// `$3: 0 -> 5`. Float registers are listed as `float $1`. The register banks and events are left out.
+    pub fn diff(&self, after: &VmSnapshot) -> Vec<String> {
+        let mut changes = vec![];
+        for (register, (before, after)) in self.registers.iter().zip(&after.registers).enumerate() {
+            if before != after {
+                changes.push(format!("${}: {} -> {}", register, before, after));
+            }
+        }
+        for (register, (before, after)) in self
+            .float_registers
+            .iter()
+            .zip(&after.float_registers)
+            .enumerate()
+        {
+            if before != after {
+                changes.push(format!("float ${}: {:?} -> {:?}", register, before, after));
+            }
+        }
+        if self.equal_flag != after.equal_flag {
+            changes.push(format!(
+                "equal_flag: {} -> {}",
+                self.equal_flag, after.equal_flag
+            ));
+        }
+        let pointers = [
+            (
+                "remainder",
+                self.remainder as usize,
+                after.remainder as usize,
+            ),
+            ("pc", self.pc, after.pc),
+            ("sp", self.sp, after.sp),
+            ("bp", self.bp, after.bp),
+        ];
+        for (name, before, after) in pointers.iter() {
+            if before != after {
+                changes.push(format!("{}: {} -> {}", name, before, after));
+            }
+        }
+        changes
+    }
+}
+
 pub struct VirtualMachine {
-    /// Array that simulates having hardware registers
-    pub registers: [i32; 32],
-    /// Array that simulates having floating point hardware registers
-    pub float_registers: [f64; 32],
+    /// Simulates having hardware registers. There are `DEFAULT_REGISTER_COUNT` unless `with_register_count` says
+    /// otherwise.
+    pub registers: Vec<i32>,
+    /// Simulates having floating point hardware registers, as many as there are integer registers
+    pub float_registers: Vec<f64>,
     pub logical_cores: usize,
     pub stack: Vec<i32>,
+    /// Most values the stack may hold before PUSH and CALL crash with a stack overflow
+    stack_limit: usize,
     pub loop_counter: usize,
     pub id: Uuid,
     events: Vec<VMEvent>,
@@ -58,19 +262,63 @@ pub struct VirtualMachine {
     pc: usize,
     /// Keeps track of where in the stack the program currently is
     pub sp: usize,
-    /// Keeps track of the current frame pointer
+    /// Keeps track of the current frame pointer, the index of the first stack slot of the current function's locals
     pub bp: usize,
 
     /// Bytecode of the program being run
     pub program: Vec<u8>,
-    /// Remainder of modulo division ops
+    /// Remainder of modulo division ops. Follows Rust's `%`, so it takes the sign of the dividend and a negative
+    /// remainder is stored as its two's complement bit pattern
     remainder: u32,
     /// Result of last comparison op
     equal_flag: bool,
     heap: Vec<u8>,
+    /// Largest the heap is allowed to grow to with ALOC. `None` means there is no limit.
+    max_heap: Option<usize>,
     /// Contains the read-only section data
     ro_data: Vec<u8>,
     alias: Option<String>,
+    /// Host functions that SYSCALL dispatches to
+    syscalls: Box<dyn Syscall>,
+    /// Where READ gets its input from. `None` reads stdin through the process-wide handle, so READ and SYSCALL share
+    /// its buffer and neither reads ahead of the other.
+    input: Option<Box<dyn BufRead>>,
+    /// Logs every instruction before it is executed
+    trace: bool,
+    /// Counts how many times each opcode is executed
+    profile: bool,
+    opcode_counts: HashMap<Opcode, u64>,
+    /// Makes `run` call `validate` before executing anything
+    validate_first: bool,
+    /// Makes `run` decode the program once up front instead of each instruction as it is reached
+    decode_cache: bool,
+    /// The instruction starting at each offset of the program, while a run with the decode cache is going. Offsets
+    /// that aren't the start of an instruction are `None`.
+    decoded: Vec<Option<DecodedInstruction>>,
+    /// Where the timestamps on events come from
+    clock: Box<dyn Fn() -> DateTime<Utc>>,
+    /// When the last Start event happened, or when the VM was created if it hasn't been run. TIME counts from here.
+    started_at: DateTime<Utc>,
+    /// Called with each event as it happens, rather than when the run is over
+    event_handler: Option<EventHandler>,
+    /// Where RAND gets its numbers from
+    rng: XorShift,
+    /// Gives each CALL its own bank of registers, so a function can't clobber its caller's
+    banked_registers: bool,
+    /// The register banks of the callers of the current function, innermost last
+    register_banks: Vec<Vec<i32>>,
+}
+
+impl VMEvent {
+    /// When the event happened
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.at
+    }
+
+    /// The ID of the VM the event happened in
+    pub fn application_id(&self) -> Uuid {
+        self.application_id
+    }
 }
 
 impl VirtualMachine {
@@ -81,17 +329,33 @@ impl VirtualMachine {
             logical_cores: num_cpus::get(),
             loop_counter: 0,
             stack: Vec::with_capacity(DEFAULT_STACK_SPACE),
-            registers: [0; 32],
-            float_registers: [0.0; 32],
+            stack_limit: DEFAULT_STACK_SPACE,
+            registers: vec![0; DEFAULT_REGISTER_COUNT],
+            float_registers: vec![0.0; DEFAULT_REGISTER_COUNT],
             program: vec![],
             pc: 0,
             sp: 0,
             bp: 0,
             remainder: 0,
             equal_flag: false,
-            heap: vec![0, DEFAULT_HEAP_STARTING_SIZE as u8],
+            heap: vec![0; DEFAULT_HEAP_STARTING_SIZE],
+            max_heap: None,
             ro_data: vec![],
             alias: None,
+            syscalls: Box::new(StdioSyscalls),
+            input: None,
+            trace: false,
+            profile: false,
+            opcode_counts: HashMap::new(),
+            validate_first: false,
+            decode_cache: false,
+            decoded: vec![],
+            clock: Box::new(Utc::now),
+            started_at: Utc::now(),
+            event_handler: None,
+            rng: XorShift::new(Uuid::new_v4().as_u128() as u64),
+            banked_registers: false,
+            register_banks: vec![],
         }
     }
 
@@ -104,37 +368,316 @@ impl VirtualMachine {
         self
     }
 
-    /// Loops as long as instructions can be executed.
-    pub fn run(&mut self) -> Vec<VMEvent> {
-        self.events.push(VMEvent {
-            event: VMEventType::Start,
-            at: Utc::now(),
-            application_id: self.id,
-        });
+    /// Pins the VM's ID, which is otherwise random, so the events of a run are reproducible
+    pub fn with_id(mut self, id: Uuid) -> Self {
+        self.id = id;
+        self
+    }
 
-        if !self.verify_header() {
-            self.events.push(VMEvent {
-                event: VMEventType::Crash { code: 1 },
-                at: Utc::now(),
-                application_id: self.id,
-            });
+    /// Replaces the current time as the source of event timestamps, e.g. with a fixed time for golden-file tests
+    pub fn with_clock(mut self, clock: Box<dyn Fn() -> DateTime<Utc>>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Streams events to `handler` as they happen, for monitoring long-running programs
+    pub fn with_event_handler(mut self, handler: EventHandler) -> Self {
+        self.event_handler = Some(handler);
+        self
+    }
+
+    /// Replaces the host functions that SYSCALL dispatches to
+    pub fn with_syscalls(mut self, syscalls: Box<dyn Syscall>) -> Self {
+        self.syscalls = syscalls;
+        self
+    }
+
+    /// Caps the size the heap can grow to, so a program can't allocate the host out of memory. It never grows past
+    /// `MMIO_START` either way.
+    pub fn with_max_heap(mut self, max_heap: usize) -> Self {
+        self.max_heap = Some(max_heap);
+        self
+    }
+
+    /// Caps how many values the stack can hold, so runaway recursion crashes instead of eating the host's memory
+    pub fn with_stack_limit(mut self, stack_limit: usize) -> Self {
+        self.stack_limit = stack_limit;
+        self
+    }
+
+    /// Turns on logging of the pc and disassembly of each instruction as it is executed
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Turns on counting of the opcodes executed, which `profile_report` reports on
+    pub fn with_profile(mut self, profile: bool) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Seeds the generator RAND uses, which is otherwise seeded randomly, so runs are reproducible
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.rng = XorShift::new(seed);
+        self
+    }
+
+    /// Turns on register banking. CALL saves the caller's registers and RET puts them back, except for $0, which is
+    /// how a function hands back its result. The function starts out with a copy of the caller's registers, so it can
+    /// take its arguments in any of them.
+    pub fn with_register_banks(mut self, banked: bool) -> Self {
+        self.banked_registers = banked;
+        self
+    }
+
+    /// Sets how many integer registers there are, and as many float registers. Register operands are a single byte,
+    /// so programs can only reach the first `MAX_REGISTER_COUNT`. There is always at least `$0`.
+    pub fn with_register_count(mut self, count: usize) -> Self {
+        let count = count.clamp(1, MAX_REGISTER_COUNT);
+        self.registers = vec![0; count];
+        self.float_registers = vec![0.0; count];
+        self
+    }
+
+    /// Turns on checking the whole program with `validate` before `run` executes it
+    pub fn with_validation(mut self, validate: bool) -> Self {
+        self.validate_first = validate;
+        self
+    }
+
+    /// Turns on decoding every instruction, operands and all, once before `run` or `run_headerless` starts, rather
+    /// than each time it is reached, which speeds up loops. `run_once` always decodes as it goes.
+    pub fn with_decode_cache(mut self, decode_cache: bool) -> Self {
+        self.decode_cache = decode_cache;
+        self
+    }
+
+    /// How many times each opcode has been executed while profiling, most frequent first
+    pub fn profile_report(&self) -> Vec<(Opcode, u64)> {
+        let mut report: Vec<(Opcode, u64)> = self
+            .opcode_counts
+            .iter()
+            .map(|(opcode, count)| (*opcode, *count))
+            .collect();
+        report.sort_by(|a, b| b.1.cmp(&a.1).then((a.0 as u8).cmp(&(b.0 as u8))));
+        report
+    }
+
+    /// Replaces stdin as the source of input for READ
+    pub fn with_input(mut self, input: Box<dyn BufRead>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    /// Puts the VM back in the state it was in before running, while keeping the loaded program and configuration
+    pub fn reset(&mut self) {
+        self.registers.iter_mut().for_each(|r| *r = 0);
+        self.float_registers.iter_mut().for_each(|r| *r = 0.0);
+        self.stack.clear();
+        self.register_banks.clear();
+        self.loop_counter = 0;
+        self.events.clear();
+        self.pc = 0;
+        self.sp = 0;
+        self.bp = 0;
+        self.remainder = 0;
+        self.equal_flag = false;
+        self.heap = vec![0; DEFAULT_HEAP_STARTING_SIZE];
+        self.ro_data.clear();
+        self.opcode_counts.clear();
+    }
+
+    /// Loops as long as instructions can be executed. Returns every event so far, the same log as `events`.
+    pub fn run(&mut self) -> &[VMEvent] {
+        self.push_event(VMEventType::Start);
+
+        if let Err(code) = self.verify_header() {
+            self.push_event(VMEventType::Crash { code });
             error!("Header was incorrect");
-            return self.events.clone();
+            return &self.events;
+        }
+
+        // The read-only data sits between the header and the code, and its length is recorded in the header
+        let ro_start = PIE_HEADER_LENGTH + 4;
+        let ro_end = ro_start + self.get_ro_length();
+        let code_start = ro_start + self.get_starting_offset();
+        if ro_end > self.program.len() || code_start > self.program.len() {
+            self.push_event(VMEventType::Crash {
+                code: INVALID_HEADER_CODE,
+            });
+            error!("Read-only data or starting offset runs past the end of the program");
+            return &self.events;
+        }
+        self.ro_data = self.program[ro_start..ro_end].to_vec();
+
+        if self.validate_first {
+            if let Err(e) = self.validate() {
+                self.push_event(VMEventType::Crash {
+                    code: INVALID_BYTECODE_CODE,
+                });
+                error!("{}", e);
+                return &self.events;
+            }
+        }
+
+        self.pc = match self.get_entry_offset() {
+            0 => code_start,
+            entry if entry >= code_start && entry < self.program.len() => entry,
+            entry => {
+                self.push_event(VMEventType::Crash {
+                    code: INVALID_HEADER_CODE,
+                });
+                error!("Entry point {} is outside the code", entry);
+                return &self.events;
+            }
+        };
+        self.execute_until_stopped()
+    }
+
+    /// Scans the code section of the program once without running it, checking that every opcode is known, every
+    /// instruction is complete, every register operand exists, and every immediate jump target is the start of an
+    /// instruction. Jumps through registers can only be checked as they happen.
+    pub fn validate(&self) -> Result<(), VMError> {
+        self.verify_header()
+            .map_err(|code| VMError::InvalidHeader { code })?;
+        let ro_start = PIE_HEADER_LENGTH + 4;
+        let code_start = ro_start + self.get_starting_offset();
+        if ro_start + self.get_ro_length() > self.program.len() || code_start > self.program.len() {
+            return Err(VMError::InvalidHeader {
+                code: INVALID_HEADER_CODE,
+            });
+        }
+
+        // Find where every instruction starts first, so jumps forward can be checked too
+        let mut starts = vec![];
+        let mut offset = code_start;
+        while offset < self.program.len() {
+            let opcode = Opcode::from(self.program[offset]);
+            if opcode == Opcode::IGL {
+                return Err(VMError::IllegalOpcode {
+                    offset,
+                    byte: self.program[offset],
+                });
+            }
+            if offset + opcode.width() > self.program.len() {
+                return Err(VMError::TruncatedInstruction { offset });
+            }
+            starts.push(offset);
+            offset += opcode.width();
+        }
+        let boundaries: HashSet<usize> = starts.iter().cloned().collect();
+
+        for &offset in &starts {
+            let opcode = Opcode::from(self.program[offset]);
+            let instruction = &self.program[offset..offset + opcode.width()];
+            for &position in opcode.register_operands() {
+                if instruction[position] as usize >= self.registers.len() {
+                    return Err(VMError::InvalidRegister {
+                        offset,
+                        register: instruction[position],
+                        count: self.registers.len(),
+                    });
+                }
+            }
+            let immediate =
+                |position: usize| OperandEndian::read_u16(&instruction[position..]) as usize;
+            let target = match opcode {
+                Opcode::JMPE => Some(OperandEndian::read_u32(&instruction[1..]) as usize),
+                Opcode::CALL => Some(immediate(1)),
+                Opcode::JLT | Opcode::JGT | Opcode::JLE | Opcode::JGE => Some(immediate(3)),
+                Opcode::JMPFI => Some(offset + immediate(1)),
+                // Offset 0 is inside the header, so a jump back past the start is reported as a jump there
+                Opcode::JMPBI => Some(offset.saturating_sub(immediate(1))),
+                _ => None,
+            };
+            if let Some(target) = target {
+                if !boundaries.contains(&target) {
+                    return Err(VMError::MisalignedJump { offset, target });
+                }
+            }
         }
+        Ok(())
+    }
+
+    /// Runs a raw program that has no header, such as the bytes the REPL builds up, starting from its first byte
+    pub fn run_headerless(&mut self) -> &[VMEvent] {
+        self.push_event(VMEventType::Start);
+        self.pc = 0;
+        self.execute_until_stopped()
+    }
 
-        self.pc = 68 + self.get_starting_offset();
+    fn execute_until_stopped(&mut self) -> &[VMEvent] {
+        if self.decode_cache {
+            self.decoded = self.decode_program(self.pc);
+        }
         let mut is_done = None;
         while is_done.is_none() {
             is_done = self.execute_instruction();
         }
-        self.events.push(VMEvent {
-            event: VMEventType::GracefulStop {
-                code: is_done.unwrap(),
-            },
-            at: Utc::now(),
+        // The program can be changed between runs, so the cache only lasts for this one
+        self.decoded.clear();
+        self.push_event(is_done.unwrap());
+        &self.events
+    }
+
+    /// Stores a word in the heap as 4 little-endian bytes, or sends it to a device if the address is in the
+    /// memory-mapped I/O range. Crashes rather than growing the heap, or if the word would run from the heap into the
+    /// I/O range.
+    fn store_word(&mut self, address: usize, value: i32) -> Result<(), VMEventType> {
+        if (MMIO_START..MMIO_END).contains(&address) {
+            self.write_mmio(address, value);
+        } else if address < MMIO_START && address + 4 > MMIO_START {
+            error!(
+                "Store to {} runs into the memory-mapped I/O range at {:#x}",
+                address, MMIO_START
+            );
+            return Err(VMEventType::Crash { code: 1 });
+        } else if address + 4 > self.heap.len() {
+            error!(
+                "Store to {} is past the end of the heap at {}",
+                address,
+                self.heap.len()
+            );
+            return Err(VMEventType::Crash { code: 1 });
+        } else {
+            DataEndian::write_i32(&mut self.heap[address..address + 4], value);
+        }
+        Ok(())
+    }
+
+    /// The heap address of 4-byte element `index` of an array starting at `base`, or `None` if it is negative
+    fn element_address(base: i32, index: i32) -> Option<usize> {
+        let address = base as i64 + index as i64 * 4;
+        if address < 0 {
+            None
+        } else {
+            Some(address as usize)
+        }
+    }
+
+    /// Sends a store in the memory-mapped I/O range to its device
+    fn write_mmio(&mut self, address: usize, value: i32) {
+        match address {
+            MMIO_DISPLAY => self.syscalls.print_char(value as u8),
+            _ => debug!("Ignoring a store to reserved MMIO address {:#x}", address),
+        }
+    }
+
+    /// Records an event, passing it to the event handler first if there is one
+    fn push_event(&mut self, event: VMEventType) {
+        let event = VMEvent {
+            event,
+            at: (self.clock)(),
             application_id: self.id,
-        });
-        self.events.clone()
+        };
+        if let VMEventType::Start = event.event {
+            self.started_at = event.at;
+        }
+        if let Some(handler) = self.event_handler.as_mut() {
+            handler(&event);
+        }
+        self.events.push(event);
     }
 
     /// Executes one instruction. Meant to allow for more controlled execution.
@@ -142,6 +685,11 @@ impl VirtualMachine {
         self.execute_instruction();
     }
 
+    /// Executes one instruction and returns the event that stopped the VM, or `None` if it can keep going
+    pub fn step(&mut self) -> Option<VMEventType> {
+        self.execute_instruction()
+    }
+
     pub fn add_byte(&mut self, b: u8) {
         self.program.push(b);
     }
@@ -150,6 +698,74 @@ impl VirtualMachine {
         self.program.append(&mut b);
     }
 
+    /// Captures the registers, flags and events of the VM as they are right now
+    pub fn snapshot(&self) -> VmSnapshot {
+        VmSnapshot {
+            registers: self.registers.clone(),
+            float_registers: self.float_registers.clone(),
+            equal_flag: self.equal_flag,
+            remainder: self.remainder,
+            pc: self.pc,
+            sp: self.sp,
+            bp: self.bp,
+            register_banks: self.register_banks.clone(),
+            events: self.events.clone(),
+        }
+    }
+
+    /// Puts the registers, flags and register banks back the way they were when `snapshot` was taken. The program and
+    /// stack are left alone. The snapshot, and each of its register banks, has to have as many registers of each kind
+    /// as the VM, or nothing is changed.
+    pub fn restore(&mut self, snapshot: VmSnapshot) -> Result<(), VMError> {
+        if snapshot.registers.len() != self.registers.len()
+            || snapshot.float_registers.len() != self.float_registers.len()
+            || snapshot
+                .register_banks
+                .iter()
+                .any(|bank| bank.len() != self.registers.len())
+        {
+            return Err(VMError::SnapshotRegisterCount {
+                registers: snapshot.registers.len(),
+                float_registers: snapshot.float_registers.len(),
+                count: self.registers.len(),
+            });
+        }
+        self.registers = snapshot.registers;
+        self.float_registers = snapshot.float_registers;
+        self.equal_flag = snapshot.equal_flag;
+        self.remainder = snapshot.remainder;
+        self.pc = snapshot.pc;
+        self.sp = snapshot.sp;
+        self.bp = snapshot.bp;
+        self.register_banks = snapshot.register_banks;
+        Ok(())
+    }
+
+    /// Moves the program counter, so the next instruction executed is the one at `pc`
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    /// Everything that has happened in this VM so far, oldest first
+    pub fn events(&self) -> &[VMEvent] {
+        &self.events
+    }
+
+    /// The read-only data section of the program being run
+    pub fn ro_data(&self) -> &[u8] {
+        &self.ro_data
+    }
+
+    /// The remainder left by the last DIV. Cast it to `i32` to recover the sign of a negative remainder.
+    pub fn remainder(&self) -> u32 {
+        self.remainder
+    }
+
+    /// Result of the last comparison op
+    pub fn equal_flag(&self) -> bool {
+        self.equal_flag
+    }
+
     pub fn get_test_vm() -> Self {
         let mut vm = VirtualMachine::new();
         vm.registers[0] = 5;
@@ -157,300 +773,2055 @@ impl VirtualMachine {
         vm
     }
 
-    fn verify_header(&self) -> bool {
+    /// Same as `get_test_vm`, with `bytes` loaded as the program behind an empty header so it can be passed to `run`
+    pub fn get_test_vm_with_program(bytes: Vec<u8>) -> Self {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = VirtualMachine::prepend_header(bytes);
+        vm
+    }
+
+    /// Checks the header, returning the crash code to stop with if the program can't be run
+    fn verify_header(&self) -> Result<(), u32> {
+        // The header is followed by the 4-byte starting offset, which `get_starting_offset` reads unconditionally
+        if self.program.len() < PIE_HEADER_LENGTH + 4 {
+            return Err(INVALID_HEADER_CODE);
+        }
         if self.program[0..4] != PIE_HEADER_PREFIX {
-            return false;
+            return Err(INVALID_HEADER_CODE);
         }
-        true
+        let version = self.program[PIE_HEADER_VERSION_OFFSET];
+        if !(OLDEST_BYTECODE_VERSION..=BYTECODE_VERSION).contains(&version) {
+            error!("Unsupported bytecode version {}", version);
+            return Err(UNSUPPORTED_VERSION_CODE);
+        }
+        Ok(())
     }
 
-    fn execute_instruction(&mut self) -> Option<u32> {
-        if self.pc >= self.program.len() {
-            return Some(1);
-        }
-
-        match self.decode_opcode() {
-            Opcode::ADD => {
-                let register_one = self.registers[self.next_eight_bits() as usize];
-                let register_two = self.registers[self.next_eight_bits() as usize];
-                self.registers[self.next_eight_bits() as usize] = register_one + register_two;
-            }
-            Opcode::SUB => {
-                let register_one = self.registers[self.next_eight_bits() as usize];
-                let register_two = self.registers[self.next_eight_bits() as usize];
-                self.registers[self.next_eight_bits() as usize] = register_one - register_two;
-            }
-            Opcode::MUL => {
-                let register_one = self.registers[self.next_eight_bits() as usize];
-                let register_two = self.registers[self.next_eight_bits() as usize];
-                self.registers[self.next_eight_bits() as usize] = register_one * register_two;
-            }
-            Opcode::DIV => {
-                let register_one = self.registers[self.next_eight_bits() as usize];
-                let register_two = self.registers[self.next_eight_bits() as usize];
-                self.registers[self.next_eight_bits() as usize] = register_one / register_two;
-                self.remainder = (register_one % register_two) as u32;
-            }
-            Opcode::LOAD => {
-                let register = self.next_eight_bits() as usize;
-                let number = self.next_sixteen_bits() as u16;
-                self.registers[register] = number as i32;
-            }
-            Opcode::HLT => {
-                println!("HLT encountered");
-                return Some(1);
-            }
-            Opcode::JMP => {
-                let target = self.registers[self.next_eight_bits() as usize];
-                self.pc = target as usize;
-            }
-            Opcode::JMPB => {
-                let value = self.registers[self.next_eight_bits() as usize];
-                self.pc -= value as usize;
-            }
-            Opcode::JMPF => {
-                let value = self.registers[self.next_eight_bits() as usize];
-                self.pc += value as usize;
-            }
-            Opcode::EQ => {
-                let register_one = self.registers[self.next_eight_bits() as usize];
-                let register_two = self.registers[self.next_eight_bits() as usize];
-                if register_one == register_two {
-                    self.equal_flag = true;
-                } else {
-                    self.equal_flag = false;
-                }
-                self.next_eight_bits();
-            }
-
-            Opcode::NEQ => {
-                let register_one = self.registers[self.next_eight_bits() as usize];
-                let register_two = self.registers[self.next_eight_bits() as usize];
-                self.equal_flag = register_one != register_two;
-                self.next_eight_bits();
-            }
-            Opcode::GT => {
-                let register_one = self.registers[self.next_eight_bits() as usize];
-                let register_two = self.registers[self.next_eight_bits() as usize];
-                self.equal_flag = register_one > register_two;
-                self.next_eight_bits();
-            }
-            Opcode::LT => {
-                let register_one = self.registers[self.next_eight_bits() as usize];
-                let register_two = self.registers[self.next_eight_bits() as usize];
-                self.equal_flag = register_one < register_two;
-                self.next_eight_bits();
-            }
-            Opcode::GTQ => {
-                let register_one = self.registers[self.next_eight_bits() as usize];
-                let register_two = self.registers[self.next_eight_bits() as usize];
-                self.equal_flag = register_one >= register_two;
-                self.next_eight_bits();
-            }
-            Opcode::LTQ => {
-                let register_one = self.registers[self.next_eight_bits() as usize];
-                let register_two = self.registers[self.next_eight_bits() as usize];
-                self.equal_flag = register_one <= register_two;
-                self.next_eight_bits();
-            }
-            Opcode::JEQ => {
-                let register = self.next_eight_bits() as usize;
-                let target = self.registers[register];
-                if self.equal_flag {
-                    self.pc = target as usize;
-                }
+    /// Decodes each instruction from `start` to the end of the program. An instruction that runs past the end is left
+    /// out, so reaching it still crashes the usual way.
+    fn decode_program(&self, start: usize) -> Vec<Option<DecodedInstruction>> {
+        let mut decoded = vec![None; self.program.len()];
+        let mut offset = start;
+        while offset < self.program.len() {
+            let info = &OPCODES[self.program[offset] as usize];
+            if offset + info.width > self.program.len() {
+                break;
             }
-            Opcode::JNEQ => {
-                let register = self.next_eight_bits() as usize;
-                let target = self.registers[register];
-                if !self.equal_flag {
-                    self.pc = target as usize;
-                }
-            }
-            Opcode::ALOC => {
-                let register = self.next_eight_bits() as usize;
-                let bytes = self.registers[register];
-                let new_end = self.heap.len() as i32 + bytes;
-                self.heap.resize(new_end as usize, 0);
-            }
-            Opcode::IGL => {
-                println!("Illegal instruction encountered");
-                // This was false
-                return Some(1);
-            }
-            Opcode::INC => {
-                let register = self.next_eight_bits() as usize;
-                self.registers[register] += 1;
-                self.next_eight_bits();
-                self.next_eight_bits();
-            }
-            Opcode::DEC => {
-                let register = self.next_eight_bits() as usize;
-                self.registers[register] -= 1;
-                self.next_eight_bits();
-                self.next_eight_bits();
-            }
-            Opcode::LUI => {
-                let register = self.next_eight_bits() as usize;
-                let value = self.registers[register];
-                let uv1 = i32::from(self.next_eight_bits());
-                let uv2 = i32::from(self.next_eight_bits());
-                let value = value.checked_shl(8).unwrap();
-                let value = value | uv1;
-                let value = value.checked_shl(8).unwrap();
-                let value = value | uv2;
-                self.registers[register] = value;
-            }
-            Opcode::PRTS => {
-                let starting_offset = self.next_sixteen_bits() as usize;
-                let mut ending_offset = starting_offset;
-                let slice = self.ro_data.as_slice();
-                while slice[ending_offset] != 0 {
-                    ending_offset += 1;
-                }
-                let result = std::str::from_utf8(&slice[starting_offset..ending_offset]);
-                match result {
-                    Ok(s) => {
-                        print!("{}", s);
-                    }
-                    Err(e) => {
-                        println!("Error decoding string for prts instruction: {:#?}", e)
-                    }
-                };
+            decoded[offset] = Some(DecodedInstruction {
+                opcode: info.opcode,
+                handler: info.handler,
+                operands: Operands::decode(info, &self.program[offset..]),
+            });
+            offset += info.width;
+        }
+        decoded
+    }
+
+    /// Executes the instruction at `pc`, returning the event that stopped the VM if it should not keep going
+    fn execute_instruction(&mut self) -> Option<VMEventType> {
+        // Jumps can land in the middle of a decoded instruction, which then gets decoded from the bytes below
+        if let Some(Some(instruction)) = self.decoded.get(self.pc) {
+            let instruction = *instruction;
+            if self.trace || self.profile {
+                self.record_instruction(instruction.opcode, instruction.opcode.width());
             }
+            self.pc += instruction.opcode.width();
+            return (instruction.handler)(self, instruction.operands);
+        }
+
+        if self.pc >= self.program.len() {
+            return Some(VMEventType::GracefulStop { code: 1 });
+        }
+
+        let info = &OPCODES[self.program[self.pc] as usize];
+        if self.pc + info.width > self.program.len() {
+            error!(
+                "Instruction at {} runs past the end of the program",
+                self.pc
+            );
+            return Some(VMEventType::Crash { code: 1 });
+        }
+        if self.trace || self.profile {
+            self.record_instruction(info.opcode, info.width);
+        }
+        let operands = Operands::decode(info, &self.program[self.pc..]);
+        self.pc += info.width;
+        (info.handler)(self, operands)
+    }
+
+    /// The handler that runs `opcode`, for building `OPCODES`
+    const fn handler(opcode: Opcode) -> Handler {
+        match opcode {
+            Opcode::ADD => VirtualMachine::execute_add,
+            Opcode::MIN => VirtualMachine::execute_min,
+            Opcode::MAX => VirtualMachine::execute_max,
+            Opcode::SUB => VirtualMachine::execute_sub,
+            Opcode::MUL => VirtualMachine::execute_mul,
+            Opcode::DIV => VirtualMachine::execute_div,
+            Opcode::DIVU => VirtualMachine::execute_divu,
+            Opcode::LOAD => VirtualMachine::execute_load,
+            Opcode::LOADW => VirtualMachine::execute_loadw,
+            Opcode::LOADF => VirtualMachine::execute_loadf,
+            Opcode::HLT => VirtualMachine::execute_hlt,
+            Opcode::JMP => VirtualMachine::execute_jmp,
+            Opcode::JMPB => VirtualMachine::execute_jmpb,
+            Opcode::JMPF => VirtualMachine::execute_jmpf,
+            Opcode::JMPFI => VirtualMachine::execute_jmpfi,
+            Opcode::JMPBI => VirtualMachine::execute_jmpbi,
+            Opcode::EQ => VirtualMachine::execute_eq,
+            Opcode::NEQ => VirtualMachine::execute_neq,
+            Opcode::GT => VirtualMachine::execute_gt,
+            Opcode::LT => VirtualMachine::execute_lt,
+            Opcode::GTQ => VirtualMachine::execute_gtq,
+            Opcode::LTQ => VirtualMachine::execute_ltq,
+            Opcode::JEQ => VirtualMachine::execute_jeq,
+            Opcode::JNEQ => VirtualMachine::execute_jneq,
+            Opcode::JMPE => VirtualMachine::execute_jmpe,
+            Opcode::JLT => VirtualMachine::execute_jlt,
+            Opcode::JGT => VirtualMachine::execute_jgt,
+            Opcode::JLE => VirtualMachine::execute_jle,
+            Opcode::JGE => VirtualMachine::execute_jge,
+            Opcode::NOP => VirtualMachine::execute_nop,
+            Opcode::MOV => VirtualMachine::execute_mov,
+            Opcode::CMOV => VirtualMachine::execute_cmov,
+            Opcode::NEG => VirtualMachine::execute_neg,
+            Opcode::ABS => VirtualMachine::execute_abs,
+            Opcode::MOVF => VirtualMachine::execute_movf,
+            Opcode::ALOC => VirtualMachine::execute_aloc,
+            Opcode::TIME => VirtualMachine::execute_time,
+            Opcode::GETPC => VirtualMachine::execute_getpc,
+            Opcode::RAND => VirtualMachine::execute_rand,
+            Opcode::STOREM => VirtualMachine::execute_storem,
+            Opcode::LOADB => VirtualMachine::execute_loadb,
+            Opcode::STOREB => VirtualMachine::execute_storeb,
+            Opcode::BLKCPY => VirtualMachine::execute_blkcpy,
+            Opcode::LOADIDX => VirtualMachine::execute_loadidx,
+            Opcode::STOREIDX => VirtualMachine::execute_storeidx,
+            Opcode::IGL => VirtualMachine::execute_igl,
+            Opcode::INC => VirtualMachine::execute_inc,
+            Opcode::DEC => VirtualMachine::execute_dec,
+            Opcode::LUI => VirtualMachine::execute_lui,
+            Opcode::PRTS => VirtualMachine::execute_prts,
+            Opcode::MOVREM => VirtualMachine::execute_movrem,
+            Opcode::SYSCALL => VirtualMachine::execute_syscall,
+            Opcode::READ => VirtualMachine::execute_read,
+            Opcode::PUSH => VirtualMachine::execute_push,
+            Opcode::POP => VirtualMachine::execute_pop,
+            Opcode::CALL => VirtualMachine::execute_call,
+            Opcode::RET => VirtualMachine::execute_ret,
+            Opcode::LOADL => VirtualMachine::execute_loadl,
+            Opcode::STOREL => VirtualMachine::execute_storel,
+            Opcode::DUMP => VirtualMachine::execute_dump,
+            Opcode::DUMPF => VirtualMachine::execute_dumpf,
+            Opcode::PRTSF => VirtualMachine::execute_prtsf,
         }
+    }
+
+    fn execute_add(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register_one = self.registers[operands.register(0)];
+        let register_two = self.registers[operands.register(1)];
+        let destination = operands.register(2);
+        self.registers[destination] = register_one + register_two;
         None
     }
 
-    pub fn print_i32_register(&self, register: usize) {
-        let bits = self.registers[register];
-        println!("bits: {:#032b}", bits);
+    fn execute_min(&mut self, operands: Operands) -> Option<VMEventType> {
+        let destination = operands.register(0);
+        let register_one = self.registers[operands.register(1)];
+        let register_two = self.registers[operands.register(2)];
+        self.registers[destination] = register_one.min(register_two);
+        None
     }
 
-    fn decode_opcode(&mut self) -> Opcode {
-        let opcode = Opcode::from(self.program[self.pc]);
-        self.pc += 1;
-        return opcode;
+    fn execute_max(&mut self, operands: Operands) -> Option<VMEventType> {
+        let destination = operands.register(0);
+        let register_one = self.registers[operands.register(1)];
+        let register_two = self.registers[operands.register(2)];
+        self.registers[destination] = register_one.max(register_two);
+        None
     }
 
-    fn get_starting_offset(&self) -> usize {
-        let mut rdr = Cursor::new(&self.program[64..68]);
-        rdr.read_i32::<LittleEndian>().unwrap() as usize
+    fn execute_sub(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register_one = self.registers[operands.register(0)];
+        let register_two = self.registers[operands.register(1)];
+        let destination = operands.register(2);
+        self.registers[destination] = register_one - register_two;
+        None
     }
 
-    fn _i32_to_bytes(num: i32) -> [u8; 4] {
-        let mut buf: [u8; 4] = [0, 0, 0, 0];
-        buf.as_mut().write_i32::<LittleEndian>(num).unwrap();
-        buf
+    fn execute_mul(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register_one = self.registers[operands.register(0)];
+        let register_two = self.registers[operands.register(1)];
+        let destination = operands.register(2);
+        self.registers[destination] = register_one * register_two;
+        None
+    }
+
+    fn execute_div(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register_one = self.registers[operands.register(0)];
+        let register_two = self.registers[operands.register(1)];
+        let destination = operands.register(2);
+        self.registers[destination] = register_one / register_two;
+        self.remainder = (register_one % register_two) as u32;
+        None
     }
 
-    fn next_eight_bits(&mut self) -> u8 {
-        let result = self.program[self.pc];
-        self.pc += 1;
-        return result;
+    fn execute_divu(&mut self, operands: Operands) -> Option<VMEventType> {
+        // The registers hold i32s; `as u32` keeps their bits and reads them as unsigned
+        let register_one = self.registers[operands.register(0)] as u32;
+        let register_two = self.registers[operands.register(1)] as u32;
+        let destination = operands.register(2);
+        if register_two == 0 {
+            error!("divu by zero");
+            return Some(VMEventType::Crash {
+                code: DIVIDE_BY_ZERO_CODE,
+            });
+        }
+        self.registers[destination] = (register_one / register_two) as i32;
+        self.remainder = register_one % register_two;
+        None
     }
 
-    fn next_sixteen_bits(&mut self) -> u16 {
-        let result = ((self.program[self.pc] as u16) << 8) | self.program[self.pc + 1] as u16;
-        self.pc += 2;
-        return result;
+    fn execute_load(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        let number = operands.immediate as u16;
+        self.registers[register] = number as i32;
+        None
     }
 
-    pub fn prepend_header(mut b: Vec<u8>) -> Vec<u8> {
-        let mut prepension = vec![];
-        for byte in PIE_HEADER_PREFIX.into_iter() {
-            prepension.push(byte.clone());
+    fn execute_loadw(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        self.registers[register] = operands.immediate as i32;
+        None
+    }
+
+    fn execute_loadf(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        self.float_registers[register] = f64::from_bits(operands.immediate);
+        None
+    }
+
+    fn execute_hlt(&mut self, _operands: Operands) -> Option<VMEventType> {
+        println!("HLT encountered");
+        // A stopped VM is left just past the opcode that stopped it
+        self.pc -= Opcode::HLT.width() - 1;
+        Some(VMEventType::GracefulStop { code: 1 })
+    }
+
+    fn execute_jmp(&mut self, operands: Operands) -> Option<VMEventType> {
+        let target = self.registers[operands.register(0)];
+        self.pc = target as usize;
+        None
+    }
+
+    fn execute_jmpb(&mut self, operands: Operands) -> Option<VMEventType> {
+        let value = self.registers[operands.register(0)];
+        // Counts back from the byte after the register operand
+        let from = self.pc - 2;
+        match from.checked_sub(value as usize) {
+            Some(target) if value >= 0 => self.pc = target,
+            _ => {
+                error!(
+                    "jmpb by {} would jump before the start of the program from {}",
+                    value, from
+                );
+                return Some(VMEventType::Crash { code: 1 });
+            }
         }
+        None
+    }
 
-        while prepension.len() < PIE_HEADER_LENGTH + 4 {
-            prepension.push(0);
+    fn execute_jmpf(&mut self, operands: Operands) -> Option<VMEventType> {
+        let value = self.registers[operands.register(0)];
+        // Counts on from the byte after the register operand
+        self.pc = self.pc - 2 + value as usize;
+        None
+    }
+
+    fn execute_jmpfi(&mut self, operands: Operands) -> Option<VMEventType> {
+        let start = self.pc - Opcode::JMPFI.width();
+        let offset = operands.immediate as usize;
+        self.pc = start + offset;
+        None
+    }
+
+    fn execute_jmpbi(&mut self, operands: Operands) -> Option<VMEventType> {
+        let start = self.pc - Opcode::JMPBI.width();
+        let offset = operands.immediate as usize;
+        match start.checked_sub(offset) {
+            Some(target) => self.pc = target,
+            None => {
+                error!(
+                    "jmpbi offset {} would jump before the start of the program from {}",
+                    offset, start
+                );
+                return Some(VMEventType::Crash { code: 1 });
+            }
         }
-        prepension.append(&mut b);
-        prepension
+        None
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn execute_eq(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register_one = self.registers[operands.register(0)];
+        let register_two = self.registers[operands.register(1)];
+        if register_one == register_two {
+            self.equal_flag = true;
+        } else {
+            self.equal_flag = false;
+        }
+        None
+    }
 
-    #[test]
-    fn create_virtual_machine() {
-        let vm = VirtualMachine::new();
-        assert_eq!(vm.registers[0], 0);
-        assert_eq!(vm.pc, 0);
+    fn execute_neq(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register_one = self.registers[operands.register(0)];
+        let register_two = self.registers[operands.register(1)];
+        self.equal_flag = register_one != register_two;
+        None
     }
 
-    #[test]
-    fn opcode_hlt() {
-        let mut vm = VirtualMachine::new();
-        let bytes = vec![5, 0, 0, 0];
-        vm.program = bytes;
-        vm.run_once();
-        assert_eq!(vm.pc, 1);
+    fn execute_gt(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register_one = self.registers[operands.register(0)];
+        let register_two = self.registers[operands.register(1)];
+        self.equal_flag = register_one > register_two;
+        None
     }
 
+    fn execute_lt(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register_one = self.registers[operands.register(0)];
+        let register_two = self.registers[operands.register(1)];
+        self.equal_flag = register_one < register_two;
+        None
+    }
+
+    fn execute_gtq(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register_one = self.registers[operands.register(0)];
+        let register_two = self.registers[operands.register(1)];
+        self.equal_flag = register_one >= register_two;
+        None
+    }
+
+    fn execute_ltq(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register_one = self.registers[operands.register(0)];
+        let register_two = self.registers[operands.register(1)];
+        self.equal_flag = register_one <= register_two;
+        None
+    }
+
+    fn execute_jeq(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        let target = self.registers[register];
+        if self.equal_flag {
+            self.pc = target as usize;
+        }
+        None
+    }
+
+    fn execute_jneq(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        let target = self.registers[register];
+        if !self.equal_flag {
+            self.pc = target as usize;
+        }
+        None
+    }
+
+    fn execute_jmpe(&mut self, operands: Operands) -> Option<VMEventType> {
+        let target = operands.immediate;
+        if self.equal_flag {
+            self.pc = target as usize;
+        }
+        None
+    }
+
+    fn execute_jlt(&mut self, operands: Operands) -> Option<VMEventType> {
+        let (register_one, register_two, target) = self.compare_jump_operands(operands);
+        if register_one < register_two {
+            self.pc = target;
+        }
+        None
+    }
+
+    fn execute_jgt(&mut self, operands: Operands) -> Option<VMEventType> {
+        let (register_one, register_two, target) = self.compare_jump_operands(operands);
+        if register_one > register_two {
+            self.pc = target;
+        }
+        None
+    }
+
+    fn execute_jle(&mut self, operands: Operands) -> Option<VMEventType> {
+        let (register_one, register_two, target) = self.compare_jump_operands(operands);
+        if register_one <= register_two {
+            self.pc = target;
+        }
+        None
+    }
+
+    fn execute_jge(&mut self, operands: Operands) -> Option<VMEventType> {
+        let (register_one, register_two, target) = self.compare_jump_operands(operands);
+        if register_one >= register_two {
+            self.pc = target;
+        }
+        None
+    }
+
+    fn execute_nop(&mut self, _operands: Operands) -> Option<VMEventType> {
+        None
+    }
+
+    fn execute_mov(&mut self, operands: Operands) -> Option<VMEventType> {
+        let destination = operands.register(0);
+        let source = operands.register(1);
+        self.registers[destination] = self.registers[source];
+        None
+    }
+
+    fn execute_cmov(&mut self, operands: Operands) -> Option<VMEventType> {
+        let destination = operands.register(0);
+        let source = operands.register(1);
+        if self.equal_flag {
+            self.registers[destination] = self.registers[source];
+        }
+        None
+    }
+
+    fn execute_neg(&mut self, operands: Operands) -> Option<VMEventType> {
+        let destination = operands.register(0);
+        let source = operands.register(1);
+        self.registers[destination] = self.registers[source].wrapping_neg();
+        None
+    }
+
+    fn execute_abs(&mut self, operands: Operands) -> Option<VMEventType> {
+        let destination = operands.register(0);
+        let source = operands.register(1);
+        self.registers[destination] = self.registers[source].wrapping_abs();
+        None
+    }
+
+    fn execute_movf(&mut self, operands: Operands) -> Option<VMEventType> {
+        let destination = operands.register(0);
+        let source = operands.register(1);
+        self.float_registers[destination] = self.float_registers[source];
+        None
+    }
+
+    fn execute_aloc(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        let bytes = self.registers[register];
+        let new_end = self.heap.len() as i64 + bytes as i64;
+        if new_end < 0 {
+            error!("aloc of {} bytes would shrink the heap below zero", bytes);
+            return Some(VMEventType::Crash { code: 1 });
+        }
+        let max_heap = self
+            .max_heap
+            .map_or(MMIO_START, |max_heap| max_heap.min(MMIO_START));
+        if new_end as usize > max_heap {
+            error!(
+                "aloc of {} bytes would grow the heap past its maximum of {}",
+                bytes, max_heap
+            );
+            return Some(VMEventType::Crash { code: 1 });
+        }
+        self.heap.resize(new_end as usize, 0);
+        None
+    }
+
+    fn execute_time(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        let elapsed = (self.clock)() - self.started_at;
+        self.registers[register] = elapsed.num_milliseconds() as i32;
+        None
+    }
+
+    fn execute_getpc(&mut self, operands: Operands) -> Option<VMEventType> {
+        // `pc` has already moved past the instruction
+        let address = self.pc - Opcode::GETPC.width();
+        let register = operands.register(0);
+        self.registers[register] = address as i32;
+        None
+    }
+
+    fn execute_rand(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        self.registers[register] = self.rng.next_i32();
+        None
+    }
+
+    fn execute_storem(&mut self, operands: Operands) -> Option<VMEventType> {
+        let address = self.registers[operands.register(0)] as u32 as usize;
+        let value = self.registers[operands.register(1)];
+        if let Err(e) = self.store_word(address, value) {
+            return Some(e);
+        }
+        None
+    }
+
+    fn execute_loadb(&mut self, operands: Operands) -> Option<VMEventType> {
+        let destination = operands.register(0);
+        let address = self.registers[operands.register(1)] as u32 as usize;
+        match self.heap.get(address) {
+            Some(byte) => self.registers[destination] = i32::from(*byte),
+            None => {
+                error!(
+                    "loadb from {} is past the end of the heap at {}",
+                    address,
+                    self.heap.len()
+                );
+                return Some(VMEventType::Crash { code: 1 });
+            }
+        }
+        None
+    }
+
+    fn execute_storeb(&mut self, operands: Operands) -> Option<VMEventType> {
+        let address = self.registers[operands.register(0)] as u32 as usize;
+        let value = self.registers[operands.register(1)] as u8;
+        if (MMIO_START..MMIO_END).contains(&address) {
+            self.write_mmio(address, i32::from(value));
+        } else if let Some(byte) = self.heap.get_mut(address) {
+            *byte = value;
+        } else {
+            error!(
+                "storeb to {} is past the end of the heap at {}",
+                address,
+                self.heap.len()
+            );
+            return Some(VMEventType::Crash { code: 1 });
+        }
+        None
+    }
+
+    fn execute_blkcpy(&mut self, operands: Operands) -> Option<VMEventType> {
+        let destination = self.registers[operands.register(0)];
+        let source = self.registers[operands.register(1)];
+        let length = self.registers[operands.register(2)];
+        let in_heap = |start: i32| {
+            start >= 0 && length >= 0 && start as usize + length as usize <= self.heap.len()
+        };
+        if !in_heap(destination) || !in_heap(source) {
+            error!(
+                "blkcpy of {} bytes from {} to {} is outside the heap",
+                length, source, destination
+            );
+            return Some(VMEventType::Crash { code: 1 });
+        }
+        let (destination, source, length) =
+            (destination as usize, source as usize, length as usize);
+        self.heap.copy_within(source..source + length, destination);
+        None
+    }
+
+    fn execute_loadidx(&mut self, operands: Operands) -> Option<VMEventType> {
+        let destination = operands.register(0);
+        let base = self.registers[operands.register(1)];
+        let index = self.registers[operands.register(2)];
+        let address = match VirtualMachine::element_address(base, index) {
+            Some(address) if address + 4 <= self.heap.len() => address,
+            _ => {
+                error!(
+                    "loadidx of element {} of the array at {} is outside the heap",
+                    index, base
+                );
+                return Some(VMEventType::Crash { code: 1 });
+            }
+        };
+        self.registers[destination] = DataEndian::read_i32(&self.heap[address..address + 4]);
+        None
+    }
+
+    fn execute_storeidx(&mut self, operands: Operands) -> Option<VMEventType> {
+        let base = self.registers[operands.register(0)];
+        let index = self.registers[operands.register(1)];
+        let value = self.registers[operands.register(2)];
+        let address = match VirtualMachine::element_address(base, index) {
+            Some(address) => address,
+            None => {
+                error!(
+                    "storeidx to element {} of the array at {} is outside the heap",
+                    index, base
+                );
+                return Some(VMEventType::Crash { code: 1 });
+            }
+        };
+        if let Err(e) = self.store_word(address, value) {
+            return Some(e);
+        }
+        None
+    }
+
+    fn execute_igl(&mut self, _operands: Operands) -> Option<VMEventType> {
+        println!("Illegal instruction encountered");
+        // Like HLT, the VM is left just past the opcode
+        self.pc -= Opcode::IGL.width() - 1;
+        // This was false
+        Some(VMEventType::GracefulStop { code: 1 })
+    }
+
+    fn execute_inc(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        self.registers[register] += 1;
+        None
+    }
+
+    fn execute_dec(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        self.registers[register] -= 1;
+        None
+    }
+
+    fn execute_lui(&mut self, operands: Operands) -> Option<VMEventType> {
+        // Puts the immediate in the upper 16 bits, keeping the lower 16 bits a preceding LOAD put there
+        let register = operands.register(0);
+        let upper = operands.immediate as i32;
+        let lower = self.registers[register] & 0xFFFF;
+        self.registers[register] = (upper << 16) | lower;
+        None
+    }
+
+    fn execute_prts(&mut self, operands: Operands) -> Option<VMEventType> {
+        let starting_offset = operands.immediate as usize;
+        // PRTS only has a 16-bit operand, so skip the padding byte
+        self.print_ro_string(starting_offset)
+    }
+
+    fn execute_movrem(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        self.registers[register] = self.remainder as i32;
+        None
+    }
+
+    fn execute_syscall(&mut self, operands: Operands) -> Option<VMEventType> {
+        let number = operands.immediate as u16;
+        let register = operands.register(0);
+        match number {
+            SYSCALL_READ_INTEGER => match self.syscalls.read_integer() {
+                Some(value) => self.registers[register] = value,
+                None => {
+                    error!("Unable to read an integer for syscall");
+                    return Some(VMEventType::Crash { code: 1 });
+                }
+            },
+            SYSCALL_PRINT_INTEGER => self.syscalls.print_integer(self.registers[register]),
+            SYSCALL_FLUSH => self.syscalls.flush(),
+            _ => {
+                error!("Unknown syscall {}", number);
+                return Some(VMEventType::Crash { code: 1 });
+            }
+        }
+        None
+    }
+
+    fn execute_read(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        let mut line = String::new();
+        let read = match self.input.as_mut() {
+            Some(input) => input.read_line(&mut line),
+            None => io::stdin().lock().read_line(&mut line),
+        };
+        match read {
+            Ok(0) => {
+                error!("Reached the end of input during read");
+                return Some(VMEventType::Crash { code: 1 });
+            }
+            Ok(_) => match line.trim().parse::<i32>() {
+                Ok(value) => {
+                    self.registers[register] = value;
+                    self.equal_flag = true;
+                }
+                Err(_) => self.equal_flag = false,
+            },
+            Err(e) => {
+                error!("Unable to read input: {}", e);
+                return Some(VMEventType::Crash { code: 1 });
+            }
+        }
+        None
+    }
+
+    fn execute_push(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        if let Some(event) = self.push_stack(self.registers[register]) {
+            return Some(event);
+        }
+        None
+    }
+
+    fn execute_pop(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        match self.pop_stack() {
+            Ok(value) => self.registers[register] = value,
+            Err(event) => return Some(event),
+        }
+        None
+    }
+
+    fn execute_call(&mut self, operands: Operands) -> Option<VMEventType> {
+        let target = operands.immediate as usize;
+        // The return address and the caller's frame pointer go on the stack together or not at all
+        if self.stack.len() + 2 > self.stack_limit {
+            error!(
+                "Stack overflow: no room for a new frame at {} values",
+                self.stack.len()
+            );
+            return Some(VMEventType::Crash {
+                code: STACK_OVERFLOW_CODE,
+            });
+        }
+        self.stack.push(self.pc as i32);
+        self.stack.push(self.bp as i32);
+        self.sp = self.stack.len();
+        self.bp = self.sp;
+        self.pc = target;
+        if self.banked_registers {
+            self.register_banks.push(self.registers.clone());
+        }
+        None
+    }
+
+    fn execute_ret(&mut self, _operands: Operands) -> Option<VMEventType> {
+        if self.bp < 2 || self.bp > self.stack.len() {
+            error!("ret without a matching call");
+            return Some(VMEventType::Crash { code: 1 });
+        }
+        self.stack.truncate(self.bp);
+        let caller_bp = self.stack.pop().unwrap();
+        let address = self.stack.pop().unwrap();
+        self.sp = self.stack.len();
+        self.bp = caller_bp as usize;
+        self.pc = address as usize;
+        if let Some(bank) = self.register_banks.pop() {
+            let result = self.registers[0];
+            self.registers = bank;
+            self.registers[0] = result;
+        }
+        None
+    }
+
+    fn execute_loadl(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        let slot = self.bp + operands.immediate as usize;
+        match self.stack.get(slot) {
+            Some(value) => self.registers[register] = *value,
+            None => {
+                error!("loadl from stack slot {} past the top of the stack", slot);
+                return Some(VMEventType::Crash { code: 1 });
+            }
+        }
+        None
+    }
+
+    fn execute_storel(&mut self, operands: Operands) -> Option<VMEventType> {
+        let slot = self.bp + operands.immediate as usize;
+        let value = self.registers[operands.register(0)];
+        if slot >= self.stack_limit {
+            error!("Stack overflow: storel to stack slot {}", slot);
+            return Some(VMEventType::Crash {
+                code: STACK_OVERFLOW_CODE,
+            });
+        }
+        if slot >= self.stack.len() {
+            self.stack.resize(slot + 1, 0);
+            self.sp = self.stack.len();
+        }
+        self.stack[slot] = value;
+        None
+    }
+
+    fn execute_dump(&mut self, _operands: Operands) -> Option<VMEventType> {
+        print!("{}", self.register_dump());
+        None
+    }
+
+    fn execute_dumpf(&mut self, _operands: Operands) -> Option<VMEventType> {
+        print!("{}", self.float_register_dump());
+        None
+    }
+
+    fn execute_prtsf(&mut self, operands: Operands) -> Option<VMEventType> {
+        let register = operands.register(0);
+        let starting_offset = self.registers[register];
+        if starting_offset < 0 {
+            error!(
+                "Negative read-only data offset {} for prtsf instruction",
+                starting_offset
+            );
+            return Some(VMEventType::Crash { code: 1 });
+        }
+        self.print_ro_string(starting_offset as usize)
+    }
+
+    /// Pushes a value onto the stack, crashing if the stack is already at its limit
+    fn push_stack(&mut self, value: i32) -> Option<VMEventType> {
+        if self.stack.len() >= self.stack_limit {
+            error!(
+                "Stack overflow: the stack is full at {} values",
+                self.stack_limit
+            );
+            return Some(VMEventType::Crash {
+                code: STACK_OVERFLOW_CODE,
+            });
+        }
+        self.stack.push(value);
+        self.sp = self.stack.len();
+        None
+    }
+
+    /// Pops a value off the stack, crashing if the stack is empty
+    fn pop_stack(&mut self) -> Result<i32, VMEventType> {
+        match self.stack.pop() {
+            Some(value) => {
+                self.sp = self.stack.len();
+                Ok(value)
+            }
+            None => {
+                error!("Stack underflow: popped from an empty stack");
+                Err(VMEventType::Crash { code: 1 })
+            }
+        }
+    }
+
+    /// Prints the null-terminated string that starts at `starting_offset` in the read-only data. Crashes rather than
+    /// reading past the end of the read-only data if there is no terminator.
+    fn print_ro_string(&self, starting_offset: usize) -> Option<VMEventType> {
+        let slice = self.ro_data.as_slice();
+        let ending_offset = match slice.iter().skip(starting_offset).position(|b| *b == 0) {
+            Some(length) => starting_offset + length,
+            None => {
+                error!(
+                    "No null terminator in read-only data after offset {}",
+                    starting_offset
+                );
+                return Some(VMEventType::Crash { code: 1 });
+            }
+        };
+        let result = std::str::from_utf8(&slice[starting_offset..ending_offset]);
+        match result {
+            Ok(s) => {
+                print!("{}", s);
+            }
+            Err(e) => {
+                println!("Error decoding string for prts instruction: {:#?}", e)
+            }
+        };
+        None
+    }
+
+    /// Formats the integer registers eight to a line, followed by the equal flag, the way DUMP prints them
+    pub fn register_dump(&self) -> String {
+        let mut dump = String::new();
+        for (row, registers) in self.registers.chunks(8).enumerate() {
+            let line: Vec<String> = registers
+                .iter()
+                .enumerate()
+                .map(|(i, value)| format!("${}: {}", row * 8 + i, value))
+                .collect();
+            dump.push_str(&line.join("\t"));
+            dump.push('\n');
+        }
+        dump.push_str(&format!("equal_flag: {}\n", self.equal_flag));
+        dump
+    }
+
+    /// Formats the float registers eight to a line, the way DUMPF prints them
+    pub fn float_register_dump(&self) -> String {
+        let mut dump = String::new();
+        for (row, registers) in self.float_registers.chunks(8).enumerate() {
+            let line: Vec<String> = registers
+                .iter()
+                .enumerate()
+                .map(|(i, value)| format!("${}: {}", row * 8 + i, value))
+                .collect();
+            dump.push_str(&line.join("\t"));
+            dump.push('\n');
+        }
+        dump
+    }
+
+    pub fn print_i32_register(&self, register: usize) {
+        let bits = self.registers[register];
+        println!("bits: {:#032b}", bits);
+    }
+
+    /// Logs and counts the instruction at `pc` when tracing or profiling. Kept out of `execute_instruction` so the
+    /// usual case, with both off, stays small.
+    #[cold]
+    fn record_instruction(&mut self, opcode: Opcode, width: usize) {
+        if self.trace {
+            let end = self.pc + width;
+            for line in disassemble_instructions(&self.program[self.pc..end]) {
+                info!("pc {}: {}", self.pc, line);
+            }
+        }
+        if self.profile {
+            *self.opcode_counts.entry(opcode).or_insert(0) += 1;
+        }
+    }
+
+    fn get_starting_offset(&self) -> usize {
+        DataEndian::read_u32(&self.program[PIE_HEADER_LENGTH..PIE_HEADER_LENGTH + 4]) as usize
+    }
+
+    fn get_entry_offset(&self) -> usize {
+        DataEndian::read_u32(&self.program[PIE_HEADER_ENTRY_OFFSET..PIE_HEADER_ENTRY_OFFSET + 4])
+            as usize
+    }
+
+    fn get_ro_length(&self) -> usize {
+        DataEndian::read_u32(
+            &self.program[PIE_HEADER_RO_LENGTH_OFFSET..PIE_HEADER_RO_LENGTH_OFFSET + 4],
+        ) as usize
+    }
+
+    fn _i32_to_bytes(num: i32) -> [u8; 4] {
+        let mut buf: [u8; 4] = [0, 0, 0, 0];
+        buf.as_mut().write_i32::<DataEndian>(num).unwrap();
+        buf
+    }
+
+    /// The operands of the compare-and-jump opcodes: the values of two registers and the jump target
+    fn compare_jump_operands(&self, operands: Operands) -> (i32, i32, usize) {
+        let register_one = self.registers[operands.register(0)];
+        let register_two = self.registers[operands.register(1)];
+        (register_one, register_two, operands.immediate as usize)
+    }
+
+    pub fn prepend_header(mut b: Vec<u8>) -> Vec<u8> {
+        let mut prepension = vec![];
+        for byte in PIE_HEADER_PREFIX.into_iter() {
+            prepension.push(byte.clone());
+        }
+        prepension.push(BYTECODE_VERSION);
+
+        while prepension.len() < PIE_HEADER_LENGTH + 4 {
+            prepension.push(0);
+        }
+        prepension.append(&mut b);
+        prepension
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::io::Cursor;
+    use std::rc::Rc;
+
     #[test]
-    fn opcode_igl() {
+    fn create_virtual_machine() {
+        let vm = VirtualMachine::new();
+        assert_eq!(vm.registers[0], 0);
+        assert_eq!(vm.pc, 0);
+    }
+
+    #[test]
+    fn reset_and_rerun() {
+        let mut vm = VirtualMachine::new().with_alias("test".to_string());
+        vm.program = VirtualMachine::prepend_header(vec![0, 0, 1, 244, 18, 0, 0, 0, 9, 0, 0, 0]);
+        vm.run();
+        assert_eq!(vm.registers[0], 501);
+        assert!(vm.equal_flag);
+
+        vm.reset();
+        assert_eq!(vm.registers[0], 0);
+        assert_eq!(vm.pc, 0);
+        assert!(!vm.equal_flag);
+        assert!(vm.events.is_empty());
+        assert_eq!(vm.alias, Some("test".to_string()));
+
+        vm.run();
+        let events = vm.events();
+        assert_eq!(vm.registers[0], 501);
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn opcode_hlt() {
         let mut vm = VirtualMachine::new();
-        let bytes = vec![254, 0, 0, 0];
+        let bytes = vec![5, 0, 0, 0];
         vm.program = bytes;
         vm.run_once();
         assert_eq!(vm.pc, 1);
     }
 
     #[test]
-    fn opcode_load() {
+    fn step_continues() {
+        let mut vm = VirtualMachine::new();
+        vm.program = vec![0, 0, 1, 244, 5, 0, 0, 0];
+        assert!(vm.step().is_none());
+        assert_eq!(vm.registers[0], 500);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn step_halts() {
+        let mut vm = VirtualMachine::new();
+        vm.program = vec![5, 0, 0, 0];
+        match vm.step() {
+            Some(VMEventType::GracefulStop { code }) => assert_eq!(code, 1),
+            e => panic!("Expected a graceful stop, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn opcode_igl() {
+        let mut vm = VirtualMachine::new();
+        let bytes = vec![254, 0, 0, 0];
+        vm.program = bytes;
+        vm.run_once();
+        assert_eq!(vm.pc, 1);
+    }
+
+    #[test]
+    fn test_dispatch_unknown_bytes() {
+        // Every byte that isn't an opcode goes to the IGL handler
+        for byte in (0..=255u8).filter(|byte| Opcode::from(*byte) == Opcode::IGL) {
+            let mut vm = VirtualMachine::new();
+            vm.program = vec![byte, 0, 0, 0];
+            match vm.execute_instruction() {
+                Some(VMEventType::GracefulStop { .. }) => {}
+                e => panic!("Expected byte {} to stop the VM, got {:?}", byte, e),
+            }
+            assert_eq!(vm.pc, 1);
+        }
+    }
+
+    #[test]
+    fn test_dispatch_loop() {
+        let mut vm = VirtualMachine::get_test_vm();
+        // load $0 #0; load $2 #3; load $1 #1000; top: inc $0; add $0 $2 $3; mul $3 $2 $4; jlt $0 $1 @top; hlt
+        vm.program = vec![
+            0, 0, 0, 0, 0, 2, 0, 3, 0, 1, 0x03, 0xE8, 18, 0, 0, 0, 1, 0, 2, 3, 3, 3, 2, 4, 43, 0,
+            1, 0, 12, 5, 0, 0, 0,
+        ];
+        vm.run_headerless();
+        assert_eq!(vm.registers[0], 1000);
+        assert_eq!(vm.registers[3], 1003);
+        assert_eq!(vm.registers[4], 3009);
+        assert_eq!(vm.pc, 30);
+    }
+
+    #[test]
+    fn opcode_load() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![0, 0, 1, 244];
+        vm.program = VirtualMachine::prepend_header(vm.program);
+        vm.run();
+        assert_eq!(vm.registers[0], 500);
+    }
+
+    #[test]
+    fn test_add_opcode() {
+        let mut test_vm = VirtualMachine::get_test_vm();
+        test_vm.program = vec![1, 0, 1, 2];
+        test_vm.program = VirtualMachine::prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.registers[2], 15);
+    }
+
+    #[test]
+    fn opcode_min_max() {
+        // Each case runs `min $2 $0 $1` and `max $3 $0 $1`
+        let cases = [
+            (3, 9, 3, 9),
+            (9, 3, 3, 9),
+            (4, 4, 4, 4),
+            (-5, 2, -5, 2),
+            (-5, -8, -8, -5),
+        ];
+        for &(first, second, min, max) in cases.iter() {
+            let mut vm = VirtualMachine::new();
+            vm.registers[0] = first;
+            vm.registers[1] = second;
+            vm.program = vec![50, 2, 0, 1, 51, 3, 0, 1];
+            vm.run_once();
+            vm.run_once();
+            assert_eq!(vm.registers[2], min, "min of {} and {}", first, second);
+            assert_eq!(vm.registers[3], max, "max of {} and {}", first, second);
+        }
+    }
+
+    #[test]
+    fn test_sub_opcode() {
+        let mut test_vm = VirtualMachine::get_test_vm();
+        test_vm.program = vec![2, 1, 0, 2];
+        test_vm.program = VirtualMachine::prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.registers[2], 5);
+    }
+
+    #[test]
+    fn test_mul_opcode() {
+        let mut test_vm = VirtualMachine::get_test_vm();
+        test_vm.program = vec![3, 0, 1, 2];
+        test_vm.program = VirtualMachine::prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.registers[2], 50);
+    }
+
+    #[test]
+    fn opcode_jmp() {
+        let mut vm = VirtualMachine::new();
+        vm.registers[0] = 1;
+        vm.program = vec![6, 0, 0, 0];
+        vm.run_once();
+        assert_eq!(vm.pc, 1);
+    }
+
+    #[test]
+    fn test_div_opcode() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![4, 1, 0, 2];
+        vm.program = VirtualMachine::prepend_header(vm.program);
+        vm.run();
+        assert_eq!(vm.registers[2], 2)
+    }
+
+    #[test]
+    fn opcode_divu() {
+        let mut vm = VirtualMachine::new();
+        vm.registers[0] = -2;
+        vm.registers[1] = 2;
+        vm.registers[2] = 7;
+        vm.registers[3] = 3;
+        // div $0 $1 $4; divu $0 $1 $5; divu $2 $3 $6
+        vm.program = vec![4, 0, 1, 4, 47, 0, 1, 5, 47, 2, 3, 6];
+        vm.run_once();
+        assert_eq!(vm.registers[4], -1);
+        vm.run_once();
+        assert_eq!(vm.registers[5], 0x7fff_ffff);
+        assert_eq!(vm.remainder(), 0);
+        vm.run_once();
+        assert_eq!(vm.registers[6], 2);
+        assert_eq!(vm.remainder(), 1);
+    }
+
+    #[test]
+    fn opcode_divu_sign_bit_remainder() {
+        let mut vm = VirtualMachine::new();
+        vm.registers[0] = i32::MIN;
+        vm.registers[1] = 3;
+        vm.program = vec![4, 0, 1, 2, 47, 0, 1, 3];
+        vm.run_once();
+        assert_eq!(vm.registers[2], -715827882);
+        assert_eq!(vm.remainder() as i32, -2);
+        vm.run_once();
+        assert_eq!(vm.registers[3], 715827882);
+        assert_eq!(vm.remainder(), 2);
+    }
+
+    #[test]
+    fn opcode_divu_by_zero() {
+        let mut vm = VirtualMachine::new();
+        vm.registers[0] = 10;
+        vm.program = vec![47, 0, 1, 2];
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { code }) => assert_eq!(code, DIVIDE_BY_ZERO_CODE),
+            other => panic!("Expected a crash, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_div_remainder() {
+        let mut vm = VirtualMachine::new();
+        vm.registers[0] = 17;
+        vm.registers[1] = 5;
+        vm.registers[2] = -17;
+        vm.program = vec![4, 0, 1, 3, 29, 4, 0, 0, 4, 2, 1, 3, 29, 5, 0, 0];
+        vm.run_once();
+        assert_eq!(vm.remainder(), 2);
+        vm.run_once();
+        assert_eq!(vm.registers[4], 2);
+        vm.run_once();
+        assert_eq!(vm.remainder() as i32, -2);
+        vm.run_once();
+        assert_eq!(vm.registers[5], -2);
+    }
+
+    #[test]
+    fn test_vm_with_program_comparison() {
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![14, 1, 0, 0, 5, 0, 0, 0]);
+        vm.run();
+        assert!(vm.equal_flag());
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![14, 0, 1, 0, 5, 0, 0, 0]);
+        vm.run();
+        assert!(!vm.equal_flag());
+    }
+
+    #[test]
+    fn test_run_with_header() {
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![1, 0, 1, 2, 5, 0, 0, 0]);
+        vm.run();
+        let events = vm.events();
+        assert_eq!(vm.registers[2], 15);
+        match events.last().map(|e| &e.event) {
+            Some(VMEventType::GracefulStop { .. }) => {}
+            e => panic!("Expected a graceful stop, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_run_headerless() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![1, 0, 1, 2, 5, 0, 0, 0];
+        vm.run_headerless();
+        let events = vm.events();
+        assert_eq!(vm.registers[2], 15);
+        match events.last().map(|e| &e.event) {
+            Some(VMEventType::GracefulStop { .. }) => {}
+            e => panic!("Expected a graceful stop, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_run_short_program() {
+        let mut vm = VirtualMachine::new();
+        vm.program = vec![69, 80];
+        let events = vm.run();
+        match events.last().map(|e| &e.event) {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_run_truncated_header() {
+        let mut vm = VirtualMachine::new();
+        vm.program = PIE_HEADER_PREFIX.to_vec();
+        vm.program.append(&mut vec![0; 62]);
+        let events = vm.run();
+        match events.last().map(|e| &e.event) {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_run_oldest_version() {
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![0, 3, 0, 7, 5, 0, 0, 0]);
+        vm.program[PIE_HEADER_VERSION_OFFSET] = OLDEST_BYTECODE_VERSION;
+        vm.run();
+        assert_eq!(vm.registers[3], 7);
+    }
+
+    #[test]
+    fn test_run_from_entry_point() {
+        // Skips the first LOAD; the second starts 4 bytes into the code, which is right after the header
+        let mut vm =
+            VirtualMachine::get_test_vm_with_program(vec![0, 2, 0, 9, 0, 3, 0, 7, 5, 0, 0, 0]);
+        DataEndian::write_u32(
+            &mut vm.program[PIE_HEADER_ENTRY_OFFSET..PIE_HEADER_ENTRY_OFFSET + 4],
+            (PIE_HEADER_LENGTH + 4 + 4) as u32,
+        );
+        vm.run();
+        assert_eq!(vm.registers[2], 0);
+        assert_eq!(vm.registers[3], 7);
+    }
+
+    #[test]
+    fn test_run_entry_point_outside_code() {
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![5, 0, 0, 0]);
+        vm.program[PIE_HEADER_ENTRY_OFFSET] = 4;
+        let events = vm.run();
+        match events.last().map(|e| &e.event) {
+            Some(VMEventType::Crash { code }) => assert_eq!(*code, INVALID_HEADER_CODE),
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn opcode_loadw() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![41, 0, 0xFF, 0xFF, 0xFF, 0xFE, 41, 1, 0x00, 0x01, 0x86, 0xA0];
+        vm.run_once();
+        assert_eq!(vm.pc, 6);
+        assert_eq!(vm.registers[0], -2);
+        vm.run_once();
+        assert_eq!(vm.pc, 12);
+        assert_eq!(vm.registers[1], 100000);
+    }
+
+    #[test]
+    fn opcode_loadf() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![42, 2];
+        vm.program.extend_from_slice(&(-1.5f64).to_le_bytes());
+        vm.run_once();
+        assert_eq!(vm.pc, 10);
+        assert_eq!(vm.float_registers[2], -1.5);
+    }
+
+    #[test]
+    fn opcode_compare_jumps() {
+        // Each case runs `op $0 $1 @100` and checks whether the jump was taken
+        let cases = [
+            (43, 1, 2, true),
+            (43, 2, 2, false),
+            (43, 3, 2, false),
+            (44, 3, 2, true),
+            (44, 2, 2, false),
+            (44, 1, 2, false),
+            (45, 1, 2, true),
+            (45, 2, 2, true),
+            (45, 3, 2, false),
+            (46, 3, 2, true),
+            (46, 2, 2, true),
+            (46, 1, 2, false),
+        ];
+        for &(opcode, first, second, taken) in cases.iter() {
+            let mut vm = VirtualMachine::new();
+            vm.registers[0] = first;
+            vm.registers[1] = second;
+            vm.program = vec![opcode, 0, 1, 0, 100];
+            vm.run_once();
+            let expected = if taken { 100 } else { 5 };
+            assert_eq!(
+                vm.pc, expected,
+                "opcode {} with {} and {}",
+                opcode, first, second
+            );
+        }
+    }
+
+    #[test]
+    fn test_mixed_instruction_widths() {
+        // loadf $0 #0.25; loadw $1 #70000; load $2 #3; hlt
+        let mut program = vec![42, 0];
+        program.extend_from_slice(&0.25f64.to_le_bytes());
+        program.extend_from_slice(&[41, 1, 0x00, 0x01, 0x11, 0x70, 0, 2, 0, 3, 5, 0, 0, 0]);
+        let mut vm = VirtualMachine::get_test_vm_with_program(program);
+        let events = vm.run();
+        match events.last().map(|e| &e.event) {
+            Some(VMEventType::GracefulStop { .. }) => {}
+            e => panic!("Expected a graceful stop, got {:?}", e),
+        }
+        assert_eq!(vm.float_registers[0], 0.25);
+        assert_eq!(vm.registers[1], 70000);
+        assert_eq!(vm.registers[2], 3);
+    }
+
+    #[test]
+    fn test_truncated_instruction() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![41, 0, 0, 0];
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_validate_valid_program() {
+        // load $0 #10; jlt $0 $1 @77; hlt; jmpe @68; jmpfi #4; hlt
+        let vm = VirtualMachine::get_test_vm_with_program(vec![
+            0, 0, 0, 10, 43, 0, 1, 0, 77, 5, 0, 0, 0, 22, 0, 0, 0, 68, 27, 0, 4, 0, 5, 0, 0, 0,
+        ]);
+        assert_eq!(vm.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_bad_register() {
+        let vm = VirtualMachine::get_test_vm_with_program(vec![5, 0, 0, 0, 1, 0, 40, 2]);
+        assert_eq!(
+            vm.validate(),
+            Err(VMError::InvalidRegister {
+                offset: PIE_HEADER_LENGTH + 8,
+                register: 40,
+                count: DEFAULT_REGISTER_COUNT
+            })
+        );
+    }
+
+    #[test]
+    fn test_register_count() {
+        // load $40 #7; add $40 $40 $41; hlt
+        let program = vec![0, 40, 0, 7, 1, 40, 40, 41, 5, 0, 0, 0];
+        let mut vm = VirtualMachine::new().with_register_count(64);
+        vm.program = VirtualMachine::prepend_header(program.clone());
+        assert_eq!(vm.registers.len(), 64);
+        assert_eq!(vm.validate(), Ok(()));
+        vm.run();
+        assert_eq!(vm.registers[40], 7);
+        assert_eq!(vm.registers[41], 14);
+        vm.reset();
+        assert_eq!(vm.registers, vec![0; 64]);
+
+        // The same program doesn't fit in the default register file
+        let vm = VirtualMachine::get_test_vm_with_program(program);
+        assert!(vm.validate().is_err());
+
+        assert_eq!(
+            VirtualMachine::new()
+                .with_register_count(1000)
+                .registers
+                .len(),
+            MAX_REGISTER_COUNT
+        );
+        let vm = VirtualMachine::new().with_register_count(0);
+        assert_eq!(vm.registers.len(), 1);
+        assert_eq!(vm.float_registers.len(), 1);
+    }
+
+    #[test]
+    fn test_validate_illegal_opcode() {
+        let vm = VirtualMachine::get_test_vm_with_program(vec![5, 0, 0, 0, 200, 0, 0, 0]);
+        assert_eq!(
+            vm.validate(),
+            Err(VMError::IllegalOpcode {
+                offset: PIE_HEADER_LENGTH + 8,
+                byte: 200
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_misaligned_jump() {
+        let vm = VirtualMachine::get_test_vm_with_program(vec![22, 0, 0, 0, 70, 5, 0, 0, 0]);
+        assert_eq!(
+            vm.validate(),
+            Err(VMError::MisalignedJump {
+                offset: PIE_HEADER_LENGTH + 4,
+                target: 70
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_truncated_instruction() {
+        let vm = VirtualMachine::get_test_vm_with_program(vec![5, 0, 0, 0, 41, 0, 0]);
+        assert_eq!(
+            vm.validate(),
+            Err(VMError::TruncatedInstruction {
+                offset: PIE_HEADER_LENGTH + 8
+            })
+        );
+    }
+
+    #[test]
+    fn test_run_with_validation() {
+        // load $0 #5 would run before the bad register was reached without validation
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![0, 0, 0, 5, 1, 0, 40, 2])
+            .with_validation(true);
+        vm.registers[0] = 0;
+        let events = vm.run();
+        match events.last().map(|e| &e.event) {
+            Some(VMEventType::Crash { code }) => assert_eq!(*code, INVALID_BYTECODE_CODE),
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+        assert_eq!(vm.registers[0], 0);
+    }
+
+    #[test]
+    fn test_run_unsupported_version() {
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![5, 0, 0, 0]);
+        assert_eq!(vm.program[PIE_HEADER_VERSION_OFFSET], BYTECODE_VERSION);
+        vm.program[PIE_HEADER_VERSION_OFFSET] = BYTECODE_VERSION + 1;
+        let events = vm.run();
+        match events.last().map(|e| &e.event) {
+            Some(VMEventType::Crash { code }) => assert_eq!(*code, UNSUPPORTED_VERSION_CODE),
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_ro_length_separate_from_starting_offset() {
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![72, 0, 99, 99, 5, 0, 0, 0]);
+        vm.program[PIE_HEADER_RO_LENGTH_OFFSET] = 2;
+        vm.program[PIE_HEADER_LENGTH] = 4;
+        vm.run();
+        let events = vm.events();
+        assert_eq!(vm.ro_data(), &[72, 0]);
+        match events.last().map(|e| &e.event) {
+            Some(VMEventType::GracefulStop { .. }) => {}
+            e => panic!("Expected a graceful stop, got {:?}", e),
+        }
+    }
+
+    /// Records the syscalls a program makes, handing out `input` for reads
+    struct MockSyscalls {
+        input: Vec<i32>,
+        calls: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Syscall for MockSyscalls {
+        fn read_integer(&mut self) -> Option<i32> {
+            self.calls.borrow_mut().push("read".to_string());
+            self.input.pop()
+        }
+
+        fn print_integer(&mut self, value: i32) {
+            self.calls.borrow_mut().push(format!("print {}", value));
+        }
+
+        fn print_char(&mut self, byte: u8) {
+            self.calls
+                .borrow_mut()
+                .push(format!("char {}", byte as char));
+        }
+
+        fn flush(&mut self) {
+            self.calls.borrow_mut().push("flush".to_string());
+        }
+    }
+
+    #[test]
+    fn opcode_storem() {
+        let mut vm = VirtualMachine::new();
+        vm.registers[0] = 8;
+        vm.registers[1] = 0x0102_0304;
+        vm.program = vec![52, 0, 1, 0];
+        vm.run_once();
+        assert_eq!(&vm.heap[8..12], &[4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn opcode_storem_past_heap() {
+        let mut vm = VirtualMachine::new();
+        vm.registers[0] = (DEFAULT_HEAP_STARTING_SIZE - 2) as i32;
+        vm.program = vec![52, 0, 1, 0];
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn opcode_load_store_byte() {
+        let mut vm = VirtualMachine::new();
+        // storeb $0 $1 at 9 and 10, then loadb $2 $0 back from 9
+        vm.registers[0] = 9;
+        vm.registers[1] = 0x0123_45f0;
+        vm.program = vec![
+            60, 0, 1, 0, 18, 0, 0, 0, 60, 0, 1, 0, 19, 0, 0, 0, 59, 2, 0, 0,
+        ];
+        vm.run_headerless();
+        assert_eq!(&vm.heap[8..12], &[0, 0xf0, 0xf0, 0]);
+        // 0xf0 comes back as 240, not sign-extended to -16
+        assert_eq!(vm.registers[2], 0xf0);
+    }
+
+    #[test]
+    fn opcode_load_store_byte_past_heap() {
+        for program in [vec![59, 1, 0, 0], vec![60, 0, 1, 0]].iter() {
+            let mut vm = VirtualMachine::new();
+            vm.registers[0] = DEFAULT_HEAP_STARTING_SIZE as i32;
+            vm.program = program.clone();
+            match vm.execute_instruction() {
+                Some(VMEventType::Crash { .. }) => {}
+                e => panic!("Expected a crash, got {:?}", e),
+            }
+        }
+        // The last byte of the heap is still in bounds
+        let mut vm = VirtualMachine::new();
+        vm.registers[0] = DEFAULT_HEAP_STARTING_SIZE as i32 - 1;
+        vm.registers[1] = 7;
+        vm.program = vec![60, 0, 1, 0];
+        vm.run_once();
+        assert_eq!(vm.heap[DEFAULT_HEAP_STARTING_SIZE - 1], 7);
+    }
+
+    #[test]
+    fn opcode_blkcpy() {
+        let mut vm = VirtualMachine::new();
+        vm.heap[..6].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        // blkcpy $0 $1 $2: 4 bytes from 0 to 20
+        vm.registers[0] = 20;
+        vm.registers[2] = 4;
+        vm.program = vec![61, 0, 1, 2];
+        vm.run_once();
+        assert_eq!(&vm.heap[20..25], &[1, 2, 3, 4, 0]);
+        assert_eq!(&vm.heap[..6], &[1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn opcode_blkcpy_overlapping() {
+        let mut vm = VirtualMachine::new();
+        vm.heap[..6].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        // Forwards by two, then back by one, both over ranges that overlap their source
+        vm.registers[0] = 2;
+        vm.registers[1] = 0;
+        vm.registers[2] = 4;
+        vm.program = vec![61, 0, 1, 2];
+        vm.run_once();
+        assert_eq!(&vm.heap[..6], &[1, 2, 1, 2, 3, 4]);
+
+        vm.registers[0] = 0;
+        vm.registers[1] = 1;
+        vm.registers[2] = 5;
+        vm.set_pc(0);
+        vm.run_once();
+        assert_eq!(&vm.heap[..6], &[2, 1, 2, 3, 4, 4]);
+    }
+
+    #[test]
+    fn opcode_blkcpy_out_of_bounds() {
+        let heap_size = DEFAULT_HEAP_STARTING_SIZE as i32;
+        // (destination, source, length): past the end on either side, or a negative length or address
+        for (destination, source, length) in [
+            (0, 1, heap_size),
+            (heap_size - 2, 0, 3),
+            (0, 0, -1),
+            (-1, 0, 1),
+        ]
+        .iter()
+        {
+            let mut vm = VirtualMachine::new();
+            vm.registers[0] = *destination;
+            vm.registers[1] = *source;
+            vm.registers[2] = *length;
+            vm.program = vec![61, 0, 1, 2];
+            match vm.execute_instruction() {
+                Some(VMEventType::Crash { .. }) => {}
+                e => panic!("Expected a crash, got {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn opcode_indexed_load_store() {
+        let mut vm = VirtualMachine::new();
+        // An array of three words at heap address 16: storeidx $0 $1 $2 for each element, then read them back
+        vm.registers[0] = 16;
+        for (index, value) in [7, -8, 9].iter().enumerate() {
+            vm.registers[1] = index as i32;
+            vm.registers[2] = *value;
+            vm.program = vec![56, 0, 1, 2];
+            vm.set_pc(0);
+            vm.run_once();
+        }
+        assert_eq!(&vm.heap[16..20], &[7, 0, 0, 0]);
+        assert_eq!(&vm.heap[20..24], &(-8i32).to_le_bytes());
+
+        // loadidx $3 $0 $1 with $1 = 2, then with $1 = 1
+        vm.registers[1] = 2;
+        vm.program = vec![55, 3, 0, 1, 19, 1, 0, 0, 55, 4, 0, 1];
+        vm.set_pc(0);
+        vm.run_once();
+        assert_eq!(vm.registers[3], 9);
+        vm.run_once();
+        vm.run_once();
+        assert_eq!(vm.registers[4], -8);
+    }
+
+    #[test]
+    fn opcode_indexed_out_of_bounds() {
+        for program in [vec![55, 3, 0, 1], vec![56, 0, 1, 2]].iter() {
+            for index in [12, -5].iter() {
+                let mut vm = VirtualMachine::new();
+                vm.registers[0] = 16;
+                vm.registers[1] = *index;
+                vm.program = program.clone();
+                match vm.execute_instruction() {
+                    Some(VMEventType::Crash { .. }) => {}
+                    e => panic!("Expected a crash for index {}, got {:?}", index, e),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mmio_display() {
+        let calls = Rc::new(RefCell::new(vec![]));
+        let mock = MockSyscalls {
+            input: vec![],
+            calls: calls.clone(),
+        };
+        let mut vm = VirtualMachine::new().with_syscalls(Box::new(mock));
+        vm.registers[0] = MMIO_DISPLAY as i32;
+        vm.registers[1] = 'H' as i32;
+        vm.registers[2] = 'i' as i32;
+        vm.registers[3] = (MMIO_DISPLAY + 1) as i32;
+        vm.program = vec![52, 0, 1, 0, 52, 0, 2, 0, 52, 3, 1, 0];
+        vm.run_once();
+        vm.run_once();
+        vm.run_once();
+        assert_eq!(*calls.borrow(), vec!["char H", "char i"]);
+        assert!(vm.heap.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn opcode_syscall() {
+        let calls = Rc::new(RefCell::new(vec![]));
+        let mock = MockSyscalls {
+            input: vec![42],
+            calls: calls.clone(),
+        };
+        let mut vm = VirtualMachine::new().with_syscalls(Box::new(mock));
+        vm.program = vec![30, 0, 0, 3, 30, 0, 1, 3, 30, 0, 2, 0];
+        vm.run_once();
+        assert_eq!(vm.registers[3], 42);
+        vm.run_once();
+        vm.run_once();
+        assert_eq!(*calls.borrow(), vec!["read", "print 42", "flush"]);
+    }
+
+    #[test]
+    fn opcode_syscall_failures() {
+        let mock = MockSyscalls {
+            input: vec![],
+            calls: Rc::new(RefCell::new(vec![])),
+        };
+        let mut vm = VirtualMachine::new().with_syscalls(Box::new(mock));
+        vm.program = vec![30, 0, 0, 3, 30, 0, 9, 0];
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash when no input is available, got {:?}", e),
+        }
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash for an unknown syscall, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn opcode_read() {
+        let input = Cursor::new("42\nnot a number\n");
+        let mut vm = VirtualMachine::new().with_input(Box::new(input));
+        vm.program = vec![31, 2, 0, 0, 31, 2, 0, 0, 31, 2, 0, 0];
+        vm.run_once();
+        assert_eq!(vm.registers[2], 42);
+        assert!(vm.equal_flag());
+        vm.run_once();
+        assert_eq!(vm.registers[2], 42);
+        assert!(!vm.equal_flag());
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash at the end of input, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![5, 0, 0, 0]);
+        vm.run();
+        let events = vm.events();
+        assert_eq!(events.len(), 2);
+        match (&events[0].event, &events[1].event) {
+            (VMEventType::Start, VMEventType::GracefulStop { .. }) => {}
+            e => panic!("Expected a start then a graceful stop, got {:?}", e),
+        }
+        assert!(events[0].timestamp() <= events[1].timestamp());
+        assert_eq!(events[0].application_id(), vm.id);
+        assert_eq!(events[1].application_id(), vm.id);
+    }
+
+    /// Runs `program` with and without the decode cache, returning the VM's state and opcode counts after each
+    fn run_both_ways(program: &[u8], headerless: bool) -> Vec<String> {
+        let id = Uuid::parse_str("6c1b4b52-6f2f-4a5e-9d8e-1f2a3b4c5d6e").unwrap();
+        let at = Utc.ymd(2020, 1, 2).and_hms(3, 4, 5);
+        [false, true]
+            .iter()
+            .map(|decode_cache| {
+                let mut vm = VirtualMachine::new()
+                    .with_id(id)
+                    .with_clock(Box::new(move || at))
+                    .with_profile(true)
+                    .with_decode_cache(*decode_cache);
+                vm.program = program.to_vec();
+                if headerless {
+                    vm.run_headerless();
+                } else {
+                    vm.run();
+                }
+                assert!(vm.decoded.is_empty());
+                format!(
+                    "{} {:?}",
+                    serde_json::to_string(&vm.snapshot()).unwrap(),
+                    vm.profile_report()
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_cache_matches_bytes() {
+        let programs = [
+            // The loop from test_dispatch_loop
+            vec![
+                0, 0, 0, 0, 0, 2, 0, 3, 0, 1, 0x03, 0xE8, 18, 0, 0, 0, 1, 0, 2, 3, 3, 3, 2, 4, 43,
+                0, 1, 0, 12, 5, 0, 0, 0,
+            ],
+            // call #8; hlt; inc $0; ret
+            vec![34, 0, 8, 0, 5, 0, 0, 0, 18, 0, 0, 0, 35, 0, 0, 0],
+            // load $1 #6; jmp $1, which lands inside the jmp and then runs off the end
+            vec![0, 1, 0, 6, 6, 1, 0, 0, 5, 0, 0, 0],
+            // inc $0, then a truncated hlt
+            vec![18, 0, 0, 0, 5, 0],
+            // An illegal opcode
+            vec![18, 0, 0, 0, 200, 0, 0, 0],
+            // loadw $2 #70000; loadf $1 #2.5; getpc $3; hlt
+            vec![
+                41, 2, 0, 1, 0x11, 0x70, 42, 1, 0, 0, 0, 0, 0, 0, 4, 64, 58, 3, 0, 0, 5, 0, 0, 0,
+            ],
+        ];
+        for program in programs.iter() {
+            let runs = run_both_ways(program, true);
+            assert_eq!(runs[0], runs[1], "{:?}", program);
+        }
+        // Jump targets count from the start of the header, so this one has none
+        let with_header =
+            VirtualMachine::prepend_header(vec![18, 0, 0, 0, 18, 0, 0, 0, 5, 0, 0, 0]);
+        let runs = run_both_ways(&with_header, false);
+        assert_eq!(runs[0], runs[1]);
+    }
+
+    #[test]
+    fn test_decode_cache_holds_operands() {
+        // load $0 #500; loadw $1 #70000
+        let mut vm = VirtualMachine::new();
+        vm.program = vec![0, 0, 1, 244, 41, 1, 0, 1, 0x11, 0x70];
+        vm.decoded = vm.decode_program(0);
+        // Once decoded, the instructions don't need their bytes
+        vm.program = vec![0; vm.program.len()];
+        assert!(vm.execute_instruction().is_none());
+        assert!(vm.execute_instruction().is_none());
+        assert_eq!(vm.registers[0], 500);
+        assert_eq!(vm.registers[1], 70000);
+        assert_eq!(vm.pc, 10);
+    }
+
+    #[test]
+    fn test_run_returns_event_log() {
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![5, 0, 0, 0]);
+        let returned = serde_json::to_string(vm.run()).unwrap();
+        // The returned events are the VM's own log, which it keeps after returning them
+        assert_eq!(returned, serde_json::to_string(vm.events()).unwrap());
+        assert_eq!(vm.events().len(), 2);
+
+        // Without a reset, another run adds to the same log
+        assert_eq!(vm.run().len(), 4);
+        vm.reset();
+        assert!(vm.events().is_empty());
+    }
+
+    #[test]
+    fn test_pinned_id_and_clock() {
+        let id = Uuid::parse_str("6c1b4b52-6f2f-4a5e-9d8e-1f2a3b4c5d6e").unwrap();
+        let at = Utc.ymd(2020, 1, 2).and_hms(3, 4, 5);
+        let mut runs = vec![];
+        for _ in 0..2 {
+            let mut vm = VirtualMachine::get_test_vm_with_program(vec![5, 0, 0, 0])
+                .with_id(id)
+                .with_clock(Box::new(move || at));
+            runs.push(vm.run().to_vec());
+        }
+        for events in &runs {
+            assert_eq!(events.len(), 2);
+            for event in events {
+                assert_eq!(event.application_id(), id);
+                assert_eq!(event.timestamp(), at);
+            }
+        }
+        assert_eq!(
+            serde_json::to_string(&runs[0]).unwrap(),
+            serde_json::to_string(&runs[1]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_event_handler() {
+        let seen = Rc::new(RefCell::new(vec![]));
+        let handler_seen = Rc::clone(&seen);
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![5, 0, 0, 0]).with_event_handler(
+            Box::new(move |event: &VMEvent| handler_seen.borrow_mut().push(event.event.clone())),
+        );
+        let events = vm.run();
+        let seen = seen.borrow();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen.len(), events.len());
+        match (&seen[0], &seen[1]) {
+            (VMEventType::Start, VMEventType::GracefulStop { .. }) => {}
+            e => panic!("Expected a start then a graceful stop, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_json() {
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![1, 0, 1, 2, 5, 0, 0, 0]);
+        vm.run();
+        let json = serde_json::to_value(vm.snapshot()).unwrap();
+        assert_eq!(json["registers"].as_array().unwrap().len(), 32);
+        assert_eq!(json["registers"][2], 15);
+        assert_eq!(json["float_registers"].as_array().unwrap().len(), 32);
+        assert_eq!(json["equal_flag"], false);
+        assert_eq!(json["remainder"], 0);
+        assert_eq!(json["pc"], vm.pc);
+        let events = json["events"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "Start");
+        assert_eq!(events[1]["event"]["GracefulStop"]["code"], 1);
+        assert!(events[1]["at"].is_string());
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
         let mut vm = VirtualMachine::get_test_vm();
-        vm.program = vec![0, 0, 1, 244];
-        vm.program = VirtualMachine::prepend_header(vm.program);
-        vm.run();
-        assert_eq!(vm.registers[0], 500);
+        vm.program = vec![1, 0, 1, 2];
+        let before = vm.snapshot();
+        assert!(before.diff(&before).is_empty());
+        vm.step();
+        assert_eq!(
+            before.diff(&vm.snapshot()),
+            vec!["$2: 0 -> 15".to_string(), "pc: 0 -> 4".to_string()]
+        );
+
+        let mut after = vm.snapshot();
+        after.float_registers[1] = 2.5;
+        after.equal_flag = true;
+        after.sp = 2;
+        assert_eq!(
+            vm.snapshot().diff(&after),
+            vec![
+                "float $1: 0.0 -> 2.5",
+                "equal_flag: false -> true",
+                "sp: 0 -> 2"
+            ]
+        );
     }
 
     #[test]
-    fn test_add_opcode() {
-        let mut test_vm = VirtualMachine::get_test_vm();
-        test_vm.program = vec![1, 0, 1, 2];
-        test_vm.program = VirtualMachine::prepend_header(test_vm.program);
-        test_vm.run();
-        assert_eq!(test_vm.registers[2], 15);
+    fn test_snapshot_round_trip() {
+        let mut vm =
+            VirtualMachine::get_test_vm_with_program(vec![4, 1, 0, 2, 9, 0, 0, 0, 5, 0, 0, 0]);
+        vm.float_registers[3] = 1.5;
+        vm.sp = 7;
+        vm.bp = 3;
+        vm.run();
+        let json = serde_json::to_string(&vm.snapshot()).unwrap();
+
+        let mut restored = VirtualMachine::new();
+        restored
+            .restore(serde_json::from_str(&json).unwrap())
+            .unwrap();
+        assert_eq!(restored.registers, vm.registers);
+        assert_eq!(restored.float_registers, vm.float_registers);
+        assert_eq!(restored.equal_flag(), vm.equal_flag());
+        assert_eq!(restored.remainder(), vm.remainder());
+        assert_eq!(restored.pc, vm.pc);
+        assert_eq!(restored.sp, 7);
+        assert_eq!(restored.bp, 3);
     }
 
     #[test]
-    fn test_sub_opcode() {
-        let mut test_vm = VirtualMachine::get_test_vm();
-        test_vm.program = vec![2, 1, 0, 2];
-        test_vm.program = VirtualMachine::prepend_header(test_vm.program);
-        test_vm.run();
-        assert_eq!(test_vm.registers[2], 5);
+    fn test_restore_wrong_register_count() {
+        let snapshot = VirtualMachine::new().with_register_count(8).snapshot();
+        let mut vm = VirtualMachine::new();
+        vm.registers[0] = 5;
+        assert_eq!(
+            vm.restore(snapshot),
+            Err(VMError::SnapshotRegisterCount {
+                registers: 8,
+                float_registers: 8,
+                count: DEFAULT_REGISTER_COUNT
+            })
+        );
+        assert_eq!(vm.registers[0], 5);
+
+        let mut snapshot = vm.snapshot();
+        snapshot.float_registers.pop();
+        assert!(vm.restore(snapshot).is_err());
+
+        let mut snapshot = vm.snapshot();
+        snapshot.register_banks.push(vec![0; 8]);
+        assert!(vm.restore(snapshot).is_err());
     }
 
     #[test]
-    fn test_mul_opcode() {
-        let mut test_vm = VirtualMachine::get_test_vm();
-        test_vm.program = vec![3, 0, 1, 2];
-        test_vm.program = VirtualMachine::prepend_header(test_vm.program);
-        test_vm.run();
-        assert_eq!(test_vm.registers[2], 50);
+    fn test_snapshot_inside_call() {
+        // load $1 #10; call #12; hlt
+        // load $1 #99; ret
+        let program = vec![
+            0, 1, 0, 10, 34, 0, 12, 0, 5, 0, 0, 0, 0, 1, 0, 99, 35, 0, 0, 0,
+        ];
+        let mut vm = VirtualMachine::new().with_register_banks(true);
+        vm.program = program.clone();
+        vm.step();
+        vm.step();
+        let snapshot = vm.snapshot();
+        assert_eq!(snapshot.register_banks.len(), 1);
+
+        // Restoring partway through the call still gives the caller's registers back at the `ret`
+        let mut restored = VirtualMachine::new().with_register_banks(true);
+        restored.program = program;
+        restored.stack = vm.stack.clone();
+        restored.restore(snapshot).unwrap();
+        restored.step();
+        restored.step();
+        assert_eq!(restored.registers[1], 10);
+        assert!(restored.register_banks.is_empty());
+    }
+
+    /// Hands each log message to the test that logged it. The logger is process-wide but tests run on their own
+    /// threads, so messages are only kept on a thread that is inside `capture_logs`.
+    struct CaptureLogger;
+
+    thread_local! {
+        static CAPTURED_LOGS: std::cell::RefCell<Option<Vec<String>>> = const { std::cell::RefCell::new(None) };
+    }
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED_LOGS.with(|logs| {
+                if let Some(logs) = logs.borrow_mut().as_mut() {
+                    logs.push(format!("{}", record.args()));
+                }
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Runs `f` and returns everything this thread logged while it ran
+    fn capture_logs(f: impl FnOnce()) -> Vec<String> {
+        static LOGGER: CaptureLogger = CaptureLogger;
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Info);
+        CAPTURED_LOGS.with(|logs| *logs.borrow_mut() = Some(vec![]));
+        f();
+        CAPTURED_LOGS.with(|logs| logs.borrow_mut().take().unwrap_or_default())
     }
 
     #[test]
-    fn opcode_jmp() {
-        let mut vm = VirtualMachine::new();
-        vm.registers[0] = 1;
-        vm.program = vec![6, 0, 0, 0];
-        vm.run_once();
-        assert_eq!(vm.pc, 1);
+    fn test_trace() {
+        let program = vec![0, 0, 0, 7, 0, 1, 0, 9, 1, 0, 1, 2, 5, 0, 0, 0];
+        let mut traced = VirtualMachine::get_test_vm_with_program(program.clone()).with_trace(true);
+        let logs = capture_logs(|| {
+            traced.run();
+        });
+        let mut untraced = VirtualMachine::get_test_vm_with_program(program);
+        untraced.run();
+        assert_eq!(traced.registers, untraced.registers);
+
+        assert!(logs.contains(&"pc 68: LOAD $0 #7".to_string()));
+        assert!(logs.contains(&"pc 76: ADD $0 $1 $2".to_string()));
+        assert!(logs.contains(&"pc 80: HLT".to_string()));
     }
 
     #[test]
-    fn test_div_opcode() {
-        let mut vm = VirtualMachine::get_test_vm();
-        vm.program = vec![4, 1, 0, 2];
-        vm.program = VirtualMachine::prepend_header(vm.program);
-        vm.run();
-        assert_eq!(vm.registers[2], 2)
+    fn test_profile_report() {
+        let mut vm = VirtualMachine::new().with_profile(true);
+        vm.program = vec![
+            0, 0, 0, 0, 0, 1, 0, 5, 18, 0, 0, 0, 10, 0, 1, 0, 22, 0, 0, 0, 8, 5, 0, 0, 0,
+        ];
+        vm.run_headerless();
+        assert_eq!(vm.registers[0], 5);
+        let report = vm.profile_report();
+        assert_eq!(
+            report,
+            vec![
+                (Opcode::NEQ, 5),
+                (Opcode::INC, 5),
+                (Opcode::JMPE, 5),
+                (Opcode::LOAD, 2),
+                (Opcode::HLT, 1),
+            ]
+        );
     }
 
     #[test]
@@ -471,6 +2842,52 @@ mod tests {
         assert_eq!(vm.pc, 0);
     }
 
+    #[test]
+    fn opcode_jmpb_underflow() {
+        let mut vm = VirtualMachine::new();
+        vm.registers[0] = 100;
+        vm.registers[1] = -4;
+        vm.program = vec![8, 0, 0, 0, 8, 1, 0, 0];
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+        vm.pc = 4;
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn opcode_jmpfi() {
+        let mut vm = VirtualMachine::new();
+        vm.program = vec![23, 0, 0, 0, 27, 0, 8, 0, 5, 0, 0, 0, 23, 0, 0, 0];
+        vm.pc = 4;
+        vm.run_once();
+        assert_eq!(vm.pc, 12);
+    }
+
+    #[test]
+    fn opcode_jmpbi() {
+        let mut vm = VirtualMachine::new();
+        vm.program = vec![23, 0, 0, 0, 28, 0, 4, 0];
+        vm.pc = 4;
+        vm.run_once();
+        assert_eq!(vm.pc, 0);
+    }
+
+    #[test]
+    fn opcode_jmpbi_underflow() {
+        let mut vm = VirtualMachine::new();
+        vm.program = vec![23, 0, 0, 0, 28, 0, 8, 0];
+        vm.pc = 4;
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
     #[test]
     fn opcode_eq() {
         let mut vm = VirtualMachine::new();
@@ -565,15 +2982,391 @@ mod tests {
         assert_eq!(vm.pc, 7);
     }
 
+    #[test]
+    fn opcode_jmpe() {
+        let mut vm = VirtualMachine::new();
+        vm.equal_flag = true;
+        vm.program = vec![22, 0, 0, 0, 10, 22, 0, 0, 0, 10];
+        vm.run_once();
+        assert_eq!(vm.pc, 10);
+        vm.pc = 5;
+        vm.equal_flag = false;
+        vm.run_once();
+        assert_eq!(vm.pc, 10);
+
+        // Targets past 16 bits
+        vm.program = vec![22, 0, 1, 0x11, 0x70];
+        vm.program.resize(70_005, 0);
+        vm.pc = 0;
+        vm.equal_flag = true;
+        vm.run_once();
+        assert_eq!(vm.pc, 70_000);
+    }
+
+    #[test]
+    fn opcode_nop() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![23, 0, 0, 0, 23, 0, 0, 0];
+        vm.run_once();
+        assert_eq!(vm.pc, 4);
+        vm.run_once();
+        assert_eq!(vm.pc, 8);
+        assert_eq!(vm.registers, VirtualMachine::get_test_vm().registers);
+    }
+
+    #[test]
+    fn opcode_mov() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![24, 2, 1, 0];
+        vm.run_once();
+        assert_eq!(vm.registers[2], 10);
+        assert_eq!(vm.registers[1], 10);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn opcode_cmov() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![57, 2, 1, 0, 57, 3, 1, 0];
+        vm.equal_flag = false;
+        vm.run_once();
+        assert_eq!(vm.registers[2], 0);
+        vm.equal_flag = true;
+        vm.run_once();
+        assert_eq!(vm.registers[3], 10);
+        assert_eq!(vm.pc, 8);
+    }
+
+    #[test]
+    fn opcode_neg() {
+        let mut vm = VirtualMachine::new();
+        vm.registers[0] = 42;
+        vm.registers[1] = i32::MIN;
+        vm.program = vec![48, 2, 0, 0, 48, 3, 1, 0, 48, 4, 2, 0];
+        vm.run_once();
+        assert_eq!(vm.registers[2], -42);
+        vm.run_once();
+        assert_eq!(vm.registers[3], i32::MIN);
+        vm.run_once();
+        assert_eq!(vm.registers[4], 42);
+        assert_eq!(vm.pc, 12);
+    }
+
+    #[test]
+    fn opcode_abs() {
+        let cases = [(-7, 7), (7, 7), (0, 0), (i32::MIN, i32::MIN)];
+        for &(value, expected) in cases.iter() {
+            let mut vm = VirtualMachine::new();
+            vm.registers[0] = value;
+            vm.program = vec![49, 1, 0, 0];
+            vm.run_once();
+            assert_eq!(vm.registers[1], expected, "abs of {}", value);
+            assert_eq!(vm.registers[0], value);
+        }
+    }
+
+    #[test]
+    fn opcode_movf() {
+        let mut vm = VirtualMachine::new();
+        vm.float_registers[1] = 2.5;
+        vm.program = vec![25, 0, 1, 0];
+        vm.run_once();
+        assert_eq!(vm.float_registers[0], 2.5);
+        assert_eq!(vm.pc, 4);
+    }
+
     #[test]
     fn opcode_aloc() {
         let mut vm = VirtualMachine::get_test_vm();
-        vm.registers[0] = 1086;
+        vm.registers[0] = 1024;
         vm.program = vec![17, 0, 0, 0];
         vm.run_once();
         assert_eq!(vm.heap.len(), 1024 + DEFAULT_HEAP_STARTING_SIZE);
     }
 
+    #[test]
+    fn opcode_aloc_max_heap() {
+        let mut vm = VirtualMachine::new().with_max_heap(1024);
+        vm.registers[0] = 1024 - DEFAULT_HEAP_STARTING_SIZE as i32;
+        vm.registers[1] = 1;
+        vm.program = vec![17, 0, 0, 0, 17, 1, 0, 0];
+        assert!(vm.execute_instruction().is_none());
+        assert_eq!(vm.heap.len(), 1024);
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+        assert_eq!(vm.heap.len(), 1024);
+    }
+
+    #[test]
+    fn opcode_aloc_stops_at_mmio() {
+        let mut vm = VirtualMachine::new();
+        vm.registers[0] = (MMIO_START - DEFAULT_HEAP_STARTING_SIZE) as i32;
+        vm.registers[1] = 1;
+        vm.registers[2] = 0xFFEE;
+        vm.registers[3] = 0xFFEF;
+        vm.registers[4] = MMIO_START as i32;
+        // aloc $0; aloc $1; storem $2 $1; storeb $3 $1; loadb $5 $4
+        vm.program = vec![
+            17, 0, 0, 0, 17, 1, 0, 0, 52, 2, 1, 0, 60, 3, 1, 0, 59, 5, 4, 0,
+        ];
+        assert!(vm.execute_instruction().is_none());
+        assert_eq!(vm.heap.len(), MMIO_START);
+        // Not even a larger maximum lets the heap reach the I/O range
+        vm.max_heap = Some(MMIO_END);
+        for _ in 0..2 {
+            match vm.execute_instruction() {
+                Some(VMEventType::Crash { .. }) => {}
+                e => panic!("Expected a crash, got {:?}", e),
+            }
+        }
+        assert_eq!(vm.heap.len(), MMIO_START);
+        assert!(vm.heap.iter().all(|byte| *byte == 0));
+
+        // The last heap byte is still the heap's, and the first I/O address isn't
+        assert!(vm.execute_instruction().is_none());
+        assert_eq!(vm.heap[0xFFEF], 1);
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn opcode_push_pop() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![32, 0, 0, 0, 33, 2, 0, 0];
+        vm.run_once();
+        assert_eq!(vm.stack, vec![5]);
+        assert_eq!(vm.sp, 1);
+        vm.run_once();
+        assert_eq!(vm.registers[2], 5);
+        assert!(vm.stack.is_empty());
+        assert_eq!(vm.sp, 0);
+    }
+
+    #[test]
+    fn opcode_pop_empty_stack() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![33, 0, 0, 0];
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn opcode_call_ret() {
+        let mut vm = VirtualMachine::get_test_vm();
+        // call #8; hlt; inc $0; ret
+        vm.program = vec![34, 0, 8, 0, 5, 0, 0, 0, 18, 0, 0, 0, 35, 0, 0, 0];
+        vm.run_headerless();
+        assert_eq!(vm.registers[0], 6);
+        assert_eq!(vm.pc, 5);
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn opcode_time() {
+        // time $0; load $3 #20000; top: inc $1; jlt $1 $3 @top; time $2; hlt
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![
+            53, 0, 0, 0, 0, 3, 0x4e, 0x20, 18, 1, 0, 0, 43, 1, 3, 0, 76, 53, 2, 0, 0, 5, 0, 0, 0,
+        ]);
+        vm.run();
+        assert_eq!(vm.registers[1], 20000);
+        assert!(vm.registers[0] >= 0);
+        assert!(vm.registers[2] >= vm.registers[0]);
+    }
+
+    #[test]
+    fn opcode_getpc() {
+        // nop; getpc $4; getpc $5; hlt
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![
+            23, 0, 0, 0, 58, 4, 0, 0, 58, 5, 0, 0, 5, 0, 0, 0,
+        ]);
+        vm.run();
+        let code_start = (PIE_HEADER_LENGTH + 4) as i32;
+        assert_eq!(vm.registers[4], code_start + 4);
+        assert_eq!(vm.registers[5], code_start + 8);
+    }
+
+    #[test]
+    fn opcode_time_with_clock() {
+        let ticks = Rc::new(Cell::new(0));
+        let clock_ticks = Rc::clone(&ticks);
+        let start = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let mut vm = VirtualMachine::get_test_vm_with_program(vec![53, 0, 0, 0, 53, 1, 0, 0])
+            .with_clock(Box::new(move || {
+                clock_ticks.set(clock_ticks.get() + 1);
+                start + chrono::Duration::milliseconds(250 * clock_ticks.get())
+            }));
+        vm.run();
+        // The Start event is the first tick, and each TIME is another
+        assert_eq!(vm.registers[0], 250);
+        assert_eq!(vm.registers[1], 500);
+        assert_eq!(ticks.get(), 4);
+    }
+
+    #[test]
+    fn opcode_rand() {
+        // rand $0; rand $1; hlt
+        let program = vec![54, 0, 0, 0, 54, 1, 0, 0, 5, 0, 0, 0];
+        let mut first = VirtualMachine::new().with_rng_seed(1234);
+        first.program = program.clone();
+        first.run_headerless();
+        let mut second = VirtualMachine::new().with_rng_seed(1234);
+        second.program = program;
+        second.run_headerless();
+        assert_eq!(first.registers, second.registers);
+        assert_ne!(first.registers[0], first.registers[1]);
+    }
+
+    #[test]
+    fn test_register_banks() {
+        // load $1 #10; load $2 #20; call #16; hlt
+        // add $1 $2 $0; load $1 #99; load $2 #99; ret
+        let program = vec![
+            0, 1, 0, 10, 0, 2, 0, 20, 34, 0, 16, 0, 5, 0, 0, 0, 1, 1, 2, 0, 0, 1, 0, 99, 0, 2, 0,
+            99, 35, 0, 0, 0,
+        ];
+        let mut vm = VirtualMachine::new().with_register_banks(true);
+        vm.program = program.clone();
+        vm.run_headerless();
+        assert_eq!(vm.registers[0], 30);
+        assert_eq!(vm.registers[1], 10);
+        assert_eq!(vm.registers[2], 20);
+        assert!(vm.register_banks.is_empty());
+
+        // Without banks the function's registers leak back into the caller
+        let mut vm = VirtualMachine::new();
+        vm.program = program;
+        vm.run_headerless();
+        assert_eq!(vm.registers[0], 30);
+        assert_eq!(vm.registers[1], 99);
+    }
+
+    #[test]
+    fn test_nested_register_banks() {
+        // load $1 #1; call #12; hlt
+        // load $1 #2; call #28; add $0 $1 $0; ret
+        // load $1 #3; load $0 #40; ret
+        let mut vm = VirtualMachine::new().with_register_banks(true);
+        vm.program = vec![
+            0, 1, 0, 1, 34, 0, 12, 0, 5, 0, 0, 0, 0, 1, 0, 2, 34, 0, 28, 0, 1, 0, 1, 0, 35, 0, 0,
+            0, 0, 1, 0, 3, 0, 0, 0, 40, 35, 0, 0, 0,
+        ];
+        vm.run_headerless();
+        // The innermost function's $1 is gone by the time the middle one adds, and the middle one's by the end
+        assert_eq!(vm.registers[0], 42);
+        assert_eq!(vm.registers[1], 1);
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let mut vm = VirtualMachine::new().with_stack_limit(100);
+        // A function that counts how deep it is and calls itself forever: inc $0; call #0
+        vm.program = vec![18, 0, 0, 0, 34, 0, 0, 0];
+        let events = vm.run_headerless();
+        match events.last().map(|e| &e.event) {
+            Some(VMEventType::Crash { code }) => assert_eq!(*code, STACK_OVERFLOW_CODE),
+            e => panic!("Expected a stack overflow, got {:?}", e),
+        }
+        // Each frame holds a return address and a frame pointer, so the 51st call is the one that overflows
+        assert_eq!(vm.stack.len(), 100);
+        assert_eq!(vm.registers[0], 51);
+    }
+
+    #[test]
+    fn opcode_dump() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![38, 0, 0, 0, 40, 0, 0, 0, 5, 0, 0, 0];
+        vm.run_once();
+        assert_eq!(vm.pc, 4);
+        vm.run_once();
+        assert_eq!(vm.pc, 8);
+        assert_eq!(vm.registers[0], 5);
+    }
+
+    #[test]
+    fn test_register_dump() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.equal_flag = true;
+        let dump = vm.register_dump();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("$0: 5\t$1: 10\t$2: 0"));
+        assert!(lines[3].ends_with("$31: 0"));
+        assert_eq!(lines[4], "equal_flag: true");
+        vm.float_registers[9] = 1.5;
+        let lines: Vec<String> = vm.float_register_dump().lines().map(String::from).collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("$8: 0\t$9: 1.5"));
+    }
+
+    #[test]
+    fn opcode_storel_loadl() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.bp = 1;
+        vm.stack = vec![99];
+        // storel #1 $0; loadl $2 #1
+        vm.program = vec![37, 0, 1, 0, 36, 2, 0, 1];
+        vm.run_once();
+        assert_eq!(vm.stack, vec![99, 0, 5]);
+        assert_eq!(vm.sp, 3);
+        vm.run_once();
+        assert_eq!(vm.registers[2], 5);
+    }
+
+    #[test]
+    fn opcode_loadl_past_top_of_stack() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![36, 0, 0, 0];
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn opcode_ret_without_call() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![35, 0, 0, 0];
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_push_stack_overflow() {
+        let mut vm = VirtualMachine::get_test_vm().with_stack_limit(1);
+        vm.program = vec![32, 0, 0, 0, 32, 1, 0, 0];
+        assert!(vm.execute_instruction().is_none());
+        match vm.execute_instruction() {
+            Some(VMEventType::Crash { code }) => assert_eq!(code, STACK_OVERFLOW_CODE),
+            e => panic!("Expected a stack overflow, got {:?}", e),
+        }
+        assert_eq!(vm.stack, vec![5]);
+    }
+
+    #[test]
+    fn test_heap_starting_size() {
+        let vm = VirtualMachine::new();
+        assert_eq!(vm.heap.len(), DEFAULT_HEAP_STARTING_SIZE);
+        assert!(vm.heap.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_heap_starting_size_after_reset() {
+        let mut vm = VirtualMachine::get_test_vm();
+        vm.program = vec![17, 0, 0, 0];
+        vm.run_once();
+        assert_eq!(vm.heap.len(), 5 + DEFAULT_HEAP_STARTING_SIZE);
+        vm.reset();
+        assert_eq!(vm.heap.len(), DEFAULT_HEAP_STARTING_SIZE);
+    }
+
     #[test]
     fn opcode_inc() {
         let mut vm = VirtualMachine::new();
@@ -597,7 +3390,26 @@ mod tests {
         let mut test_vm = VirtualMachine::new();
         test_vm.program = vec![39, 0, 0, 1];
         test_vm.run_once();
-        assert_eq!(test_vm.registers[0], 1);
+        assert_eq!(test_vm.registers[0], 65536);
+    }
+
+    #[test]
+    fn test_load_lui_sequence() {
+        // 100000 is 0x000186A0, so LOAD the lower half and LUI the upper half
+        let mut test_vm = VirtualMachine::new();
+        test_vm.program = vec![0, 0, 0x86, 0xA0, 39, 0, 0, 1];
+        test_vm.program = VirtualMachine::prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.registers[0], 100000);
+    }
+
+    #[test]
+    fn test_lui_keeps_lower_half() {
+        let mut test_vm = VirtualMachine::new();
+        test_vm.registers[0] = 0x1234_5678;
+        test_vm.program = vec![39, 0, 0xFF, 0xFF];
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[0], 0xFFFF_5678_u32 as i32);
     }
 
     #[test]
@@ -609,6 +3421,58 @@ mod tests {
         // TODO: How can we validate the output since it is just printing to stdout in a test?
     }
 
+    #[test]
+    fn test_prts_opcode_without_terminator() {
+        let mut test_vm = VirtualMachine::new();
+        let mut program = VirtualMachine::prepend_header(vec![21, 0, 0, 0, 5, 0, 0, 0]);
+        program[PIE_HEADER_LENGTH] = 2;
+        program.splice(68..68, vec![72, 105]);
+        test_vm.program = program;
+        let events = test_vm.run();
+        match events.last().unwrap().event {
+            VMEventType::Crash { code } => assert_eq!(code, 1),
+            ref e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_prts_opcode_offset_past_end() {
+        let mut test_vm = VirtualMachine::new();
+        test_vm.ro_data = vec![72, 105, 0];
+        test_vm.program = vec![21, 0, 10, 0];
+        match test_vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_prtsf_opcode() {
+        let mut test_vm = VirtualMachine::new();
+        test_vm.ro_data = vec![72, 105, 0, 89, 111, 0];
+        test_vm.registers[0] = 3;
+        test_vm.program = vec![26, 0, 0, 0];
+        assert!(test_vm.execute_instruction().is_none());
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_prtsf_opcode_bad_offset() {
+        let mut test_vm = VirtualMachine::new();
+        test_vm.ro_data = vec![72, 105, 0];
+        test_vm.program = vec![26, 0, 0, 0, 26, 1, 0, 0];
+        test_vm.registers[0] = -1;
+        test_vm.registers[1] = 3;
+        match test_vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+        match test_vm.execute_instruction() {
+            Some(VMEventType::Crash { .. }) => {}
+            e => panic!("Expected a crash, got {:?}", e),
+        }
+    }
+
     #[test]
     fn opcode_mul() {
         let mut vm = VirtualMachine::get_test_vm();
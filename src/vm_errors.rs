@@ -0,0 +1,114 @@
+use std::error::Error;
+use std::fmt;
+
+/// Problems `VirtualMachine::validate` can find in a program before it runs, and snapshots `VirtualMachine::restore`
+/// can't use. Offsets are from the start of the program, header included.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VMError {
+    InvalidHeader {
+        code: u32,
+    },
+    IllegalOpcode {
+        offset: usize,
+        byte: u8,
+    },
+    TruncatedInstruction {
+        offset: usize,
+    },
+    InvalidRegister {
+        offset: usize,
+        register: u8,
+        count: usize,
+    },
+    MisalignedJump {
+        offset: usize,
+        target: usize,
+    },
+    SnapshotRegisterCount {
+        registers: usize,
+        float_registers: usize,
+        count: usize,
+    },
+}
+
+impl fmt::Display for VMError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VMError::InvalidHeader { code } => {
+                f.write_str(&format!("The program's header is invalid (crash code {})", code))
+            }
+            VMError::IllegalOpcode { offset, byte } => {
+                f.write_str(&format!("Illegal opcode {:#04x} at offset {}", byte, offset))
+            }
+            VMError::TruncatedInstruction { offset } => f.write_str(&format!(
+                "The instruction at offset {} runs past the end of the program",
+                offset
+            )),
+            VMError::InvalidRegister {
+                offset,
+                register,
+                count,
+            } => f.write_str(&format!(
+                "The instruction at offset {} uses register ${}, but there are only {}",
+                offset, register, count
+            )),
+            VMError::MisalignedJump { offset, target } => f.write_str(&format!(
+                "The instruction at offset {} jumps to {}, which is not the start of an instruction",
+                offset, target
+            )),
+            VMError::SnapshotRegisterCount {
+                registers,
+                float_registers,
+                count,
+            } => f.write_str(&format!(
+                "The snapshot has {} registers and {} float registers, but the VM has {} of each",
+                registers, float_registers, count
+            )),
+        }
+    }
+}
+
+impl Error for VMError {
+    fn description(&self) -> &str {
+        match self {
+            VMError::InvalidHeader { .. } => "The program's header is invalid.",
+            VMError::IllegalOpcode { .. } => "Found a byte that is not a known opcode.",
+            VMError::TruncatedInstruction { .. } => {
+                "An instruction runs past the end of the program."
+            }
+            VMError::InvalidRegister { .. } => {
+                "An instruction uses a register that does not exist."
+            }
+            VMError::MisalignedJump { .. } => "A jump target is not the start of an instruction.",
+            VMError::SnapshotRegisterCount { .. } => {
+                "A snapshot has a different number of registers than the VM."
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            VMError::IllegalOpcode {
+                offset: 68,
+                byte: 200
+            }
+            .to_string(),
+            "Illegal opcode 0xc8 at offset 68"
+        );
+        assert_eq!(
+            VMError::InvalidRegister {
+                offset: 72,
+                register: 40,
+                count: 32
+            }
+            .to_string(),
+            "The instruction at offset 72 uses register $40, but there are only 32"
+        );
+    }
+}
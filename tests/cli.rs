@@ -0,0 +1,276 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Creates an empty scratch directory for tests that need files on disk
+fn scratch_dir() -> PathBuf {
+    let dir = env::temp_dir().join(format!("iridium-cli-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn assemble_writes_bytecode() {
+    let dir = scratch_dir().join("assemble");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("program.iasm");
+    let output = dir.join("program.bin");
+    fs::write(&source, ".data\n.code\nload $0 #7\nhlt\n").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .arg("assemble")
+        .arg(&source)
+        .arg("-o")
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let bytecode = fs::read(&output).unwrap();
+    assert_eq!(&bytecode[0..4], &[0x45, 0x50, 0x49, 0x45]);
+    assert_eq!(bytecode.len(), 64 + 4 + 8);
+
+    let run = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .arg("run")
+        .arg(&output)
+        .arg("--json")
+        .output()
+        .unwrap();
+    assert!(run.status.success());
+    assert!(String::from_utf8_lossy(&run.stdout).contains("\"registers\""));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn malformed_input_exits_with_an_error() {
+    let dir = scratch_dir().join("malformed");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("program.iasm");
+    fs::write(&source, ".data\n.code\nload $0 #99999999999\nhlt\n").unwrap();
+
+    for args in [vec!["assemble", "-o", "out.bin"], vec![]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_iridium"))
+            .args(&args)
+            .arg(&source)
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(!output.stderr.is_empty());
+    }
+    assert!(!dir.join("out.bin").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn set_seeds_registers_before_running() {
+    let dir = scratch_dir().join("set");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("program.iasm");
+    fs::write(&source, ".code\nadd $0 $1 $2\nhlt\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .arg(&source)
+        .args(["--set", "$0=5", "--set", "$1=-12", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let json = &stdout[stdout.find('{').unwrap()..];
+    let snapshot: serde_json::Value = serde_json::from_str(json).unwrap();
+    assert_eq!(snapshot["registers"][0], 5);
+    assert_eq!(snapshot["registers"][1], -12);
+    assert_eq!(snapshot["registers"][2], -7);
+
+    for bad in ["$32=1", "0=1", "$0=99999999999", "$0"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_iridium"))
+            .arg(&source)
+            .args(["--set", bad])
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "--set {} should be rejected", bad);
+        assert!(!output.stderr.is_empty());
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn disassemble_prints_listing() {
+    let dir = scratch_dir().join("disassemble");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("program.iasm");
+    let output = dir.join("program.bin");
+    fs::write(
+        &source,
+        ".data\nhello: .asciiz 'Hi'\n.code\nload $0 #7\nprts @hello\nhlt\n",
+    )
+    .unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .arg("assemble")
+        .arg(&source)
+        .arg("-o")
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let listing = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .arg("--disassemble")
+        .arg(&output)
+        .output()
+        .unwrap();
+    assert!(listing.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&listing.stdout),
+        "LOAD $0 #7\nPRTS #0\nHLT\n"
+    );
+
+    // Source text isn't bytecode
+    let rejected = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .arg("--disassemble")
+        .arg(&source)
+        .output()
+        .unwrap();
+    assert!(!rejected.status.success());
+    assert!(!rejected.stderr.is_empty());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn dump_rodata_prints_hex() {
+    let dir = scratch_dir().join("dump-rodata");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("program.iasm");
+    fs::write(
+        &source,
+        ".data\nhello: .asciiz 'Hello'\nbye: .asciiz 'Bye'\n.code\nhlt\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .arg("--dump-rodata")
+        .arg(&source)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "00000000  48 65 6c 6c 6f 00 42 79 65 00                    |Hello.Bye.|\n"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn assemble_writes_symbol_map() {
+    let dir = scratch_dir().join("map");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("program.iasm");
+    let map = dir.join("program.map");
+    fs::write(&source, ".code\nstart: load $0 #7\nend: hlt\n").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .arg("assemble")
+        .arg(&source)
+        .args(["-o", "program.bin", "--map", "program.map"])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let map = fs::read_to_string(map).unwrap();
+    assert!(map.contains("\"start\""));
+    assert!(map.contains("\"end\""));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn disassemble_with_symbol_map() {
+    let dir = scratch_dir().join("disassemble-symbols");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("program.iasm"),
+        ".code\nload $1 #2\ntop: inc $0\njlt $0 $1 @top\nhlt\n",
+    )
+    .unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .args([
+            "assemble",
+            "program.iasm",
+            "-o",
+            "program.bin",
+            "--map",
+            "program.map",
+        ])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let listing = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .args(["--disassemble", "program.bin", "--symbols", "program.map"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(listing.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&listing.stdout),
+        "LOAD $1 #2\ntop: INC $0\nJLT $0 $1 @top\nHLT\n"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn decode_cache_gives_the_same_result() {
+    let dir = scratch_dir().join("decode-cache");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("program.iasm");
+    fs::write(
+        &source,
+        ".code\nload $1 #50\ntop: inc $0\nadd $0 $2 $2\njlt $0 $1 @top\nhlt\n",
+    )
+    .unwrap();
+
+    let outputs: Vec<String> = [vec![], vec!["--decode-cache"]]
+        .iter()
+        .map(|flags| {
+            let output = Command::new(env!("CARGO_BIN_EXE_iridium"))
+                .arg(&source)
+                .args(flags)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            String::from_utf8_lossy(&output.stdout).to_string()
+        })
+        .collect();
+    assert!(outputs[0].contains("1275"));
+    assert_eq!(outputs[0], outputs[1]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn read_and_syscall_share_stdin() {
+    let dir = scratch_dir().join("stdin");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("program.iasm");
+    fs::write(&source, ".code\nread $0\nsyscall #0 $1\nhlt\n").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .arg(&source)
+        .arg("--json")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"4\n5\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let json = &stdout[stdout.find('{').unwrap()..];
+    let snapshot: serde_json::Value = serde_json::from_str(json).unwrap();
+    assert_eq!(snapshot["registers"][0], 4);
+    assert_eq!(snapshot["registers"][1], 5);
+    fs::remove_dir_all(dir).unwrap();
+}